@@ -0,0 +1,47 @@
+// ZIP archives as sources and destinations.
+//
+// Tasks work on files in directories, so a zip source is extracted to a working directory first
+// and a zip dest is written from the directory the outputs were collected in.
+
+/// Whether a path names a zip archive.
+pub fn is_zip(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// Extract the entries accepted by `keep` into a directory, keeping their layout.
+///
+/// Entries whose name would end up outside the directory are skipped.
+pub fn extract(archive_path: &std::path::Path, directory: &std::path::Path, keep: impl Fn(&std::path::Path) -> bool) {
+    let file = std::fs::File::open(archive_path).unwrap();
+    let mut archive = zip::ZipArchive::new(file).unwrap_or_else(|error| panic!("Invalid zip archive {}: {}", archive_path.display(), error));
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).unwrap();
+        let name = match entry.enclosed_name() {
+            Some(name) if entry.is_file() && keep(name) => name.to_path_buf(),
+            _ => continue,
+        };
+        let path = directory.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::io::copy(&mut entry, &mut std::fs::File::create(&path).unwrap()).unwrap();
+    }
+}
+
+/// Write every file under a directory into a new zip archive, named relative to the directory.
+pub fn create(directory: &std::path::Path, archive_path: &std::path::Path) {
+    if let Some(parent) = archive_path.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    let mut archive = zip::ZipWriter::new(std::fs::File::create(archive_path).unwrap());
+    let options = zip::write::FileOptions::default();
+    for entry in walkdir::WalkDir::new(directory).sort_by_file_name() {
+        let entry = entry.unwrap();
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry.path().strip_prefix(directory).unwrap();
+        let name = name.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        archive.start_file(name, options).unwrap();
+        std::io::copy(&mut std::fs::File::open(entry.path()).unwrap(), &mut archive).unwrap();
+    }
+    archive.finish().unwrap();
+}
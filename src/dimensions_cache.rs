@@ -0,0 +1,75 @@
+// Image dimensions remembered across runs, for `--dimensions-cache`.
+//
+// Filters like `--orientation` and the already-sized check of resize only need the width and
+// height of an image, yet have to open it to read its header. The cache keeps them by path
+// together with the size and modification time of the file, and an entry only counts while both
+// are unchanged.
+
+/// The entries of a cache, and whether the run changed them.
+#[derive(Clone, Default)]
+pub struct DimensionsCache {
+    entries: serde_json::Map<String, serde_json::Value>,
+    changed: bool,
+}
+
+/// Size and modification time (in nanoseconds since the epoch) of a file, which an entry has to
+/// match.
+fn stamp(path: &std::path::Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos() as u64))
+}
+
+fn key(path: &std::path::Path) -> String {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string()
+}
+
+impl DimensionsCache {
+    /// Load a cache, or start an empty one when the file doesn't exist yet.
+    pub fn load(path: &std::path::Path) -> DimensionsCache {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(serde_json::Value::Object(cache)) => match cache.get("files") {
+                    Some(serde_json::Value::Object(entries)) => entries.clone(),
+                    _ => panic!("Invalid dimensions cache {}: missing files", path.display()),
+                },
+                _ => panic!("Invalid dimensions cache {}", path.display()),
+            },
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+            Err(error) => panic!("Can't read dimensions cache {}: {}", path.display(), error),
+        };
+        DimensionsCache { entries, changed: false }
+    }
+
+    /// The dimensions recorded for an image, unless the file changed since.
+    pub fn get(&self, path: &std::path::Path) -> Option<(u32, u32)> {
+        let entry = self.entries.get(&key(path))?;
+        let (size, modified) = stamp(path)?;
+        if entry["size"] != size || entry["modified"] != modified {
+            return None;
+        }
+        Some((entry["width"].as_u64()? as u32, entry["height"].as_u64()? as u32))
+    }
+
+    /// Record the dimensions and format of an image as its file is now.
+    pub fn record(&mut self, path: &std::path::Path, dimensions: (u32, u32), format: Option<image::ImageFormat>) {
+        let Some((size, modified)) = stamp(path) else {
+            return;
+        };
+        let format = format.and_then(|format| format.extensions_str().first().copied());
+        let entry = serde_json::json!({ "size": size, "modified": modified, "width": dimensions.0, "height": dimensions.1, "format": format });
+        self.entries.insert(key(path), entry);
+        self.changed = true;
+    }
+
+    /// Write the cache if the run changed it, dropping the entries of images that no longer exist.
+    pub fn save(&self, path: &std::path::Path) {
+        if !self.changed {
+            return;
+        }
+        let entries: serde_json::Map<String, serde_json::Value> =
+            self.entries.iter().filter(|(key, _)| std::path::Path::new(key).exists()).map(|(key, entry)| (key.clone(), entry.clone())).collect();
+        let cache = serde_json::json!({ "files": entries });
+        std::fs::write(path, serde_json::to_string_pretty(&cache).unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+// Faces in color images, with the face-detection feature.
+//
+// Faces are found as in "Face detection in color images" by Hsu, Abdel-Mottaleb and Jain (2002),
+// which needs no trained model: regions of skin tones are the candidates, and a candidate is a
+// face when it holds two eyes and a mouth where a face has them. Eyes are dark spots with bright
+// surroundings and more blue than red, mouths are redder than the skin around them. Faces seen
+// from the side or much smaller than 1% of the image aren't found.
+
+/// A bounding box (left, top, right, bottom) in pixels, with both ends included.
+type Bounds = (usize, usize, usize, usize);
+
+/// Luma and chroma of a pixel, as in JPEG's YCbCr.
+fn ycbcr(pixel: &image::Rgb<u8>) -> (f32, f32, f32) {
+    let [r, g, b] = pixel.0.map(|channel| channel as f32);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    (luma, cb, cr)
+}
+
+/// Values scaled to 0.0-1.0.
+fn normalized(mut values: Vec<f32>) -> Vec<f32> {
+    let (min, max) = values.iter().fold((f32::MAX, f32::MIN), |(min, max), value| (min.min(*value), max.max(*value)));
+    let range = (max - min).max(f32::EPSILON);
+    values.iter_mut().for_each(|value| *value = (*value - min) / range);
+    values
+}
+
+/// Gray-level dilation (the largest value around each pixel) or erosion (the smallest) with a
+/// square of `2 * radius + 1` pixels.
+fn morphology(values: &[f32], width: usize, height: usize, radius: usize, dilate: bool) -> Vec<f32> {
+    let pick = |a: f32, b: f32| if dilate { a.max(b) } else { a.min(b) };
+    // separable, rows first.
+    let mut rows = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (start, end) = (x.saturating_sub(radius), (x + radius).min(width - 1));
+            rows[y * width + x] = values[y * width + start..=y * width + end].iter().copied().reduce(pick).unwrap();
+        }
+    }
+    let mut result = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (start, end) = (y.saturating_sub(radius), (y + radius).min(height - 1));
+            result[y * width + x] = (start..=end).map(|y| rows[y * width + x]).reduce(pick).unwrap();
+        }
+    }
+    result
+}
+
+/// The strongest local maxima of a map within bounds, at most `count` of them, each the largest
+/// value within `radius` pixels.
+fn peaks(map: &[f32], width: usize, (left, top, right, bottom): Bounds, radius: usize, count: usize) -> Vec<(usize, usize, f32)> {
+    let mut candidates: Vec<(usize, usize, f32)> = (top..=bottom).flat_map(|y| (left..=right).map(move |x| (x, y))).map(|(x, y)| (x, y, map[y * width + x])).collect();
+    candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+    let mut peaks: Vec<(usize, usize, f32)> = Vec::new();
+    for candidate in candidates {
+        if peaks.len() == count {
+            break;
+        }
+        if peaks.iter().all(|peak| peak.0.abs_diff(candidate.0) > radius || peak.1.abs_diff(candidate.1) > radius) {
+            peaks.push(candidate);
+        }
+    }
+    peaks
+}
+
+/// Mean of a map within bounds.
+fn mean(map: &[f32], width: usize, (left, top, right, bottom): Bounds) -> f32 {
+    let sum: f32 = (top..=bottom).flat_map(|y| (left..=right).map(move |x| map[y * width + x])).sum();
+    sum / ((right - left + 1) * (bottom - top + 1)) as f32
+}
+
+/// Center of the face with the clearest eyes and mouth among regions of skin tones, as fractions
+/// of the width and height of the image, if any of them is one. `skin` tells the skin pixels of
+/// the image, row by row.
+pub fn face_center(image: &image::RgbImage, skin: &[bool], regions: &[Bounds]) -> Option<(f64, f64)> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels: Vec<(f32, f32, f32)> = image.pixels().map(ycbcr).collect();
+
+    // eyes have high Cb, low Cr, and are darker than what surrounds them.
+    let blue = normalized(pixels.iter().map(|(_, cb, _)| cb * cb).collect());
+    let not_red = normalized(pixels.iter().map(|(_, _, cr)| (255.0 - cr) * (255.0 - cr)).collect());
+    let ratio = normalized(pixels.iter().map(|(_, cb, cr)| cb / cr.max(1.0)).collect());
+    let chroma: Vec<f32> = (0..pixels.len()).map(|index| (blue[index] + not_red[index] + ratio[index]) / 3.0).collect();
+    let luma: Vec<f32> = pixels.iter().map(|(luma, _, _)| *luma).collect();
+    let radius = (width.max(height) / 100).max(2);
+    let (dilated, eroded) = (morphology(&luma, width, height, radius, true), morphology(&luma, width, height, radius, false));
+    let contrast = normalized((0..pixels.len()).map(|index| dilated[index] / (eroded[index] + 1.0)).collect());
+    let eye_map = normalized((0..pixels.len()).map(|index| chroma[index] * contrast[index]).collect());
+    let red = normalized(pixels.iter().map(|(_, _, cr)| cr * cr).collect());
+    let red_ratio = normalized(pixels.iter().map(|(_, cb, cr)| cr / cb.max(1.0)).collect());
+
+    let mut best: Option<(f32, (f64, f64))> = None;
+    for &(left, top, right, bottom) in regions {
+        let region_width = right - left + 1;
+        if region_width < 12 || bottom - top + 1 < 12 {
+            continue;
+        }
+        // a head may have neck and shoulders below it, the face is at its top.
+        let face_bottom = (top + region_width * 3 / 2).min(bottom);
+        let face = (left, top, right, face_bottom);
+
+        // mouths have high Cr and low Cb, compared to the skin of the face (Cr² - η Cr/Cb).
+        let skin_indices: Vec<usize> = (top..=face_bottom).flat_map(|y| (left..=right).map(move |x| y * width + x)).filter(|index| skin[*index]).collect();
+        if skin_indices.is_empty() {
+            continue;
+        }
+        let skin_mean = |map: &[f32]| skin_indices.iter().map(|index| map[*index]).sum::<f32>() / skin_indices.len() as f32;
+        let eta = 0.95 * skin_mean(&red) / skin_mean(&red_ratio).max(f32::EPSILON);
+        let mouth_map = normalized((0..pixels.len()).map(|index| red[index] * (red[index] - eta * red_ratio[index]).powi(2)).collect());
+
+        // eyes are holes in the skin, inside the face rather than at its edges.
+        let face_height = face_bottom - top + 1;
+        let eye_band = (left, top + face_height / 8, right, top + face_height * 3 / 5);
+        let is_inside = |x: usize, y: usize| !skin[y * width + x] && (left..x).any(|x| skin[y * width + x]) && (x + 1..=right).any(|x| skin[y * width + x]);
+        let eye_threshold = 2.0 * mean(&eye_map, width, face);
+        let eyes: Vec<(usize, usize, f32)> =
+            peaks(&eye_map, width, eye_band, region_width / 10, 6).into_iter().filter(|(x, y, value)| *value > eye_threshold && is_inside(*x, *y)).collect();
+        let mouth_threshold = 2.0 * mean(&mouth_map, width, face);
+
+        for (index, first) in eyes.iter().enumerate() {
+            for second in &eyes[index + 1..] {
+                let eye_distance = first.0.abs_diff(second.0) as f32;
+                // eyes are side by side, a fifth to two thirds of the face apart.
+                if eye_distance < region_width as f32 * 0.2 || eye_distance > region_width as f32 * 0.7 || first.1.abs_diff(second.1) as f32 > eye_distance * 0.25 {
+                    continue;
+                }
+                let (eyes_x, eyes_y) = ((first.0 + second.0) as f32 / 2.0, (first.1 + second.1) as f32 / 2.0);
+                // the mouth is centered below the eyes, about as far below them as they are apart.
+                let mouth_band = (
+                    (eyes_x - eye_distance * 0.25).max(left as f32) as usize,
+                    ((eyes_y + eye_distance * 0.6) as usize).min(bottom),
+                    ((eyes_x + eye_distance * 0.25) as usize).min(right),
+                    ((eyes_y + eye_distance * 1.5) as usize).min(bottom),
+                );
+                let Some(&(mouth_x, mouth_y, mouth)) = peaks(&mouth_map, width, mouth_band, 0, 1).first() else {
+                    continue;
+                };
+                if mouth <= mouth_threshold || mouth_y as f32 <= eyes_y + eye_distance * 0.6 {
+                    continue;
+                }
+                let score = first.2 + second.2 + mouth;
+                if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+                    let center = ((eyes_x + mouth_x as f32) / 2.0, (eyes_y + mouth_y as f32) / 2.0);
+                    best = Some((score, ((center.0 as f64 + 0.5) / width as f64, (center.1 as f64 + 0.5) / height as f64)));
+                }
+            }
+        }
+    }
+    best.map(|(_, center)| center)
+}
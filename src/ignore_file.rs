@@ -0,0 +1,193 @@
+// Exclusions from `.rsimgignore` files, with gitignore semantics.
+//
+// Every directory of a source may have a `.rsimgignore` whose patterns apply to the files and
+// directories below it, the deeper files taking precedence, like `.gitignore` files in a work
+// tree. Ignored directories aren't walked, so nothing inside them can be included again.
+
+/// Name of the ignore files looked for in every directory of a source.
+pub const IGNORE_FILE: &str = ".rsimgignore";
+
+/// A pattern of an ignore file.
+#[derive(Clone)]
+struct Rule {
+    pattern: glob::Pattern,
+    /// `!pattern`, includes what an earlier pattern ignored.
+    negated: bool,
+    /// `pattern/`, only matches directories.
+    directory_only: bool,
+    /// A pattern with a slash other than at its end matches paths relative to the directory of
+    /// its file, others match names at any depth.
+    anchored: bool,
+}
+
+/// The rules of ignore files, read once per directory.
+#[derive(Clone, Default)]
+pub struct IgnoreRules {
+    by_directory: std::cell::RefCell<std::collections::HashMap<std::path::PathBuf, std::rc::Rc<Vec<Rule>>>>,
+    /// Rules of an `--ignore-file`, applying from the source down before any `.rsimgignore`.
+    global: Vec<Rule>,
+}
+
+/// Parse the lines of an ignore file: blank lines and `#` comments are skipped, `\#` and `\!`
+/// start patterns with those characters.
+fn parse_rules(path: &std::path::Path, text: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in text.lines().map(|line| line.trim_end_matches('\r')) {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        let pattern = glob::Pattern::new(line).unwrap_or_else(|error| panic!("Invalid pattern in {}: {} ({})", path.display(), line, error));
+        rules.push(Rule { pattern, negated, directory_only, anchored });
+    }
+    rules
+}
+
+/// Whether the last rule that matches a path ignores it, `None` when no rule matches.
+fn matches(rules: &[Rule], relative_path: &std::path::Path, is_dir: bool) -> Option<bool> {
+    let options = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+    let name = relative_path.file_name()?.to_string_lossy();
+    rules
+        .iter()
+        .rev()
+        .filter(|rule| is_dir || !rule.directory_only)
+        .find(|rule| match rule.anchored {
+            true => rule.pattern.matches_path_with(relative_path, options),
+            false => rule.pattern.matches_with(&name, options),
+        })
+        .map(|rule| !rule.negated)
+}
+
+impl IgnoreRules {
+    /// Rules with those of an `--ignore-file` applying from the source down.
+    pub fn new(ignore_file: Option<&std::path::Path>) -> IgnoreRules {
+        let global = match ignore_file {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).unwrap_or_else(|error| panic!("Can't read ignore file {}: {}", path.display(), error));
+                parse_rules(path, &text)
+            }
+            None => Vec::new(),
+        };
+        IgnoreRules { by_directory: Default::default(), global }
+    }
+
+    /// The rules of the ignore file of a directory, none when it has no such file.
+    fn rules(&self, directory: &std::path::Path) -> std::rc::Rc<Vec<Rule>> {
+        let mut by_directory = self.by_directory.borrow_mut();
+        let rules = by_directory.entry(directory.to_path_buf()).or_insert_with(|| {
+            let path = directory.join(IGNORE_FILE);
+            let rules = match std::fs::read_to_string(&path) {
+                Ok(text) => parse_rules(&path, &text),
+                Err(_) => Vec::new(),
+            };
+            std::rc::Rc::new(rules)
+        });
+        rules.clone()
+    }
+
+    /// Whether a file or directory under the source is ignored by an `--ignore-file` or by the
+    /// `.rsimgignore` files of the directories between the source and it.
+    pub fn is_ignored(&self, source_path: &std::path::Path, path: &std::path::Path, is_dir: bool) -> bool {
+        let Ok(relative_path) = path.strip_prefix(source_path) else {
+            return false;
+        };
+        let mut ignored = matches(&self.global, relative_path, is_dir).unwrap_or(false);
+        // ignore files from the source down, the deepest one that matches decides.
+        let directories: Vec<&std::path::Path> = path.ancestors().skip(1).take_while(|directory| directory.starts_with(source_path)).collect();
+        for directory in directories.into_iter().rev() {
+            let relative_path = path.strip_prefix(directory).unwrap();
+            if let Some(matched) = matches(&self.rules(directory), relative_path, is_dir) {
+                ignored = matched;
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the rules of an ignore file ignore a path relative to its directory.
+    fn ignores(text: &str, path: &str, is_dir: bool) -> bool {
+        matches(&parse_rules(std::path::Path::new(IGNORE_FILE), text), std::path::Path::new(path), is_dir).unwrap_or(false)
+    }
+
+    #[test]
+    fn later_negation_includes_again() {
+        let text = "*.png\n!keep.png\n";
+        assert!(ignores(text, "a.png", false));
+        assert!(ignores(text, "photos/a.png", false));
+        assert!(!ignores(text, "keep.png", false));
+        assert!(!ignores(text, "photos/keep.png", false));
+        // the last matching rule decides.
+        assert!(ignores("!keep.png\n*.png\n", "keep.png", false));
+    }
+
+    #[test]
+    fn double_star_matches_any_depth() {
+        assert!(ignores("**/raw/*.png", "raw/a.png", false));
+        assert!(ignores("**/raw/*.png", "2023/06/raw/a.png", false));
+        assert!(!ignores("**/raw/*.png", "raw/sub/a.png", false));
+        assert!(ignores("exports/**", "exports/a/b.png", false));
+        assert!(ignores("a/**/b.png", "a/b.png", false));
+        assert!(ignores("a/**/b.png", "a/x/y/b.png", false));
+        assert!(!ignores("a/**/b.png", "z/a/b.png", false));
+    }
+
+    #[test]
+    fn slash_anchors_to_ignore_file_directory() {
+        // a leading slash or one in the middle anchors the pattern.
+        assert!(ignores("/build", "build", true));
+        assert!(!ignores("/build", "src/build", true));
+        assert!(ignores("doc/*.png", "doc/a.png", false));
+        assert!(!ignores("doc/*.png", "src/doc/a.png", false));
+        // a star doesn't match a slash.
+        assert!(!ignores("doc/*.png", "doc/sub/a.png", false));
+        // without one, names match at any depth.
+        assert!(ignores("*.tmp", "a/b/c.tmp", false));
+        assert!(ignores("build", "src/build", true));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_directories() {
+        assert!(ignores("cache/", "cache", true));
+        assert!(ignores("cache/", "a/cache", true));
+        assert!(!ignores("cache/", "cache", false));
+        assert!(ignores("cache", "cache", false));
+    }
+
+    #[test]
+    fn comments_blank_lines_and_escapes() {
+        let text = "# comment\n\n\\#hash.png\n\\!bang.png\nspace.png   \n";
+        assert!(!ignores(text, "# comment", false));
+        assert!(ignores(text, "#hash.png", false));
+        assert!(ignores(text, "!bang.png", false));
+        assert!(ignores(text, "space.png", false));
+    }
+
+    #[test]
+    fn deeper_ignore_files_take_precedence() {
+        let source_path = std::env::temp_dir().join(format!("rsimg-ignore-test-{}", std::process::id()));
+        let sub_path = source_path.join("sub");
+        std::fs::create_dir_all(&sub_path).unwrap();
+        std::fs::write(source_path.join(IGNORE_FILE), "*.png\n").unwrap();
+        std::fs::write(sub_path.join(IGNORE_FILE), "!*.png\n").unwrap();
+
+        let rules = IgnoreRules::new(None);
+        assert!(rules.is_ignored(&source_path, &source_path.join("a.png"), false));
+        assert!(!rules.is_ignored(&source_path, &sub_path.join("a.png"), false));
+        assert!(!rules.is_ignored(&source_path, &source_path.join("a.jpg"), false));
+        std::fs::remove_dir_all(&source_path).unwrap();
+    }
+}
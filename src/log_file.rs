@@ -0,0 +1,48 @@
+// A copy of the warnings and errors of a run, with `--log-file`.
+//
+// Everything that goes to stderr through `log!` is appended to the log as well, with the local
+// time in front, so unattended runs can be looked into afterwards.
+
+/// The open log, set once at the start of the run.
+static LOG: std::sync::OnceLock<std::sync::Mutex<std::fs::File>> = std::sync::OnceLock::new();
+
+/// Print a line to stderr like `eprintln!`, and append it to the `--log-file`.
+macro_rules! log {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{}", line);
+        crate::log_file::write(&line);
+    }};
+}
+
+/// Open the log for appending. A log that has grown to `max_size` or more is moved to
+/// `{path}.1` first, replacing the one before it.
+pub fn open(path: &std::path::Path, max_size: Option<u64>) {
+    let size = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    if max_size.is_some_and(|max_size| size >= max_size) {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        std::fs::rename(path, &rotated).unwrap_or_else(|error| panic!("Can't rotate log file {}: {}", path.display(), error));
+    }
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|error| panic!("Can't open log file {}: {}", path.display(), error));
+    LOG.set(std::sync::Mutex::new(file)).unwrap();
+
+    // errors end the run with a panic, which the log should tell about as well.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info.payload_as_str().unwrap_or("unknown error");
+        write(&format!("Error: {}", message));
+        default_hook(info);
+    }));
+}
+
+/// Append a line to the log, if there is one.
+pub fn write(line: &str) {
+    use std::io::Write;
+    if let Some(log) = LOG.get() {
+        let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        let mut log = log.lock().unwrap_or_else(|error| error.into_inner());
+        // a log that can't be written to mustn't stop the run.
+        let _ = writeln!(log, "{} {}", time, line);
+    }
+}
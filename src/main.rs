@@ -4,194 +4,3547 @@ use clap::Parser;
 use image::GenericImageView;
 use walkdir::WalkDir;
 
+mod archive;
+mod dimensions_cache;
+#[cfg(feature = "face-detection")]
+mod face;
+mod ignore_file;
+#[macro_use]
+mod log_file;
+mod manifest;
+mod metadata;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod quality;
+mod tasks;
+
 #[derive(Parser, Default)]
-struct CLI {
+struct Cli {
+    /// Directory or zip archive to read images from.
     #[clap(short = 's', long = "source", default_value = ".")]
     source_path: std::path::PathBuf,
-    #[clap(short = 't', long = "task", default_value = "resize")]
+    /// Task to run on every image.
+    #[clap(short = 't', long = "task", default_value = "resize", value_parser = tasks::names())]
     task: String,
-    #[clap(short = 'o', long = "options", default_value = "size=128x128")]
-    options: String,
+    /// Task options as key=value pairs separated by commas, quote values that contain commas (resize defaults to size=128x128).
+    #[clap(short = 'o', long = "options")]
+    options: Option<String>,
+    /// Read the task options from a file instead, with entries separated by commas or newlines.
+    #[clap(long = "options-file", value_name = "PATH", conflicts_with = "options")]
+    options_file: Option<std::path::PathBuf>,
+    /// Directory or zip archive to write processed images to. Images are overwritten in place when omitted.
+    #[clap(short = 'd', long = "dest")]
+    dest_path: Option<std::path::PathBuf>,
+    /// Refuse to overwrite or remove any source image, e.g. when --dest was forgotten or a --name-template gives the original name, unless --in-place is given as well.
+    #[clap(long = "safe")]
+    safe: bool,
+    /// Allow --safe runs to replace images in place.
+    #[clap(long = "in-place", requires = "safe", conflicts_with = "dest_path")]
+    in_place: bool,
+    /// Mirror the source subfolder layout under the dest directory (default).
+    #[clap(long = "preserve-structure", conflicts_with = "flatten")]
+    preserve_structure: bool,
+    /// Write every image directly into the dest root, disambiguating colliding names.
+    #[clap(long = "flatten", requires = "dest_path")]
+    flatten: bool,
+    /// Subdirectory of the dest to write into (e.g. thumbs), so that several tasks over the same source keep their outputs apart.
+    #[clap(long = "output-subdir", value_name = "DIR", requires = "dest_path")]
+    output_subdir: Option<std::path::PathBuf>,
+    /// Minimum size reduction (e.g. 5% or 0.05) for the optimize task to replace an image.
+    #[clap(long = "min-savings", default_value = "0%")]
+    min_savings: String,
+    /// Shrink images so neither side exceeds this many pixels, instead of using the size option.
+    #[clap(long = "max-dimension")]
+    max_dimension: Option<u32>,
+    /// Re-encode images even when they already have the requested size.
+    #[clap(long = "force")]
+    force: bool,
+    /// How to write multi-page TIFFs: as one multi-page file or as one `name_p{n}` file per page.
+    #[clap(long = "tiff-pages", value_enum, default_value = "combine")]
+    tiff_pages: TiffPages,
+    /// Resolution to render the pages of PDFs at, e.g. 150 (pdf feature). By default every page takes that of its largest image, so scans keep all their pixels.
+    #[clap(long = "pdf-dpi", value_name = "DPI")]
+    pdf_dpi: Option<f64>,
+    /// Keep the EXIF metadata of JPEGs but remove their GPS location (and their orientation after rotate, deskew and mirror-tile).
+    #[clap(long = "strip-gps")]
+    strip_gps: bool,
+    /// Move files that would be overwritten to the system trash. Without it, they are replaced permanently.
+    #[clap(long = "trash")]
+    trash: bool,
+    /// Skip directories matching this name or glob without descending into them. Can be repeated.
+    #[clap(long = "exclude-dir", value_name = "NAME_OR_GLOB")]
+    exclude_dirs: Vec<String>,
+    /// Skip files and directories matching the gitignore-style patterns in this file, relative to the source. `.rsimgignore` files in the source and its directories are always honoured, deeper ones taking precedence.
+    #[clap(long = "ignore-file", value_name = "PATH")]
+    ignore_file: Option<std::path::PathBuf>,
+    /// Skip this directory, e.g. the dest of another run, when it is inside the source. The dest is always skipped. Can be repeated.
+    #[clap(long = "exclude-output-dir", value_name = "DIR")]
+    exclude_output_dirs: Vec<std::path::PathBuf>,
+    /// Output format per source format, e.g. png=webp,bmp=png. Unmapped formats are kept.
+    #[clap(long = "map", value_name = "FROM=TO,...")]
+    format_map: Option<String>,
+    /// Fail instead of warning when an option isn't recognized by the task.
+    #[clap(long = "strict")]
+    strict: bool,
+    /// Print the options accepted by the task, check the given ones and exit without processing.
+    #[clap(long = "explain", alias = "help-task")]
+    explain: bool,
+    /// Keep running after the first pass and process images as they are added or changed.
+    #[clap(long = "watch")]
+    watch: bool,
+    /// Process the files listed in this file, one path per line, instead of walking the source. Use - to read the list from stdin, e.g. from find. Listed files still have to be inside the source.
+    #[clap(long = "files-from", alias = "stdin-list", value_name = "PATH", conflicts_with = "watch")]
+    files_from: Option<std::path::PathBuf>,
+    /// Only process images with this orientation, skipping the others.
+    #[clap(long = "orientation", value_enum)]
+    orientation: Option<Orientation>,
+    /// Print a notice for every image that is skipped.
+    #[clap(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// Decode truncated JPEGs and PNGs as far as their data goes instead of skipping them.
+    #[clap(long = "tolerant", alias = "tolerant-decode")]
+    tolerant: bool,
+    /// Print decode, process and encode times per image and a summary at the end.
+    #[clap(long = "timings")]
+    timings: bool,
+    /// Pick images by their content instead of their extension, fixing the extension of outputs.
+    #[clap(long = "by-content", alias = "mime-detect")]
+    by_content: bool,
+    /// Convert images with an embedded ICC profile to this color space before processing them.
+    #[clap(long = "color-profile", value_enum)]
+    color_profile: Option<ColorProfile>,
+    /// Write a JSON report with the outcome of every image, and what tasks found out about it.
+    #[clap(long = "report", value_name = "PATH")]
+    report_path: Option<std::path::PathBuf>,
+    /// Write a JSON map from every source image to the outputs written for it, with their dimensions and formats, e.g. to generate srcsets from.
+    #[clap(long = "manifest-out", alias = "output-map", value_name = "PATH")]
+    output_map_path: Option<std::path::PathBuf>,
+    /// Format of the summary printed at the end: human readable lines, a single JSON line, or a CSV header and row.
+    #[clap(long = "summary-format", value_enum, default_value = "human")]
+    summary_format: SummaryFormat,
+    /// Make outputs byte-identical across runs and machines: images are processed in sorted path order and {date} in a --name-template never falls back to modification times. Outputs don't embed timestamps otherwise. PNG, JPEG, GIF, BMP, TIFF and ICO outputs and zip dests are then fully deterministic; lossy WebP is only for the same libwebp build and CPU architecture.
+    #[clap(long = "deterministic", alias = "reproducible")]
+    deterministic: bool,
+    /// Print a JSON line to stderr for every finished image, with its path, outcome and the running totals, for frontends that show progress.
+    #[clap(long = "progress-json")]
+    progress_json: bool,
+    /// Directory that the image paths in the report are relative to, the source by default.
+    #[clap(long = "report-base", value_name = "DIR", requires = "report_path")]
+    report_base: Option<std::path::PathBuf>,
+    /// Name outputs with the canonical lowercase extension of their format, e.g. .jpg for .jpeg.
+    #[clap(long = "normalize-ext", alias = "normalize-extension")]
+    normalize_ext: bool,
+    /// Only process the first frame of animated PNGs and WebPs instead of every frame.
+    #[clap(long = "single-frame")]
+    single_frame: bool,
+    /// Encode JPEG and WebP outputs at the lowest quality that keeps this SSIM against the original, e.g. ssim=0.98.
+    #[clap(long = "output-quality-auto", value_name = "TARGET")]
+    quality_target: Option<String>,
+    /// Skip images whose content, task and output are unchanged since the run recorded in this JSON file, and update it.
+    #[clap(long = "manifest", alias = "checksum-skip", value_name = "PATH")]
+    manifest_path: Option<std::path::PathBuf>,
+    /// Remember the dimensions of images in this JSON file by path, size and modification time, so reruns over the same files filter them without reading their headers again.
+    #[clap(long = "dimensions-cache", value_name = "PATH")]
+    dimensions_cache_path: Option<std::path::PathBuf>,
+    /// Retry reading or writing a file this many times after an I/O error before giving up.
+    #[clap(long = "retry", value_name = "N", default_value_t = 0)]
+    retries: u32,
+    /// Delay before the first retry in milliseconds, doubled for every further one.
+    #[clap(long = "retry-delay", value_name = "MS", default_value_t = 200)]
+    retry_delay: u64,
+    /// Append the output path of every completed image to this file.
+    #[clap(long = "run-log", value_name = "PATH")]
+    run_log: Option<std::path::PathBuf>,
+    /// Append the warnings and errors of the run to this file as well, each with the time, followed by the summary.
+    #[clap(long = "log-file", value_name = "PATH")]
+    log_file: Option<std::path::PathBuf>,
+    /// Move a --log-file that has reached this size (e.g. 10MB) to {path}.1 at the start of a run, replacing the one before it.
+    #[clap(long = "log-max-size", value_name = "SIZE", requires = "log_file")]
+    log_max_size: Option<String>,
+    /// Skip images whose output is already in the --run-log of an interrupted run, instead of starting the log over.
+    #[clap(long = "resume", requires = "run_log")]
+    resume: bool,
+    /// Stop starting new images once the run has taken this long, e.g. 90s, 5m or 1h30m, and exit with code 75. Together with --run-log and --resume a backlog is processed in bounded chunks.
+    #[clap(long = "max-runtime", value_name = "DURATION", conflicts_with = "watch")]
+    max_runtime: Option<String>,
+    /// Only check that the header of every matching image can be read, exiting non-zero if any can't. Nothing is written but the --manifest-out map of the planned outputs.
+    #[clap(long = "preflight", alias = "abort-if-any-fail")]
+    preflight: bool,
+    /// Only count the images the filters select, without reading or processing them.
+    #[clap(long = "count", alias = "count-only", conflicts_with = "preflight")]
+    count: bool,
+    /// Only process every Nth of the matching images, e.g. into a preview dest to try options on. Walks are sorted by path for it, so the sample is the same every run.
+    #[clap(long = "sample", alias = "sample-every", value_name = "N", conflicts_with = "watch")]
+    sample: Option<usize>,
+    /// Decode every written output again, deleting the ones that can't be read back and reporting them as failed.
+    #[clap(long = "verify", alias = "verify-output")]
+    verify: bool,
+    /// Compare every written output with the file at the same path in this directory, e.g. a baseline committed for CI, and exit non-zero if any is missing or further off than --baseline-threshold.
+    #[clap(long = "baseline", alias = "compare-baseline", value_name = "DIR", requires = "dest_path")]
+    baseline: Option<std::path::PathBuf>,
+    /// Lowest similarity to the baseline an output may have, as ssim=0.0-1.0 or psnr={decibels}.
+    #[clap(long = "baseline-threshold", value_name = "METRIC=VALUE", default_value = "ssim=0.99", requires = "baseline")]
+    baseline_threshold: String,
+    /// Amount of memory (e.g. 4GB or 512MB) images should take once decoded, estimated from their header dimensions at 4 bytes per pixel. Images are processed one at a time, so there is no concurrency to limit: larger images are warned about and still processed.
+    #[clap(long = "mem-budget", value_name = "SIZE")]
+    mem_budget: Option<String>,
+    /// Hardlink originals that are kept unchanged into the dest instead of copying them, copying only across file systems.
+    #[clap(long = "link-unchanged", alias = "hardlink-unchanged")]
+    link_unchanged: bool,
+    /// Don't mark PNG outputs as sRGB. By default they get sRGB and gAMA chunks so that every viewer shows the same colors.
+    #[clap(long = "no-srgb-chunk")]
+    no_srgb_chunk: bool,
+    /// Give outputs the permission bits of their source instead of the default ones for new files.
+    #[clap(long = "preserve-mode", alias = "preserve-permissions")]
+    preserve_mode: bool,
+    /// Give outputs the owner and group of their source as well (Unix only, usually needs root).
+    #[clap(long = "preserve-owner")]
+    preserve_owner: bool,
+    /// File name for outputs, with {stem} and {ext} standing for those of the image, e.g. {stem}_small.webp, and {date:%Y%m%d_%H%M%S} for when it was taken (EXIF DateTimeOriginal, else its modification time). The extension picks the output format.
+    #[clap(long = "name-template", value_name = "TEMPLATE")]
+    name_template: Option<String>,
+    /// Only process images with these extensions, e.g. jpg,png. jpg also matches jpeg and tif matches tiff.
+    #[clap(long = "ext", alias = "include-ext", value_name = "EXT,...", value_delimiter = ',')]
+    include_exts: Vec<String>,
+    /// Skip images with these extensions, e.g. gif.
+    #[clap(long = "not-ext", alias = "exclude-ext", value_name = "EXT,...", value_delimiter = ',')]
+    exclude_exts: Vec<String>,
+    /// Copy files that aren't processed (sidecars, text, ...) into the dest unchanged, so it mirrors the whole source.
+    #[clap(long = "copy-others", alias = "copy-nonimage")]
+    copy_others: bool,
+    /// What to do when an output would replace an existing file other than its source: overwrite it, skip the image, rename the output to `name (1).ext`, `name (2).ext`, ... or stop with an error.
+    #[clap(long = "on-conflict", value_enum, default_value = "overwrite")]
+    on_conflict: OnConflict,
+}
+
+/// The images of a pass over a directory by output format, see `read_format_defaults`.
+#[derive(Clone)]
+enum FormatPass {
+    /// Images written in this format, with its defaults.
+    Only(image::ImageFormat),
+    /// Images written in none of these formats, which have no defaults.
+    Except(Vec<image::ImageFormat>),
+}
+
+impl FormatPass {
+    fn includes(&self, target_path: &std::path::Path) -> bool {
+        let format = image::ImageFormat::from_path(target_path).ok();
+        match self {
+            FormatPass::Only(only) => format == Some(*only),
+            FormatPass::Except(formats) => !format.is_some_and(|format| formats.contains(&format)),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, Default)]
+enum TiffPages {
+    #[default]
+    Combine,
+    Split,
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, Default, PartialEq)]
+enum SummaryFormat {
+    #[default]
+    Human,
+    Json,
+    Csv,
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, Default, PartialEq)]
+enum OnConflict {
+    #[default]
+    Overwrite,
+    Skip,
+    Rename,
+    Error,
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq)]
+enum Orientation {
+    Portrait,
+    Landscape,
+    Square,
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq)]
+enum ColorProfile {
+    Srgb,
+}
+
+/// Settings shared by every task.
+#[derive(Clone, Default)]
+struct RunArgs {
+    dest_path: Option<std::path::PathBuf>,
+    /// Whether sources must never be replaced, with `--safe` but without `--in-place`.
+    safe: bool,
+    flatten: bool,
+    tiff_pages: TiffPages,
+    /// Resolution PDF pages are rendered at, from `--pdf-dpi`.
+    #[cfg(feature = "pdf")]
+    pdf_dpi: Option<f64>,
+    strip_gps: bool,
+    /// Whether the task rotates or flips the pixels, see `Task::reorients`.
+    reorients: bool,
+    /// Whether the task is run once per page of a PDF, see `Task::splits_pages`.
+    splits_pages: bool,
+    trash: bool,
+    exclude_dirs: Vec<glob::Pattern>,
+    /// Patterns of the `--ignore-file` and the `.rsimgignore` files, see `is_ignored_entry`.
+    ignore_rules: ignore_file::IgnoreRules,
+    /// Canonical paths of the dest and `--exclude-output-dir` directories, which are never walked
+    /// so outputs aren't processed again.
+    output_dirs: Vec<std::path::PathBuf>,
+    format_map: Vec<(image::ImageFormat, image::ImageFormat)>,
+    watch: bool,
+    /// Files to process instead of walking the source, from `--files-from`.
+    files: Option<Vec<std::path::PathBuf>>,
+    /// Files written by this run with their modification time, so `--watch` can tell its own
+    /// outputs apart from new images.
+    written: std::cell::RefCell<std::collections::HashMap<std::path::PathBuf, std::time::SystemTime>>,
+    orientation: Option<Orientation>,
+    verbose: bool,
+    tolerant: bool,
+    timings: bool,
+    by_content: bool,
+    color_profile: Option<ColorProfile>,
+    report: bool,
+    /// Whether the outputs of every image are collected for `--manifest-out`.
+    output_map: bool,
+    /// Whether images are only checked with `--preflight`, their outputs planned but not written.
+    preflight: bool,
+    /// Canonical `--report-base`, report paths are relative to the source without one.
+    report_base: Option<std::path::PathBuf>,
+    normalize_ext: bool,
+    single_frame: bool,
+    /// SSIM that lossy outputs must keep, from `--output-quality-auto`.
+    quality_target: Option<f64>,
+    retries: u32,
+    retry_delay: std::time::Duration,
+    /// When `--max-runtime` is up.
+    deadline: Option<std::time::Instant>,
+    run_log: Option<std::path::PathBuf>,
+    link_unchanged: bool,
+    /// Whether PNG outputs get sRGB and gAMA chunks, unless `--no-srgb-chunk`.
+    srgb_chunk: bool,
+    preserve_mode: bool,
+    preserve_owner: bool,
+    verify: bool,
+    /// The directory outputs are compared with and how close they must be, with `--baseline`.
+    baseline: Option<(std::path::PathBuf, quality::Threshold)>,
+    mem_budget: Option<u64>,
+    name_template: Option<String>,
+    include_exts: Vec<String>,
+    exclude_exts: Vec<String>,
+    copy_others: bool,
+    on_conflict: OnConflict,
+    progress_json: bool,
+    /// Images are sorted and nothing depends on the machine or the time of the run, with
+    /// `--deterministic`.
+    deterministic: bool,
+    /// Only every Nth image is processed, with `--sample`.
+    sample: Option<usize>,
+    /// Images found in the directory being processed, for `--progress-json`.
+    queued: std::cell::Cell<usize>,
+    /// Outputs completed by an earlier run, with `--resume`.
+    done: std::collections::HashSet<std::path::PathBuf>,
+    /// Content hashes from earlier runs, with `--manifest`.
+    manifest: std::cell::RefCell<Option<manifest::Manifest>>,
+    /// Dimensions of images by file, from earlier runs, with `--dimensions-cache`.
+    dimensions_cache: std::cell::RefCell<Option<dimensions_cache::DimensionsCache>>,
+    /// Only process the images a `.rsimg` config in this directory applies to, see `config_scope`.
+    scope: std::cell::RefCell<Option<std::path::PathBuf>>,
+    /// Only process the images written in some formats, for the passes of `[format.x]` config
+    /// defaults.
+    format_pass: std::cell::RefCell<Option<FormatPass>>,
+    /// Set by the convert task when outputs take the extension of their new format: that of its
+    /// `format`, or `None` when only `--map` changes them. See `output_paths`.
+    converted_extension: std::cell::Cell<Option<Option<&'static str>>>,
+    /// Set by tasks that always write one format, such as favicon's ICOs, to the extension of
+    /// their outputs. See `output_paths`.
+    output_extension: std::cell::Cell<Option<&'static str>>,
+    /// Report fields added by the task for the image being processed.
+    report_fields: std::cell::RefCell<serde_json::Map<String, serde_json::Value>>,
+    /// Time spent on the image being processed.
+    timing: std::cell::Cell<Timing>,
+    /// Pages of the PDFs read for the image being processed, with their numbers.
+    #[cfg(feature = "pdf")]
+    pdf_pages: std::cell::RefCell<std::collections::HashMap<std::path::PathBuf, Vec<(usize, image::DynamicImage)>>>,
+    /// Number of the PDF page the task is run on, see `run_task`.
+    #[cfg(feature = "pdf")]
+    pdf_page: std::cell::Cell<Option<usize>>,
+    /// Outputs written for the image being processed, with `--manifest-out`.
+    outputs: std::cell::RefCell<Vec<std::path::PathBuf>>,
+    /// Bytes written by `write_output` for the image being processed.
+    written_bytes: std::cell::Cell<u64>,
+    /// Whether an output of the image being processed failed `--verify`.
+    unverified: std::cell::Cell<bool>,
+    /// Whether an output of the image being processed didn't match its `--baseline`.
+    regressed: std::cell::Cell<bool>,
+    /// Whether an output of the image being processed was skipped by `--on-conflict=skip`.
+    conflicted: std::cell::Cell<bool>,
+    /// Whether a file of the image being processed couldn't be read, see `RunArgs::read`.
+    unreadable: std::cell::Cell<bool>,
+    /// Whether a file replaced for the image being processed couldn't be trashed or removed.
+    unremoved: std::cell::Cell<bool>,
+    /// Names created by `--on-conflict=rename` for outputs about to be written, see
+    /// `conflict_target`.
+    reserved: std::cell::RefCell<std::collections::HashSet<std::path::PathBuf>>,
+}
+
+impl RunArgs {
+    /// Add a field to the report entry of the image being processed, with `--report`.
+    fn report_field(&self, key: &str, value: impl Into<serde_json::Value>) {
+        if self.report {
+            self.report_fields.borrow_mut().insert(key.to_string(), value.into());
+        }
+    }
+
+    /// Run a file operation, retrying it with `--retry` when it fails with an error that
+    /// `is_transient` accepts. The delay doubles after every attempt.
+    fn with_retry<T, E: std::fmt::Display>(&self, path: &std::path::Path, is_transient: impl Fn(&E) -> bool, mut operation: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+        let mut delay = self.retry_delay;
+        for attempt in 1..=self.retries {
+            match operation() {
+                Err(error) if is_transient(&error) => {
+                    if self.verbose {
+                        println!("Retrying {} in {} ({} of {}): {}", path.display(), format_duration(delay), attempt, self.retries, error);
+                    }
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+        operation()
+    }
+
+    /// Read a whole file, retrying with `--retry`. A file that still can't be read, e.g. for its
+    /// permissions or because it was removed meanwhile, fails the image being processed.
+    fn read(&self, path: &std::path::Path) -> Result<Vec<u8>, Outcome> {
+        self.with_retry(path, |_: &std::io::Error| true, || std::fs::read(path)).map_err(|error| {
+            log!("Error: can't read {}: {}", path.display(), error);
+            self.unreadable.set(true);
+            Outcome::Unreadable
+        })
+    }
+
+    /// Decode something, adding the time it takes to the current image's timing.
+    fn timed_decode<T>(&self, decode: impl FnOnce() -> T) -> T {
+        let start = std::time::Instant::now();
+        let decoded = decode();
+        let mut timing = self.timing.get();
+        timing.decode += start.elapsed();
+        self.timing.set(timing);
+        decoded
+    }
+
+    /// Encode something, adding the time it takes to the current image's timing.
+    fn timed_encode<T>(&self, encode: impl FnOnce() -> T) -> T {
+        let start = std::time::Instant::now();
+        let encoded = encode();
+        let mut timing = self.timing.get();
+        timing.encode += start.elapsed();
+        self.timing.set(timing);
+        encoded
+    }
+}
+
+/// Time spent on a single image, for `--timings`.
+#[derive(Copy, Clone, Default)]
+struct Timing {
+    decode: std::time::Duration,
+    encode: std::time::Duration,
+    total: std::time::Duration,
+    pixels: u64,
+}
+
+impl Timing {
+    /// Everything that wasn't decoding or encoding.
+    fn process(&self) -> std::time::Duration {
+        self.total.saturating_sub(self.decode).saturating_sub(self.encode)
+    }
+}
+
+/// What happened to a single image.
+#[derive(Copy, Clone, PartialEq)]
+enum Outcome {
+    /// The image was processed and written.
+    Processed,
+    /// Re-encoding did not save enough bytes, so the original was kept.
+    NoImprovement,
+    /// The image already had the requested size, so the original was kept.
+    AlreadySized,
+    /// The task wasn't confident enough to change the image, so the original was kept.
+    LowConfidence,
+    /// The image header is fine but its data ends early or is corrupt.
+    Truncated,
+    /// The file couldn't be read as an image at all.
+    Unreadable,
+    /// The image was only read by a task that doesn't write anything.
+    Inspected,
+    /// The image is the same as in the run recorded by `--manifest`, so it was skipped.
+    Unchanged,
+    /// The image was completed by the interrupted run that `--resume` continues.
+    Done,
+    /// The output was written but couldn't be decoded again by `--verify`.
+    Unverified,
+    /// The output already existed and `--on-conflict=skip` left it alone.
+    Existing,
+    /// The output is missing from the `--baseline` or differs from it beyond the threshold.
+    Regressed,
+    /// A file the image replaces couldn't be moved to the trash or removed.
+    Unremoved,
+}
+
+impl Outcome {
+    /// Name of the outcome in the report.
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Processed => "processed",
+            Outcome::NoImprovement => "no_improvement",
+            Outcome::AlreadySized => "already_sized",
+            Outcome::LowConfidence => "low_confidence",
+            Outcome::Truncated => "truncated",
+            Outcome::Unreadable => "unreadable",
+            Outcome::Inspected => "inspected",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Done => "done",
+            Outcome::Unverified => "unverified",
+            Outcome::Existing => "existing",
+            Outcome::Regressed => "regressed",
+            Outcome::Unremoved => "unremoved",
+        }
+    }
+}
+
+/// Counts of outcomes across a run.
+#[derive(Default)]
+struct Summary {
+    processed: usize,
+    no_improvement: usize,
+    already_sized: usize,
+    low_confidence: usize,
+    truncated: usize,
+    unreadable: usize,
+    inspected: usize,
+    /// Files copied with `--copy-others`, not counted as images.
+    copied: usize,
+    /// Images left for a later run when `--max-runtime` was up, not counted as files.
+    remaining: usize,
+    unchanged: usize,
+    done: usize,
+    unverified: usize,
+    existing: usize,
+    regressed: usize,
+    unremoved: usize,
+    source_bytes: u64,
+    output_bytes: u64,
+    timings: Vec<Timing>,
+    wall_time: std::time::Duration,
+    /// Report entries by path relative to the source or `--report-base`, with `--report`.
+    files: serde_json::Map<String, serde_json::Value>,
+    /// Outputs by source path like the report entries, with `--manifest-out`.
+    outputs: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Summary {
+    fn record(&mut self, outcome: Outcome, source_bytes: u64, output_bytes: u64) {
+        self.source_bytes += source_bytes;
+        self.output_bytes += output_bytes;
+        match outcome {
+            Outcome::Processed => self.processed += 1,
+            Outcome::NoImprovement => self.no_improvement += 1,
+            Outcome::AlreadySized => self.already_sized += 1,
+            Outcome::LowConfidence => self.low_confidence += 1,
+            Outcome::Truncated => self.truncated += 1,
+            Outcome::Unreadable => self.unreadable += 1,
+            Outcome::Inspected => self.inspected += 1,
+            Outcome::Unchanged => self.unchanged += 1,
+            Outcome::Done => self.done += 1,
+            Outcome::Unverified => self.unverified += 1,
+            Outcome::Existing => self.existing += 1,
+            Outcome::Regressed => self.regressed += 1,
+            Outcome::Unremoved => self.unremoved += 1,
+        }
+    }
+
+    /// Add the counts and entries of another run.
+    fn merge(&mut self, other: Summary) {
+        self.processed += other.processed;
+        self.no_improvement += other.no_improvement;
+        self.already_sized += other.already_sized;
+        self.low_confidence += other.low_confidence;
+        self.truncated += other.truncated;
+        self.unreadable += other.unreadable;
+        self.inspected += other.inspected;
+        self.copied += other.copied;
+        self.remaining += other.remaining;
+        self.unchanged += other.unchanged;
+        self.done += other.done;
+        self.unverified += other.unverified;
+        self.existing += other.existing;
+        self.regressed += other.regressed;
+        self.unremoved += other.unremoved;
+        self.source_bytes += other.source_bytes;
+        self.output_bytes += other.output_bytes;
+        self.timings.extend(other.timings);
+        self.wall_time += other.wall_time;
+        self.files.extend(other.files);
+        self.outputs.extend(other.outputs);
+    }
+
+    fn files(&self) -> usize {
+        self.processed + self.no_improvement + self.already_sized + self.low_confidence + self.truncated + self.unreadable + self.inspected + self.unchanged + self.done + self.unverified + self.existing + self.regressed + self.unremoved
+    }
+
+    fn print(&self) {
+        // read-only tasks print their own report, there are no outputs to sum up.
+        if self.inspected > 0 && self.inspected == self.files() {
+            println!("Inspected {} images.", self.inspected);
+            return;
+        }
+        let mut line = format!("Processed {} images", self.processed);
+        if self.no_improvement > 0 {
+            line.push_str(&format!(", kept {} originals (no improvement)", self.no_improvement));
+        }
+        if self.already_sized > 0 {
+            line.push_str(&format!(", skipped {} (already sized)", self.already_sized));
+        }
+        if self.low_confidence > 0 {
+            line.push_str(&format!(", left {} unchanged (low confidence)", self.low_confidence));
+        }
+        if self.unchanged > 0 {
+            line.push_str(&format!(", skipped {} (unchanged since last run)", self.unchanged));
+        }
+        if self.done > 0 {
+            line.push_str(&format!(", skipped {} (done before resuming)", self.done));
+        }
+        if self.existing > 0 {
+            line.push_str(&format!(", skipped {} (output exists)", self.existing));
+        }
+        if self.truncated > 0 {
+            line.push_str(&format!(", failed on {} (truncated)", self.truncated));
+        }
+        if self.unreadable > 0 {
+            line.push_str(&format!(", failed on {} (unreadable)", self.unreadable));
+        }
+        if self.unverified > 0 {
+            line.push_str(&format!(", failed on {} (output didn't verify)", self.unverified));
+        }
+        if self.regressed > 0 {
+            line.push_str(&format!(", failed on {} (differs from baseline)", self.regressed));
+        }
+        if self.unremoved > 0 {
+            line.push_str(&format!(", failed on {} (couldn't remove a replaced file)", self.unremoved));
+        }
+        println!("{}.", line);
+
+        let files = self.files();
+        if files > 0 && self.source_bytes > 0 {
+            let change = (self.output_bytes as f64 - self.source_bytes as f64) / self.source_bytes as f64 * 100.0;
+            let sign = if change < 0.0 { "\u{2212}" } else { "+" };
+            println!(
+                "Total: {} files, {} \u{2192} {} ({}{:.0}%).",
+                files,
+                format_bytes(self.source_bytes),
+                format_bytes(self.output_bytes),
+                sign,
+                change.abs()
+            );
+        }
+        if self.copied > 0 {
+            println!("Copied {} other files.", self.copied);
+        }
+        if self.remaining > 0 {
+            println!("Stopped at the --max-runtime, {} images remain.", self.remaining);
+        }
+        if !self.timings.is_empty() {
+            self.print_timings();
+        }
+    }
+
+    /// The counts of `--summary-format` json and csv, by column. Originals that were kept or
+    /// images that weren't needed count as skipped.
+    fn totals(&self) -> [(&'static str, u64); 8] {
+        let skipped = self.no_improvement + self.already_sized + self.low_confidence + self.unchanged + self.done + self.existing;
+        [
+            ("files", self.files() as u64),
+            ("processed", self.processed as u64),
+            ("skipped", skipped as u64),
+            ("failed", (self.truncated + self.unreadable + self.unverified + self.regressed + self.unremoved) as u64),
+            ("inspected", self.inspected as u64),
+            ("remaining", self.remaining as u64),
+            ("source_bytes", self.source_bytes),
+            ("output_bytes", self.output_bytes),
+        ]
+    }
+
+    /// Print the summary in a `--summary-format`.
+    fn print_as(&self, format: SummaryFormat) {
+        let totals = self.totals();
+        match format {
+            SummaryFormat::Human => self.print(),
+            SummaryFormat::Json => {
+                let line: serde_json::Map<String, serde_json::Value> = totals.iter().map(|(key, value)| (key.to_string(), (*value).into())).collect();
+                println!("{}", serde_json::Value::Object(line));
+            }
+            SummaryFormat::Csv => {
+                println!("{}", totals.map(|(key, _)| key).join(","));
+                println!("{}", totals.map(|(_, value)| value.to_string()).join(","));
+            }
+        }
+    }
+
+    /// The `--report` JSON: every image with its outcome and sizes, and the totals.
+    fn report(&self) -> serde_json::Value {
+        serde_json::json!({
+            "files": self.files,
+            "total": {
+                "files": self.files(),
+                "source_bytes": self.source_bytes,
+                "output_bytes": self.output_bytes,
+                "remaining": self.remaining,
+            },
+        })
+    }
+
+    fn print_timings(&self) {
+        let spread = |step: fn(&Timing) -> std::time::Duration| {
+            let mut durations: Vec<_> = self.timings.iter().map(step).collect();
+            durations.sort();
+            format!(
+                "{} / {} / {}",
+                format_duration(durations[0]),
+                format_duration(durations[durations.len() / 2]),
+                format_duration(durations[durations.len() - 1])
+            )
+        };
+        println!("Timings over {} images (min / median / max):", self.timings.len());
+        println!("  decode  {}", spread(|timing| timing.decode));
+        println!("  process {}", spread(|timing| timing.process()));
+        println!("  encode  {}", spread(|timing| timing.encode));
+        println!("  total   {}", spread(|timing| timing.total));
+        let megapixels = self.timings.iter().map(|timing| timing.pixels).sum::<u64>() as f64 / 1_000_000.0;
+        println!(
+            "Wall time {}, {:.1} MP/s.",
+            format_duration(self.wall_time),
+            megapixels / self.wall_time.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+}
+
+/// Format a duration for humans, in milliseconds below a second.
+fn format_duration(duration: std::time::Duration) -> String {
+    if duration.as_secs() == 0 {
+        format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+    } else {
+        format!("{:.2} s", duration.as_secs_f64())
+    }
+}
+
+/// Parse a duration like `90s`, `5m`, `1h30m` or `45` (seconds).
+fn parse_runtime(value: &str) -> std::time::Duration {
+    let invalid = || -> ! { panic!("Invalid duration (expected e.g. 90s, 5m or 1h30m): {}", value) };
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    for c in value.trim().chars() {
+        let scale = match c {
+            '0'..='9' | '.' => {
+                number.push(c);
+                continue;
+            }
+            's' => 1.0,
+            'm' => 60.0,
+            'h' => 3600.0,
+            _ => invalid(),
+        };
+        seconds += number.parse::<f64>().unwrap_or_else(|_| invalid()) * scale;
+        number.clear();
+    }
+    if !number.is_empty() {
+        seconds += number.parse::<f64>().unwrap_or_else(|_| invalid());
+    }
+    match seconds {
+        seconds if seconds > 0.0 && seconds.is_finite() => std::time::Duration::from_secs_f64(seconds),
+        _ => invalid(),
+    }
+}
+
+/// Exit code of a run stopped by `--max-runtime` (EX_TEMPFAIL), so scripts can tell it from a
+/// finished one and resume.
+const EXIT_OUT_OF_TIME: i32 = 75;
+
+/// Exit code of a run stopped because the disk is full (EX_IOERR).
+const EXIT_DISK_FULL: i32 = 74;
+
+/// Working directory of the run for zip archives: sources are extracted to it and zip dests
+/// collect their outputs in it.
+fn work_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("rsimg-{}", std::process::id()))
+}
+
+/// Parse a byte count like `4GB`, `512 MB` or `1048576`, with the units of `format_bytes`.
+fn parse_bytes(value: &str) -> u64 {
+    const UNITS: [(&str, u64); 5] = [("TB", 1 << 40), ("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)];
+    let upper = value.trim().to_ascii_uppercase();
+    let (number, scale) = UNITS
+        .iter()
+        .find_map(|(unit, scale)| upper.strip_suffix(unit).map(|number| (number.trim(), *scale)))
+        .unwrap_or((upper.as_str(), 1));
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => (number * scale as f64) as u64,
+        _ => panic!("Invalid size (expected e.g. 4GB or 512MB): {}", value),
+    }
+}
+
+/// Format a byte count for humans, e.g. 1.2 GB.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn is_image(path: &std::path::Path) -> bool {
+    if let Some(extension) = path.extension() {
+        if let Some(extension) = extension.to_str() {
+            return extension == "png" || extension == "jpg" || extension == "jpeg" || extension == "tif" || extension == "tiff" || extension == "bmp" || extension == "webp" || is_pdf(path);
+        }
+    }
+    false
+}
+
+/// Whether a file is a PDF whose pages are processed, with the pdf feature.
+fn is_pdf(path: &std::path::Path) -> bool {
+    cfg!(feature = "pdf") && has_extension(path, "pdf")
+}
+
+/// The format of an image judging by its first bytes, if it is one that can be processed.
+fn sniff_format(path: &std::path::Path) -> Option<image::ImageFormat> {
+    use std::io::Read;
+    let mut header = Vec::with_capacity(16);
+    std::fs::File::open(path).ok()?.take(16).read_to_end(&mut header).ok()?;
+    match image::guess_format(&header).ok()? {
+        format @ (image::ImageFormat::Png | image::ImageFormat::Jpeg | image::ImageFormat::Tiff | image::ImageFormat::Bmp | image::ImageFormat::WebP) => Some(format),
+        _ => None,
+    }
+}
+
+/// Whether a file should be processed, by extension or with `--by-content` by its first bytes,
+/// and whether its extension passes `--ext` and `--not-ext`.
+fn is_candidate(path: &std::path::Path, run: &RunArgs) -> bool {
+    let is_included = run.include_exts.is_empty() || run.include_exts.iter().any(|extension| has_extension(path, extension));
+    let is_excluded = run.exclude_exts.iter().any(|extension| has_extension(path, extension));
+    if !is_included || is_excluded {
+        return false;
+    }
+    match run.by_content {
+        true => sniff_format(path).is_some() || is_pdf(path),
+        false => is_image(path),
+    }
+}
+
+/// Whether a file has an extension, ignoring case and the leading dot, with the extensions of a
+/// format (jpg and jpeg, tif and tiff) matching each other.
+fn has_extension(path: &std::path::Path, extension: &str) -> bool {
+    let extension = extension.trim_start_matches('.');
+    let Some(path_extension) = path.extension().and_then(|extension| extension.to_str()) else {
+        return false;
+    };
+    if path_extension.eq_ignore_ascii_case(extension) {
+        return true;
+    }
+    match image::ImageFormat::from_extension(extension) {
+        Some(format) => image::ImageFormat::from_extension(path_extension) == Some(format),
+        None => false,
+    }
+}
+
+/// Target path with the extension of the image's actual format when `--by-content` finds the
+/// extension doesn't match it.
+fn content_target_path(source_path: &std::path::Path, target_path: std::path::PathBuf, run: &RunArgs) -> std::path::PathBuf {
+    if !run.by_content {
+        return target_path;
+    }
+    match sniff_format(source_path) {
+        Some(format) if image::ImageFormat::from_path(source_path).ok() != Some(format) => target_path.with_extension(format.extensions_str()[0]),
+        _ => target_path,
+    }
+}
+
+/// Whether a target is the image itself with its extension fixed in place by `--normalize-ext` or
+/// `--by-content`. Targets renamed by a `--name-template`, or given another extension by `--map`
+/// or a conversion, are new files next to the original.
+fn is_fixed_extension(path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) -> bool {
+    if run.dest_path.is_some() || target_path == path {
+        return false;
+    }
+    let normalized = match run.normalize_ext {
+        true => normalized_extension(path),
+        false => path.to_path_buf(),
+    };
+    content_target_path(path, normalized, run) == target_path
+}
+
+/// Compute where each image should be written.
+///
+/// Without a dest directory images are overwritten in place. With one, the source layout is
+/// mirrored under it unless flatten is requested, in which case every image lands in the dest
+/// root and colliding file names get the parent directory name (and, if needed, an index)
+/// appended to their stem. A `--name-template` then renames them, converted images get the
+/// extension of their new format, as do the outputs of tasks that write one format, and with
+/// `--normalize-ext` targets get the canonical extension of their format. Any of these may make targets collide, which then get an index as well.
+fn output_paths(source_path: &std::path::Path, images: &[std::path::PathBuf], run: &RunArgs) -> Vec<std::path::PathBuf> {
+    let mut targets = dest_paths(source_path, images, run);
+    // PDFs can't be written, their pages become PNGs.
+    targets = images.iter().zip(targets).map(|(image, target)| if is_pdf(image) { target.with_extension("png") } else { target }).collect();
+    if let Some(template) = &run.name_template {
+        targets = images.iter().zip(&targets).map(|(image, target)| templated_name(image, target, template, run.deterministic)).collect();
+        targets = unique_targets(images, targets);
+    }
+    // e.g. `a.png` and `a.tiff` both becoming `a.jpg` in place.
+    if let Some(extension) = run.converted_extension.get() {
+        targets = images
+            .iter()
+            .zip(targets)
+            .map(|(image, target)| {
+                let target = extension.map_or(target.clone(), |extension| target.with_extension(extension));
+                mapped_target_path(image, &target, run)
+            })
+            .collect();
+        targets = unique_targets(images, targets);
+    }
+    // e.g. `a.png` and `a.bmp` both becoming `a.ico`.
+    if let Some(extension) = run.output_extension.get() {
+        targets = targets.iter().map(|target| target.with_extension(extension)).collect();
+        targets = unique_targets(images, targets);
+    }
+    match run.normalize_ext {
+        true => normalize_extensions(images, targets),
+        false => targets,
+    }
+}
+
+/// Default format of the `{date}` name template token.
+const DATE_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// When an image was taken: its EXIF DateTimeOriginal as written, which has no time zone, or
+/// else its modification time in local time. Modification times differ between checkouts, so
+/// `--deterministic` runs refuse images without an EXIF date instead.
+fn image_date(path: &std::path::Path, deterministic: bool) -> chrono::NaiveDateTime {
+    let exif_date = metadata::capture_date(path).and_then(|date| {
+        let day = chrono::NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?;
+        day.and_hms_opt(date.hour as u32, date.minute as u32, date.second as u32)
+    });
+    exif_date.unwrap_or_else(|| {
+        if deterministic {
+            panic!("{} has no EXIF date for the name template, --deterministic doesn't fall back to its modification time", path.display());
+        }
+        let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).unwrap();
+        chrono::DateTime::<chrono::Local>::from(modified).naive_local()
+    })
+}
+
+/// Replace the `{date}` and `{date:FORMAT}` tokens of a name template with the date an image was
+/// taken, formatted with strftime specifiers (`%Y%m%d_%H%M%S` by default).
+fn with_dates(path: &std::path::Path, template: &str, deterministic: bool) -> String {
+    let mut name = String::new();
+    let mut rest = template;
+    let mut date = None;
+    while let Some(start) = rest.find("{date") {
+        let token = &rest[start..];
+        let Some(end) = token.find('}') else {
+            break;
+        };
+        let format = match &token[5..end] {
+            "" => DATE_FORMAT,
+            spec => match spec.strip_prefix(':') {
+                Some(format) => format,
+                None => {
+                    // some other token that starts with date, keep it as it is.
+                    name.push_str(&rest[..start + end + 1]);
+                    rest = &rest[start + end + 1..];
+                    continue;
+                }
+            },
+        };
+        let items: Vec<chrono::format::Item> = chrono::format::StrftimeItems::new(format).collect();
+        if items.contains(&chrono::format::Item::Error) {
+            panic!("Invalid date format in name template: {}", format);
+        }
+        let date = *date.get_or_insert_with(|| image_date(path, deterministic));
+        name.push_str(&rest[..start]);
+        name.push_str(&date.format_with_items(items.into_iter()).to_string());
+        rest = &token[end + 1..];
+    }
+    name.push_str(rest);
+    name
+}
+
+/// Target path renamed by a `--name-template`, whose extension has to name a format that can be
+/// written.
+fn templated_name(path: &std::path::Path, target_path: &std::path::Path, template: &str, deterministic: bool) -> std::path::PathBuf {
+    let stem = target_path.file_stem().unwrap().to_string_lossy();
+    let extension = target_path.extension().map(|extension| extension.to_string_lossy()).unwrap_or_default();
+    let name = with_dates(path, template, deterministic).replace("{stem}", &stem).replace("{ext}", &extension);
+    if name.is_empty() || name.contains(std::path::is_separator) {
+        panic!("Invalid name template: {}", template);
+    }
+    let target_path = target_path.with_file_name(&name);
+    match image::ImageFormat::from_path(&target_path) {
+        Ok(format) if format.can_write() => target_path,
+        _ => panic!("Name template {} gives {}, which isn't a format that can be written", template, name),
+    }
+}
+
+/// Target path with the canonical extension of its format, if it has a known one.
+fn normalized_extension(path: &std::path::Path) -> std::path::PathBuf {
+    match image::ImageFormat::from_path(path) {
+        Ok(format) => path.with_extension(format.extensions_str()[0]),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Give targets their canonical extension, appending an index to those that would then collide
+/// (e.g. `a.jpeg` and `a.jpg` in place).
+fn normalize_extensions(images: &[std::path::PathBuf], targets: Vec<std::path::PathBuf>) -> Vec<std::path::PathBuf> {
+    unique_targets(images, targets.iter().map(|target| normalized_extension(target)).collect())
+}
+
+/// Append an index to targets that collide with an earlier one (`a.jpg`, `a_2.jpg`, ...). Targets
+/// that are the image itself keep their name.
+fn unique_targets(images: &[std::path::PathBuf], targets: Vec<std::path::PathBuf>) -> Vec<std::path::PathBuf> {
+    // an image written in place under its own name must never be overwritten by another one.
+    let mut used: std::collections::HashSet<std::path::PathBuf> =
+        images.iter().zip(&targets).filter(|(image, target)| image == target).map(|(_, target)| target.clone()).collect();
+    images
+        .iter()
+        .zip(targets)
+        .map(|(image, target)| {
+            if *image == target {
+                return target;
+            }
+            let mut candidate = target.clone();
+            let mut index = 2;
+            while used.contains(&candidate) {
+                let stem = target.file_stem().unwrap().to_string_lossy();
+                let extension = target.extension().unwrap().to_string_lossy();
+                candidate = target.with_file_name(format!("{}_{}.{}", stem, index, extension));
+                index += 1;
+            }
+            used.insert(candidate.clone());
+            candidate
+        })
+        .collect()
+}
+
+/// Where each image is written, in place or under the dest.
+fn dest_paths(source_path: &std::path::Path, images: &[std::path::PathBuf], run: &RunArgs) -> Vec<std::path::PathBuf> {
+    let dest_path = match &run.dest_path {
+        Some(dest_path) => dest_path,
+        None => return images.to_vec(),
+    };
+
+    if !run.flatten {
+        return images
+            .iter()
+            .map(|path| dest_path.join(path.strip_prefix(source_path).unwrap_or(path)))
+            .collect();
+    }
+
+    // count file names so only colliding ones are renamed.
+    let mut name_counts = std::collections::HashMap::new();
+    for path in images {
+        *name_counts.entry(path.file_name().unwrap().to_os_string()).or_insert(0) += 1;
+    }
+
+    let mut used = std::collections::HashSet::new();
+    let mut targets = Vec::with_capacity(images.len());
+    for path in images {
+        let file_name = path.file_name().unwrap();
+        let mut target = dest_path.join(file_name);
+        if name_counts[file_name] > 1 || used.contains(&target) {
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+            let parent = path
+                .parent()
+                .and_then(|p| p.strip_prefix(source_path).ok())
+                .and_then(|p| p.file_name())
+                .map(|p| p.to_string_lossy().to_string());
+            let base = match parent {
+                Some(parent) => format!("{}_{}", stem, parent),
+                None => stem.to_string(),
+            };
+            target = dest_path.join(format!("{}{}", base, extension));
+            // still colliding (e.g. same parent name in different branches), append an index.
+            let mut index = 2;
+            while used.contains(&target) {
+                target = dest_path.join(format!("{}_{}{}", base, index, extension));
+                index += 1;
+            }
+        }
+        used.insert(target.clone());
+        targets.push(target);
+    }
+    targets
+}
+
+/// Whether an image has the `--orientation` to process, judging by its stored width and height.
+fn has_orientation(path: &std::path::Path, run: &RunArgs) -> bool {
+    let orientation = match run.orientation {
+        Some(orientation) => orientation,
+        None => return true,
+    };
+    // let the task report images that can't be read.
+    let (width, height) = match image_dimensions(path, run) {
+        Ok(dimensions) => dimensions,
+        Err(_) => return true,
+    };
+    let actual = match width.cmp(&height) {
+        std::cmp::Ordering::Less => Orientation::Portrait,
+        std::cmp::Ordering::Greater => Orientation::Landscape,
+        std::cmp::Ordering::Equal => Orientation::Square,
+    };
+    if actual != orientation && run.verbose {
+        println!("Skipping {} ({}x{} doesn't match the orientation).", path.display(), width, height);
+    }
+    actual == orientation
+}
+
+/// An output in the `--manifest-out` map: its path relative to the dest (or the source when
+/// writing in place) with its dimensions and format, read back from the written file. Outputs
+/// only planned by `--preflight` have no dimensions yet.
+fn output_entry(root: &std::path::Path, output_path: &std::path::Path) -> serde_json::Value {
+    let relative_path = output_path.strip_prefix(root).unwrap_or(output_path).to_string_lossy().replace('\\', "/");
+    let (width, height) = image::image_dimensions(output_path).map_or((None, None), |(width, height)| (Some(width), Some(height)));
+    let format = image::ImageFormat::from_path(output_path).ok().and_then(|format| format.extensions_str().first().copied());
+    serde_json::json!({ "path": relative_path, "width": width, "height": height, "format": format })
+}
+
+/// Run the executor on one image and record its outcome and sizes.
+///
+/// An image the executor didn't write anything for is counted at its original size.
+/// Run the task on an image, or on every page of a PDF for tasks that split pages.
+fn run_task(path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs, executor: &impl Fn(std::path::PathBuf, std::path::PathBuf) -> Outcome) -> Outcome {
+    if run.splits_pages && is_pdf(path) {
+        return run_on_pages(path, target_path, run, executor);
+    }
+    executor(path.to_path_buf(), target_path.to_path_buf())
+}
+
+/// Run the task on every page of a PDF, with a `_p{n}` target each. The PDF counts as processed
+/// when any of its pages was.
+#[cfg(feature = "pdf")]
+fn run_on_pages(path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs, executor: &impl Fn(std::path::PathBuf, std::path::PathBuf) -> Outcome) -> Outcome {
+    let numbers = match pdf_page_numbers(path, run) {
+        Ok(numbers) => numbers,
+        Err(outcome) => return outcome,
+    };
+    let mut outcomes = Vec::new();
+    for number in numbers {
+        run.pdf_page.set(Some(number));
+        outcomes.push(executor(path.to_path_buf(), page_path(target_path, number)));
+    }
+    run.pdf_page.set(None);
+    match outcomes.contains(&Outcome::Processed) {
+        true => Outcome::Processed,
+        false => outcomes[0],
+    }
+}
+
+#[cfg(not(feature = "pdf"))]
+fn run_on_pages(path: &std::path::Path, target_path: &std::path::Path, _run: &RunArgs, executor: &impl Fn(std::path::PathBuf, std::path::PathBuf) -> Outcome) -> Outcome {
+    executor(path.to_path_buf(), target_path.to_path_buf())
+}
+
+fn process_file(
+    source_path: &std::path::Path,
+    path: std::path::PathBuf,
+    target_path: std::path::PathBuf,
+    run: &RunArgs,
+    executor: &impl Fn(std::path::PathBuf, std::path::PathBuf) -> Outcome,
+    summary: &mut Summary,
+) {
+    let target_path = content_target_path(&path, target_path, run);
+    // make sure the target directory exists when writing to a dest.
+    if let (Some(parent), false) = (target_path.parent(), run.preflight) {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    let source_bytes = std::fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+    run.written_bytes.set(0);
+    run.unverified.set(false);
+    run.regressed.set(false);
+    run.conflicted.set(false);
+    run.unremoved.set(false);
+    run.unreadable.set(false);
+    run.outputs.borrow_mut().clear();
+    // the pixel count of the source, before it may be overwritten in place.
+    let pixels = match run.timings {
+        true => image_dimensions(&path, run).map_or(0, |(width, height)| width as u64 * height as u64),
+        false => 0,
+    };
+    run.timing.set(Timing { pixels, ..Default::default() });
+    let start = std::time::Instant::now();
+    let key = path.strip_prefix(source_path).unwrap_or(&path).to_string_lossy().to_string();
+    // the report path is taken before the image may be replaced in place.
+    let report_key = match run.report || run.output_map {
+        true => report_path(source_path, &path, run),
+        false => String::new(),
+    };
+    // where outputs are written, manifest and --manifest-out entries are relative to it.
+    let root = run.dest_path.as_deref().unwrap_or(source_path);
+    let is_unchanged = match run.manifest.borrow().as_ref() {
+        Some(manifest) => run.read(&path).is_ok_and(|bytes| manifest.is_unchanged(&key, &manifest::hash(&bytes), root, &target_path)),
+        None => false,
+    };
+    let outcome = if run.done.contains(&target_path) {
+        Outcome::Done
+    } else if is_unchanged {
+        Outcome::Unchanged
+    } else if run.unreadable.get() {
+        Outcome::Unreadable
+    } else {
+        run_task(&path, &target_path, run, executor)
+    };
+    #[cfg(feature = "pdf")]
+    run.pdf_pages.borrow_mut().clear();
+    // an output that got its extension fixed in place replaces the mislabeled original.
+    if run.written_bytes.get() > 0 && is_fixed_extension(&path, &target_path, run) && path.exists() && target_path.exists() {
+        remove_original(&path, run);
+    }
+    let outcome = match run.unverified.get() {
+        true => Outcome::Unverified,
+        false if run.unreadable.get() => Outcome::Unreadable,
+        false if run.regressed.get() => Outcome::Regressed,
+        false if run.unremoved.get() => Outcome::Unremoved,
+        // an image counts as skipped when none of its outputs could be written.
+        false if run.conflicted.get() && run.written_bytes.get() == 0 => Outcome::Existing,
+        false => outcome,
+    };
+    let output_bytes = match run.written_bytes.get() {
+        0 => source_bytes,
+        written_bytes => written_bytes,
+    };
+    summary.record(outcome, source_bytes, output_bytes);
+    if run.progress_json {
+        print_progress(&path, outcome, summary, run);
+    }
+
+    // record what the source looks like after the run, it may have been overwritten in place.
+    if let Some(manifest) = run.manifest.borrow_mut().as_mut() {
+        if !matches!(outcome, Outcome::Truncated | Outcome::Unreadable | Outcome::Unchanged | Outcome::Unverified | Outcome::Existing | Outcome::Unremoved) && path.exists() {
+            if let Ok(bytes) = run.read(&path) {
+                manifest.record(key.clone(), manifest::hash(&bytes), root, &target_path);
+            }
+        }
+    }
+
+    // outputs are written atomically, so a logged output is complete.
+    if let Some(run_log) = &run.run_log {
+        if !matches!(outcome, Outcome::Truncated | Outcome::Unreadable | Outcome::Done | Outcome::Unverified) {
+            use std::io::Write;
+            let mut log = std::fs::OpenOptions::new().create(true).append(true).open(run_log).unwrap();
+            writeln!(log, "{}", target_path.display()).unwrap();
+        }
+    }
+
+    if run.output_map {
+        let mut outputs = run.outputs.take();
+        // an original kept in place, or an output left by an earlier run, is the output as well.
+        if outputs.is_empty() && !matches!(outcome, Outcome::Truncated | Outcome::Unreadable | Outcome::Unverified) && target_path.is_file() {
+            outputs.push(target_path.clone());
+        }
+        let outputs: Vec<serde_json::Value> = outputs.iter().map(|output_path| output_entry(root, output_path)).collect();
+        summary.outputs.insert(report_key.clone(), outputs.into());
+    }
+
+    if run.report {
+        let mut entry = run.report_fields.take();
+        entry.insert("outcome".to_string(), outcome.name().into());
+        entry.insert("source_bytes".to_string(), source_bytes.into());
+        entry.insert("output_bytes".to_string(), output_bytes.into());
+        summary.files.insert(report_key, entry.into());
+    }
+
+    if run.timings {
+        let mut timing = run.timing.get();
+        timing.total = start.elapsed();
+        println!(
+            "Timing {}: decode {}, process {}, encode {}.",
+            path.display(),
+            format_duration(timing.decode),
+            format_duration(timing.process()),
+            format_duration(timing.encode)
+        );
+        summary.timings.push(timing);
+    }
+}
+
+/// Print a `--progress-json` event for a finished image to stderr: its path and outcome, how
+/// many images the directory has, and the totals of `--summary-format` json so far.
+fn print_progress(path: &std::path::Path, outcome: Outcome, summary: &Summary, run: &RunArgs) {
+    use std::io::Write;
+    let mut event = serde_json::Map::new();
+    event.insert("path".to_string(), path.to_string_lossy().into());
+    event.insert("outcome".to_string(), outcome.name().into());
+    event.insert("queued".to_string(), run.queued.get().into());
+    for (key, value) in summary.totals() {
+        event.insert(key.to_string(), value.into());
+    }
+    // one locked write and a flush per event, so a consumer never sees half a line.
+    let mut stderr = std::io::stderr().lock();
+    writeln!(stderr, "{}", serde_json::Value::Object(event)).unwrap();
+    stderr.flush().unwrap();
+}
+
+/// Whether a directory matches one of the `--exclude-dir` patterns, by name or by path relative
+/// to the source. Excluded directories are pruned from the walk entirely.
+fn is_excluded_dir(source_path: &std::path::Path, entry: &walkdir::DirEntry, run: &RunArgs) -> bool {
+    if entry.depth() == 0 || !entry.file_type().is_dir() {
+        return false;
+    }
+    is_excluded_path(source_path, entry.path(), run) || is_in_output_dir(entry.path(), run)
+}
+
+/// Whether a file or directory is ignored by the `--ignore-file` or a `.rsimgignore`. Ignored
+/// directories are pruned from the walk like excluded ones.
+fn is_ignored_entry(source_path: &std::path::Path, entry: &walkdir::DirEntry, run: &RunArgs) -> bool {
+    entry.depth() > 0 && run.ignore_rules.is_ignored(source_path, entry.path(), entry.file_type().is_dir())
+}
+
+/// Path of an image in the report, relative to `--report-base` (going up with `..` for images
+/// outside of it) or to the source, with `/` separators so reports of different machines compare.
+fn report_path(source_path: &std::path::Path, path: &std::path::Path, run: &RunArgs) -> String {
+    let base = run.report_base.clone().unwrap_or_else(|| canonical_path(source_path));
+    let path = canonical_path(path);
+    let common = base.components().zip(path.components()).take_while(|(a, b)| a == b).count();
+    let parents = std::iter::repeat_n("..".to_string(), base.components().count() - common);
+    let names = path.components().skip(common).map(|component| component.as_os_str().to_string_lossy().to_string());
+    parents.chain(names).collect::<Vec<_>>().join("/")
+}
+
+/// A path made absolute with symlinks resolved, also when it doesn't exist yet: the part that
+/// exists is canonicalized and the rest appended.
+fn canonical_path(path: &std::path::Path) -> std::path::PathBuf {
+    let absolute = std::path::absolute(path).unwrap();
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing.iter().rev().fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return absolute,
+        }
+    }
+}
+
+/// The canonical output directories to skip while walking a source. Those that are the source
+/// or contain it would skip everything, so they are left out.
+fn output_dirs<'a>(source_path: &std::path::Path, dirs: impl Iterator<Item = &'a std::path::PathBuf>) -> Vec<std::path::PathBuf> {
+    let source_path = canonical_path(source_path);
+    dirs.map(|dir| canonical_path(dir)).filter(|dir| !source_path.starts_with(dir)).collect()
+}
+
+/// Whether a path is inside the dest or an `--exclude-output-dir`.
+fn is_in_output_dir(path: &std::path::Path, run: &RunArgs) -> bool {
+    !run.output_dirs.is_empty() && path.canonicalize().is_ok_and(|path| run.output_dirs.iter().any(|dir| path.starts_with(dir)))
+}
+
+fn is_excluded_path(source_path: &std::path::Path, path: &std::path::Path, run: &RunArgs) -> bool {
+    let relative_path = path.strip_prefix(source_path).unwrap_or(path);
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    run.exclude_dirs
+        .iter()
+        .any(|pattern| pattern.matches(&name) || pattern.matches_path(relative_path))
+}
+
+/// Whether a file changed by a watch event should be processed: it is an image, isn't inside an
+/// excluded or output directory, isn't ignored, and isn't an output this run wrote itself.
+fn is_watched_image(source_path: &std::path::Path, path: &std::path::Path, run: &RunArgs) -> bool {
+    if !path.is_file() || !is_candidate(path, run) || is_in_output_dir(path, run) {
+        return false;
+    }
+    let in_excluded_dir = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(source_path) && *dir != source_path)
+        .any(|dir| is_excluded_path(source_path, dir, run) || run.ignore_rules.is_ignored(source_path, dir, true));
+    if in_excluded_dir || run.ignore_rules.is_ignored(source_path, path, false) {
+        return false;
+    }
+    let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    modified.is_none() || run.written.borrow().get(path) != modified.as_ref()
+}
+
+/// Watch the source tree and run the executor on every image that is created or modified.
+///
+/// Events are collected until the tree has been quiet for a moment, so a file that is still
+/// being copied is processed once, after it is complete. Never returns.
+fn watch_directory(source_path: &std::path::Path, run: &RunArgs, executor: impl Fn(std::path::PathBuf, std::path::PathBuf) -> Outcome) {
+    use notify::Watcher;
+    const QUIET_PERIOD: std::time::Duration = std::time::Duration::from_millis(500);
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).unwrap();
+    watcher.watch(source_path, notify::RecursiveMode::Recursive).unwrap();
+    println!("Watching {} for new images...", source_path.display());
+
+    // the watcher reports absolute paths, compare against the same form.
+    let source_path = source_path.canonicalize().unwrap();
+    loop {
+        // wait for the first event, then keep collecting until things settle down.
+        let mut changed = std::collections::BTreeSet::new();
+        let mut event = receiver.recv().unwrap();
+        loop {
+            if let Ok(event) = event {
+                if event.kind.is_create() || event.kind.is_modify() {
+                    changed.extend(event.paths);
+                }
+            }
+            match receiver.recv_timeout(QUIET_PERIOD) {
+                Ok(next) => event = next,
+                Err(_) => break,
+            }
+        }
+
+        let images: Vec<_> = changed
+            .into_iter()
+            .filter(|path| is_watched_image(&source_path, path, run) && has_orientation(path, run))
+            .collect();
+        if images.is_empty() {
+            continue;
+        }
+        let start = std::time::Instant::now();
+        let mut summary = Summary::default();
+        let targets = output_paths(&source_path, &images, run);
+        for (path, target_path) in images.into_iter().zip(targets) {
+            process_file(&source_path, path, target_path, run, &executor, &mut summary);
+        }
+        summary.wall_time = start.elapsed();
+        summary.print();
+    }
+}
+
+fn process_directory(source_path: std::path::PathBuf, run: &RunArgs, executor: impl Fn(std::path::PathBuf, std::path::PathBuf) -> Outcome) -> Summary {
+    let start = std::time::Instant::now();
+    // the source path is a directory. iterate all children and collect images.
+    let mut images = Vec::new();
+    let mut others = Vec::new();
+    // listed files that can't be processed are reported, there may be typos in the list.
+    let mut skipped = 0;
+    let mut paths: Vec<std::path::PathBuf> = match &run.files {
+        Some(files) => listed_files(&source_path, files, run, &mut skipped),
+        None => WalkDir::new(&source_path)
+            .into_iter()
+            .filter_entry(|entry| !is_excluded_dir(&source_path, entry, run) && !is_ignored_entry(&source_path, entry, run))
+            .map(|entry| entry.unwrap().into_path())
+            .filter(|path| path.is_file())
+            .collect(),
+    };
+    // walks follow the file system's order, which differs between machines, while a
+    // `--files-from` list keeps its own.
+    if run.files.is_none() && (run.deterministic || run.sample.is_some()) {
+        paths.sort();
+    }
+    for path in paths {
+        if !is_in_scope(&source_path, &path, run) {
+            continue;
+        }
+        if is_candidate(&path, run) && has_orientation(&path, run) {
+            images.push(path);
+        } else if run.copy_others {
+            others.push(path);
+        } else if run.files.is_some() && !is_image(&path) && sniff_format(&path).is_none() {
+            log!("Warning: {} isn't an image, skipping it.", path.display());
+            skipped += 1;
+        }
+    }
+    // the first of every n images, in path order (or the order of `--files-from`).
+    if let Some(sample) = run.sample {
+        images = images.into_iter().step_by(sample).collect();
+    }
+
+    let mut targets = output_paths(&source_path, &images, run);
+    if let Some(format_pass) = run.format_pass.borrow().as_ref() {
+        (images, targets) = images.into_iter().zip(targets).filter(|(_, target_path)| format_pass.includes(target_path)).unzip();
+        // the pass over the images without defaults takes care of everything else.
+        if let FormatPass::Only(_) = format_pass {
+            others.clear();
+            skipped = 0;
+        }
+    }
+
+    let mut summary = Summary::default();
+    for _ in 0..skipped {
+        summary.record(Outcome::Unreadable, 0, 0);
+    }
+    let count = images.len();
+    run.queued.set(count);
+    for (index, (path, target_path)) in images.into_iter().zip(targets).enumerate() {
+        // the image in flight is finished, but no new one is started after the deadline.
+        if run.deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) {
+            summary.remaining = count - index;
+            break;
+        }
+        process_file(&source_path, path, target_path, run, &executor, &mut summary);
+    }
+    // other files are copied by the run that finishes the images.
+    if let (Some(dest_path), 0, false) = (&run.dest_path, summary.remaining, run.preflight) {
+        summary.copied = copy_others(&source_path, dest_path, &others, run);
+    }
+    summary.wall_time = start.elapsed();
+    if run.watch {
+        summary.print();
+        watch_directory(&source_path, run, executor);
+    }
+    summary
+}
+
+/// The files of a `--files-from` list, as paths under the source. Files that don't exist or are
+/// outside the source are reported and counted as `skipped`, those in an output directory are
+/// left out like in a walk.
+fn listed_files(source_path: &std::path::Path, files: &[std::path::PathBuf], run: &RunArgs, skipped: &mut usize) -> Vec<std::path::PathBuf> {
+    let source = canonical_path(source_path);
+    let mut seen = std::collections::HashSet::new();
+    let mut paths = Vec::new();
+    for file in files {
+        if !file.is_file() {
+            log!("Warning: {} doesn't exist, skipping it.", file.display());
+            *skipped += 1;
+            continue;
+        }
+        let path = match canonical_path(file).strip_prefix(&source) {
+            Ok(relative) => source_path.join(relative),
+            Err(_) => {
+                log!("Warning: {} isn't inside the source {}, skipping it.", file.display(), source_path.display());
+                *skipped += 1;
+                continue;
+            }
+        };
+        if !is_in_output_dir(&path, run) && seen.insert(path.clone()) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// Paths listed one per line in a file, or on stdin for `-`.
+fn read_file_list(path: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let text = match path.to_str() {
+        Some("-") => std::io::read_to_string(std::io::stdin()).unwrap(),
+        _ => std::fs::read_to_string(path).unwrap_or_else(|error| panic!("Can't read file list {}: {}", path.display(), error)),
+    };
+    text.lines().map(|line| line.trim_end_matches('\r')).filter(|line| !line.trim().is_empty()).map(std::path::PathBuf::from).collect()
+}
+
+/// Copy files that aren't processed into the dest at the same relative path (into its root with
+/// `--flatten`), returning how many were copied.
+fn copy_others(source_path: &std::path::Path, dest_path: &std::path::Path, others: &[std::path::PathBuf], run: &RunArgs) -> usize {
+    let mut copied = 0;
+    for path in others {
+        let target_path = match run.flatten {
+            true => dest_path.join(path.file_name().unwrap()),
+            false => dest_path.join(path.strip_prefix(source_path).unwrap_or(path)),
+        };
+        std::fs::create_dir_all(target_path.parent().unwrap()).unwrap();
+        let Some(target_path) = conflict_target(path, &target_path, run) else {
+            continue;
+        };
+        if !trash_replaced(&target_path, run) {
+            continue;
+        }
+        run.with_retry(path, |_: &std::io::Error| true, || std::fs::copy(path, &target_path)).unwrap();
+        copied += 1;
+    }
+    copied
+}
+
+/// Name of the per-directory config files that override task options for their subtree.
+const CONFIG_FILE: &str = ".rsimg";
+
+/// Directories under the source with a `.rsimg` config, sorted.
+fn config_dirs(source_path: &std::path::Path, run: &RunArgs) -> Vec<std::path::PathBuf> {
+    WalkDir::new(source_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !is_excluded_dir(source_path, entry, run) && !is_ignored_entry(source_path, entry, run))
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().is_file() && entry.file_name() == CONFIG_FILE)
+        .map(|entry| entry.path().parent().unwrap().to_path_buf())
+        .collect()
+}
+
+/// The directory whose config applies to an image: the nearest one with a `.rsimg` file, or the
+/// source itself when there is none.
+fn config_scope(source_path: &std::path::Path, path: &std::path::Path) -> std::path::PathBuf {
+    path.ancestors()
+        .skip(1)
+        .take_while(|directory| directory.starts_with(source_path))
+        .find(|directory| directory.join(CONFIG_FILE).is_file())
+        .unwrap_or(source_path)
+        .to_path_buf()
+}
+
+fn is_in_scope(source_path: &std::path::Path, path: &std::path::Path, run: &RunArgs) -> bool {
+    match run.scope.borrow().as_ref() {
+        Some(scope) => config_scope(source_path, path) == *scope,
+        None => true,
+    }
+}
+
+/// Read the options of a `.rsimg` config: its top-level keys, and those of a table named after
+/// the task, which take precedence.
+///
+/// ```toml
+/// size = "64x64"
+///
+/// [convert]
+/// format = "png"
+/// ```
+///
+/// The `format` table holds defaults by output format instead, see `read_format_defaults`.
+fn read_config(path: &std::path::Path, task: &str) -> Vec<(String, String)> {
+    let config = parse_config(path);
+    let option_value = |key: &str, value: &toml::Value| config_value(path, key, value);
+    let mut options: Vec<(String, String)> = Vec::new();
+    let mut task_options: Vec<(String, String)> = Vec::new();
+    for (key, value) in &config {
+        match value {
+            toml::Value::Table(table) if key == task => {
+                task_options.extend(table.iter().map(|(key, value)| (key.clone(), option_value(key, value))));
+            }
+            // tables of other tasks don't apply.
+            toml::Value::Table(_) => {}
+            _ => options.push((key.clone(), option_value(key, value))),
+        }
+    }
+    options.extend(task_options);
+    options
+}
+
+fn parse_config(path: &std::path::Path) -> toml::Table {
+    let text = std::fs::read_to_string(path).unwrap();
+    text.parse().unwrap_or_else(|error| panic!("Invalid config {}: {}", path.display(), error))
+}
+
+/// An option value of a config, which may be written as a TOML string, number or boolean.
+fn config_value(path: &std::path::Path, key: &str, value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => value.to_string(),
+        _ => panic!("Invalid option in {}: {}", path.display(), key),
+    }
+}
+
+/// Read the defaults by output format of a `.rsimg` config, the tables under `format` named
+/// after an extension of the format.
+///
+/// ```toml
+/// [format.jpg]
+/// filter = "lanczos3"
+/// quality = 82
+///
+/// [format.png]
+/// filter = "catmull-rom"
+/// ```
+///
+/// Defaults only fill in options that neither the command line nor a config gives, and only
+/// those the task takes, so one set serves every task of a pipeline.
+fn read_format_defaults(path: &std::path::Path) -> Vec<(image::ImageFormat, Vec<(String, String)>)> {
+    let config = parse_config(path);
+    let Some(formats) = config.get("format") else {
+        return Vec::new();
+    };
+    let Some(formats) = formats.as_table() else {
+        panic!("Invalid option in {}: format (expected a table per output format)", path.display());
+    };
+    formats
+        .iter()
+        .map(|(extension, defaults)| {
+            let format = image::ImageFormat::from_extension(extension).unwrap_or_else(|| panic!("Invalid format in {}: {}", path.display(), extension));
+            let Some(defaults) = defaults.as_table() else {
+                panic!("Invalid format in {}: {} (expected a table of options)", path.display(), extension);
+            };
+            (format, defaults.iter().map(|(key, value)| (key.clone(), config_value(path, key, value))).collect())
+        })
+        .collect()
+}
+
+/// Leave the original image untouched, copying it over when writing to a dest.
+fn keep_original(source_path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) {
+    // in place there is nothing to write, unless metadata has to be removed.
+    if target_path == source_path && !run.strip_gps {
+        return;
+    }
+    // the page of a PDF is kept as an image.
+    #[cfg(feature = "pdf")]
+    if is_pdf(source_path) {
+        if let Ok(page) = open_image(source_path, run) {
+            save_image(&page, source_path, target_path, run, &parse_save_args(&Default::default()));
+        }
+        return;
+    }
+    let Some(target_path) = conflict_target(source_path, target_path, run) else {
+        return;
+    };
+    let target_path = target_path.as_path();
+    // a hardlink shares the original's bytes, falling back to a copy when it can't be made.
+    if run.link_unchanged && !run.strip_gps && target_path != source_path {
+        if !trash_replaced(target_path, run) {
+            return;
+        }
+        if target_path.exists() {
+            std::fs::remove_file(target_path).unwrap();
+        }
+        if std::fs::hard_link(source_path, target_path).is_ok() {
+            if run.output_map {
+                run.outputs.borrow_mut().push(target_path.to_path_buf());
+            }
+            return;
+        }
+    }
+    // the pixels are untouched, so the original orientation still applies.
+    if let Ok(bytes) = run.read(source_path) {
+        write_file(source_path, target_path, bytes, run, false);
+    }
+}
+
+/// Where an output goes given `--on-conflict`, or `None` when it shouldn't be written.
+///
+/// Only existing files other than the source and this run's own outputs conflict. A renamed
+/// output reserves its name by creating the file, so no other output can take it meanwhile.
+fn conflict_target(source_path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) -> Option<std::path::PathBuf> {
+    refuse_unsafe(source_path, target_path, run);
+    let is_own = || target_path == source_path || target_path.canonicalize().is_ok_and(|path| run.written.borrow().contains_key(&path));
+    if !target_path.exists() {
+        return Some(target_path.to_path_buf());
+    }
+    if run.on_conflict == OnConflict::Overwrite || is_own() {
+        return Some(target_path.to_path_buf());
+    }
+    match run.on_conflict {
+        OnConflict::Skip => {
+            println!("Notice: {} already exists, skipping it.", target_path.display());
+            run.conflicted.set(true);
+            None
+        }
+        OnConflict::Error => panic!("Output already exists: {} (see --on-conflict)", target_path.display()),
+        _ => {
+            let stem = target_path.file_stem().unwrap().to_string_lossy();
+            let extension = target_path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+            for index in 1.. {
+                let candidate = target_path.with_file_name(format!("{} ({}){}", stem, index, extension));
+                match std::fs::OpenOptions::new().write(true).create_new(true).open(&candidate) {
+                    Ok(_) => {
+                        run.reserved.borrow_mut().insert(candidate.clone());
+                        return Some(candidate);
+                    }
+                    Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                    Err(error) => panic!("Can't create {}: {}", candidate.display(), error),
+                }
+            }
+            unreachable!()
+        }
+    }
+}
+
+/// Give an encoded JPEG the EXIF metadata of its source, minus the GPS fields.
+///
+/// Encoding drops all metadata, so this restores everything else (camera, timestamps, ...) while
+/// making sure the location never ends up in the output. When the pixels were `reoriented` the
+/// Orientation field is dropped as well, so viewers don't rotate the output a second time. An
+/// orientation the output was given itself, by a lossless rotate, is kept.
+fn strip_gps(source_path: &std::path::Path, target_path: &std::path::Path, bytes: Vec<u8>, reoriented: bool) -> Vec<u8> {
+    let is_jpeg = |path: &std::path::Path| image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::Jpeg);
+    if !is_jpeg(target_path) {
+        println!("Notice: {} has no EXIF, nothing to strip GPS from.", target_path.display());
+        return bytes;
+    }
+    let exif = if is_jpeg(source_path) {
+        let exif = std::fs::read(source_path).ok().and_then(|bytes| metadata::jpeg_exif(&bytes)).and_then(metadata::without_gps);
+        if reoriented {
+            exif.and_then(metadata::without_orientation)
+        } else {
+            exif
+        }
+    } else {
+        None
+    };
+    let exif = match metadata::jpeg_exif(&bytes).and_then(|exif| metadata::orientation(&exif)) {
+        Some(orientation) => metadata::with_orientation(exif, orientation),
+        None => exif,
+    };
+    metadata::with_exif(&bytes, exif.as_deref())
+}
+
+/// Write an output file.
+///
+/// Every task writes through here. Whatever the output replaces is moved to the trash with
+/// `--trash`, otherwise it is overwritten permanently, and `--on-conflict` decides whether files
+/// other than the source are replaced at all. Encoded PNGs are marked as sRGB unless
+/// `--no-srgb-chunk`.
+fn write_output(source_path: &std::path::Path, target_path: &std::path::Path, bytes: Vec<u8>, run: &RunArgs) {
+    let bytes = match run.srgb_chunk && image::ImageFormat::from_path(target_path).ok() == Some(image::ImageFormat::Png) {
+        true => metadata::with_srgb(bytes),
+        false => bytes,
+    };
+    if let Some(target_path) = conflict_target(source_path, target_path, run) {
+        write_file(source_path, &target_path, bytes, run, run.reorients);
+    }
+}
+
+/// Stop the run when the disk is full, after the partial output was removed: any further image
+/// would fail the same way. The working directory of zip archives is removed as well.
+fn halt_on_disk_full(target_path: &std::path::Path) -> ! {
+    log!("Error: the disk is full, stopping before {} could be written. Outputs written before it are complete.", target_path.display());
+    let work_path = work_path();
+    if work_path.exists() {
+        let _ = std::fs::remove_dir_all(&work_path);
+    }
+    std::process::exit(EXIT_DISK_FULL);
+}
+
+/// Give a written output the permissions (`--preserve-mode`) and owner (`--preserve-owner`) of
+/// its source. An owner that can't be changed, e.g. without root, is only warned about.
+fn preserve_metadata(source_metadata: &std::fs::Metadata, path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) {
+    if run.preserve_mode {
+        std::fs::set_permissions(path, source_metadata.permissions()).unwrap();
+    }
+    if run.preserve_owner {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if let Err(error) = std::os::unix::fs::chown(path, Some(source_metadata.uid()), Some(source_metadata.gid())) {
+                log!("Warning: can't give {} the owner of its source: {}", target_path.display(), error);
+            }
+        }
+        #[cfg(not(unix))]
+        log!("Warning: can't give {} the owner of its source: --preserve-owner is only supported on Unix", target_path.display());
+    }
+}
+
+/// Move the file an output is about to replace to the trash with `--trash`. A name reserved by
+/// `conflict_target` holds nothing to keep. Returns whether the output may be written, which it
+/// mustn't when the file couldn't be trashed.
+fn trash_replaced(target_path: &std::path::Path, run: &RunArgs) -> bool {
+    let is_reserved = run.reserved.borrow_mut().remove(target_path);
+    if run.trash && !is_reserved && target_path.exists() {
+        if let Err(error) = trash::delete(target_path) {
+            log!("Warning: can't move {} to the trash, leaving it as it is: {}", target_path.display(), error);
+            run.unremoved.set(true);
+            return false;
+        }
+    }
+    true
+}
+
+/// Write an output file whose pixels were `reoriented` (rotated or flipped) or not, where
+/// `conflict_target` put it.
+fn write_file(source_path: &std::path::Path, target_path: &std::path::Path, bytes: Vec<u8>, run: &RunArgs, reoriented: bool) {
+    let bytes = if run.strip_gps { strip_gps(source_path, target_path, bytes, reoriented) } else { bytes };
+    // the source may be the file that is replaced, take what has to be preserved first.
+    let source_metadata = match run.preserve_mode || run.preserve_owner {
+        true => Some(std::fs::metadata(source_path).unwrap()),
+        false => None,
+    };
+    if !trash_replaced(target_path, run) {
+        return;
+    }
+    run.written_bytes.set(run.written_bytes.get() + bytes.len() as u64);
+    // write next to the target and move it in place, so an interrupted run never leaves half a file.
+    let temporary_path = target_path.with_file_name(format!(".{}.rsimg-tmp", target_path.file_name().unwrap().to_string_lossy()));
+    // a full disk won't free up by retrying.
+    let written = run.with_retry(target_path, |error: &std::io::Error| error.kind() != std::io::ErrorKind::StorageFull, || std::fs::write(&temporary_path, &bytes));
+    if let Err(error) = written {
+        let _ = std::fs::remove_file(&temporary_path);
+        if error.kind() == std::io::ErrorKind::StorageFull {
+            halt_on_disk_full(target_path);
+        }
+        panic!("Can't write {}: {}", target_path.display(), error);
+    }
+    if let Some(source_metadata) = &source_metadata {
+        preserve_metadata(source_metadata, &temporary_path, target_path, run);
+    }
+    std::fs::rename(&temporary_path, target_path).unwrap();
+    if run.verify {
+        verify_output(source_path, target_path, run);
+    }
+    if let Some(baseline) = &run.baseline {
+        compare_baseline(target_path, baseline, run);
+    }
+    // outputs that didn't verify are gone again.
+    if run.output_map && target_path.exists() {
+        run.outputs.borrow_mut().push(target_path.to_path_buf());
+    }
+    if run.watch {
+        let modified = std::fs::metadata(target_path).unwrap().modified().unwrap();
+        run.written.borrow_mut().insert(target_path.canonicalize().unwrap(), modified);
+    }
+}
+
+/// Check that a written output decodes again, for `--verify`.
+///
+/// An output that doesn't is deleted, unless it replaced its source in place and is all that is
+/// left of the image.
+fn verify_output(source_path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) {
+    let Err(error) = image::open(target_path) else {
+        return;
+    };
+    log!("Error: {} doesn't decode after writing: {}", target_path.display(), error);
+    if target_path != source_path {
+        std::fs::remove_file(target_path).unwrap();
+    }
+    run.unverified.set(true);
+}
+
+/// Check a written output against the file at the same path under the `--baseline`, logging
+/// outputs that are missing from it or fall below the threshold as regressions.
+fn compare_baseline(target_path: &std::path::Path, (baseline_path, threshold): &(std::path::PathBuf, quality::Threshold), run: &RunArgs) {
+    let relative_path = target_path.strip_prefix(run.dest_path.as_ref().unwrap()).unwrap_or(target_path);
+    let baseline_path = baseline_path.join(relative_path);
+    let regression = match (image::open(target_path), image::open(&baseline_path)) {
+        (Ok(_), Err(_)) if !baseline_path.exists() => Some(format!("{} is missing", baseline_path.display())),
+        (Ok(_), Err(error)) => Some(format!("can't read {}: {}", baseline_path.display(), error)),
+        (Ok(output), Ok(baseline)) if output.dimensions() != baseline.dimensions() => {
+            Some(format!("{}x{} instead of {}x{}", output.width(), output.height(), baseline.width(), baseline.height()))
+        }
+        (Ok(output), Ok(baseline)) => {
+            let (metric, value, reaches_threshold) = threshold.measure(&baseline, &output);
+            run.report_field(&format!("baseline_{}", metric), value);
+            (!reaches_threshold).then(|| format!("{} {:.4}", metric, value))
+        }
+        // outputs that don't decode are up to `--verify`.
+        (Err(_), _) => None,
+    };
+    if let Some(regression) = regression {
+        log!("Error: {} differs from the baseline: {}", target_path.display(), regression);
+        run.regressed.set(true);
+    }
+}
+
+/// Encoder settings taken from the options of any task that writes images.
+#[derive(Copy, Clone)]
+struct SaveArgs {
+    /// JPEG quality, the encoder default when not given.
+    quality: Option<u8>,
+    /// Palette size for GIFs.
+    colors: usize,
+    /// Whether GIFs are dithered.
+    dither: bool,
+    /// Convert to premultiplied (true) or straight (false) alpha before encoding.
+    premultiply: Option<bool>,
+    /// Deflate level for PNGs.
+    png_compression: image::codecs::png::CompressionType,
+    /// Scanline filter for PNGs.
+    png_filter: image::codecs::png::FilterType,
+}
+
+/// Parse the encoder options shared by every task that writes images.
+///
+///  ## quality
+///  - 1-100, JPEG quality
+///
+///  ## colors
+///  - 2-256, palette size for GIFs (default 256)
+///
+///  ## dither
+///  - on (default), Floyd-Steinberg dithering for GIFs
+///  - off
+///
+///  ## premultiply
+///  - true, multiply colors by alpha before saving
+///  - false, divide premultiplied colors by alpha to get straight alpha back
+///
+///  ## png_compression, png_filter
+///  - see `parse_png_args`, fast and adaptive by default
+fn parse_save_args(options: &std::collections::HashMap<&str, &str>) -> SaveArgs {
+    let colors = match options.get("colors") {
+        None => 256,
+        Some(value) => match value.parse::<usize>() {
+            Ok(colors) if (2..=256).contains(&colors) => colors,
+            _ => panic!("Invalid colors: {}", value),
+        },
+    };
+    let dither = match options.get("dither").copied() {
+        None | Some("on") => true,
+        Some("off") => false,
+        Some(dither) => panic!("Invalid dither: {}", dither),
+    };
+    let premultiply = match options.get("premultiply").copied() {
+        None => None,
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        Some(premultiply) => panic!("Invalid premultiply: {}", premultiply),
+    };
+    let (png_compression, png_filter) = parse_png_args(options, image::codecs::png::CompressionType::Fast);
+    SaveArgs {
+        quality: parse_quality(options),
+        colors,
+        dither,
+        premultiply,
+        png_compression,
+        png_filter,
+    }
+}
+
+/// Parse the PNG encoder options, with the given compression when there is none.
+///
+///  ## png_compression
+///  - fast, default or best deflate compression
+///
+///  ## png_filter
+///  - adaptive (default), picks a filter per scanline
+///  - none, sub, up, avg, paeth
+fn parse_png_args(options: &std::collections::HashMap<&str, &str>, compression: image::codecs::png::CompressionType) -> (image::codecs::png::CompressionType, image::codecs::png::FilterType) {
+    use image::codecs::png::{CompressionType, FilterType};
+    let compression = match options.get("png_compression").copied() {
+        None => compression,
+        Some("fast") => CompressionType::Fast,
+        Some("default") => CompressionType::Default,
+        Some("best") => CompressionType::Best,
+        Some(compression) => panic!("Invalid png_compression: {}", compression),
+    };
+    let filter = match options.get("png_filter").copied() {
+        None | Some("adaptive") => FilterType::Adaptive,
+        Some("none") => FilterType::NoFilter,
+        Some("sub") => FilterType::Sub,
+        Some("up") => FilterType::Up,
+        Some("avg") => FilterType::Avg,
+        Some("paeth") => FilterType::Paeth,
+        Some(filter) => panic!("Invalid png_filter: {}", filter),
+    };
+    (compression, filter)
+}
+
+/// Convert interleaved samples between straight and premultiplied alpha, alpha being the last
+/// channel of each pixel.
+fn convert_alpha<T: Copy + Into<f32>>(samples: &mut [T], channels: usize, max: f32, premultiply: bool, from_f32: impl Fn(f32) -> T) {
+    for pixel in samples.chunks_exact_mut(channels) {
+        let alpha = pixel[channels - 1].into() / max;
+        for sample in &mut pixel[..channels - 1] {
+            let value = (*sample).into();
+            let value = match premultiply {
+                true => value * alpha,
+                false if alpha > 0.0 => (value / alpha).min(max),
+                false => 0.0,
+            };
+            *sample = from_f32(value);
+        }
+    }
+}
+
+/// The image with premultiplied or straight alpha. Images without alpha are returned as they are.
+fn with_alpha_mode(image: &image::DynamicImage, premultiply: bool) -> image::DynamicImage {
+    let mut image = image.clone();
+    match &mut image {
+        image::DynamicImage::ImageLumaA8(buffer) => convert_alpha(buffer, 2, 255.0, premultiply, |v| v.round() as u8),
+        image::DynamicImage::ImageRgba8(buffer) => convert_alpha(buffer, 4, 255.0, premultiply, |v| v.round() as u8),
+        image::DynamicImage::ImageLumaA16(buffer) => convert_alpha(buffer, 2, 65535.0, premultiply, |v| v.round() as u16),
+        image::DynamicImage::ImageRgba16(buffer) => convert_alpha(buffer, 4, 65535.0, premultiply, |v| v.round() as u16),
+        image::DynamicImage::ImageRgba32F(buffer) => convert_alpha(buffer, 4, 1.0, premultiply, |v| v),
+        _ => {}
+    }
+    image
+}
+
+/// Save an image in the format of the target path.
+///
+/// JPEGs honor the quality when one is given and GIFs go through median cut quantization.
+fn save_image(image: &image::DynamicImage, source_path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs, save: &SaveArgs) {
+    let format = image::ImageFormat::from_path(target_path).unwrap();
+    let converted = save.premultiply.map(|premultiply| with_alpha_mode(image, premultiply));
+    let image = converted.as_ref().unwrap_or(image);
+    let narrowed = narrowed(image, format);
+    let image = narrowed.as_ref().unwrap_or(image);
+    let bytes = run.timed_encode(|| match save.quality {
+        _ if run.quality_target.is_some() && is_lossy(format) => encode_auto_quality(image, format, run),
+        Some(quality) if format == image::ImageFormat::Jpeg => encode_image(image, format, quality),
+        _ if format == image::ImageFormat::Gif => encode_gif(image, save.colors, save.dither),
+        _ if format == image::ImageFormat::WebP => encode_webp(image, save.quality),
+        _ if format == image::ImageFormat::Png => encode_png(image, save.png_compression, save.png_filter),
+        _ => {
+            let mut bytes = Vec::new();
+            image.write_to(&mut std::io::Cursor::new(&mut bytes), format).unwrap();
+            bytes
+        }
+    });
+    write_output(source_path, target_path, bytes, run);
+}
+
+/// Parse the `filter` option of tasks that resample images, or the `filter_down` or `filter_up`
+/// one given by `key`. `None` when it isn't given.
+///
+/// Every `image::imageops::FilterType` is available by its canonical name, with aliases:
+///  - nearest (also point, box)
+///  - triangle (also linear)
+///  - catmull-rom (also cubic, default)
+///  - gaussian
+///  - lanczos3
+///
+/// Two names separated by a colon, e.g. `nearest:lanczos3`, give the horizontal and the vertical
+/// filter, for sprites that should stay sharp in one direction only.
+fn parse_filter(options: &std::collections::HashMap<&str, &str>, key: &str) -> Option<(image::imageops::FilterType, image::imageops::FilterType)> {
+    let filter_value = match options.get(key).copied() {
+        None | Some("default") => return None,
+        Some(filter_value) => filter_value,
+    };
+    let filter = |name| filter_from_name(name).unwrap_or_else(|| panic!("Invalid {}: {}", key, filter_value));
+    match filter_value.split_once(':') {
+        Some((horizontal, vertical)) => Some((filter(horizontal), filter(vertical))),
+        None => Some((filter(filter_value), filter(filter_value))),
+    }
+}
+
+fn filter_from_name(name: &str) -> Option<image::imageops::FilterType> {
+    match name {
+        "nearest" | "point" | "box" => Some(image::imageops::FilterType::Nearest),
+        "triangle" | "linear" => Some(image::imageops::FilterType::Triangle),
+        "catmull-rom" | "cubic" => Some(image::imageops::FilterType::CatmullRom),
+        "gaussian" => Some(image::imageops::FilterType::Gaussian),
+        "lanczos3" => Some(image::imageops::FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+/// Parse the optional `quality` option (1-100).
+fn parse_quality(options: &std::collections::HashMap<&str, &str>) -> Option<u8> {
+    options.get("quality").map(|quality_value| match quality_value.parse::<u8>() {
+        Ok(quality) if (1..=100).contains(&quality) => quality,
+        _ => panic!("Invalid quality: {}", quality_value),
+    })
+}
+
+/// How images are resampled when resizing.
+#[derive(Copy, Clone)]
+struct Resample {
+    /// Filters for the horizontal and the vertical direction when shrinking, usually the same.
+    filters_down: (image::imageops::FilterType, image::imageops::FilterType),
+    /// And when enlarging.
+    filters_up: (image::imageops::FilterType, image::imageops::FilterType),
+    /// Halve large reductions step by step before the final resize.
+    staged: bool,
+}
+
+/// Parse the resampling options of tasks that resize images.
+///
+///  ## filter
+///  - see `parse_filter` (default catmull-rom)
+///
+///  ## filter_down, filter_up
+///  - see `parse_filter` (default `filter`), the filter for sides that shrink or grow, e.g.
+///    `filter_down=lanczos3,filter_up=nearest` for mixed batches with pixel art
+///
+///  ## downscale
+///  - direct (default), resize in one step
+///  - staged, halve repeatedly before the final resize to reduce aliasing on big reductions
+fn parse_resample(options: &std::collections::HashMap<&str, &str>) -> Resample {
+    let staged = match options.get("downscale").copied() {
+        None | Some("direct") => false,
+        Some("staged") => true,
+        Some(downscale) => panic!("Invalid downscale: {}", downscale),
+    };
+    let filters = parse_filter(options, "filter").unwrap_or((image::imageops::FilterType::CatmullRom, image::imageops::FilterType::CatmullRom));
+    Resample {
+        filters_down: parse_filter(options, "filter_down").unwrap_or(filters),
+        filters_up: parse_filter(options, "filter_up").unwrap_or(filters),
+        staged,
+    }
+}
+
+/// Halve an image with a triangle filter while it is still at least twice the target size, so
+/// that the final resize never reduces by more than 2x. Each halving averages neighboring pixels,
+/// which removes the detail that would otherwise alias in a single big reduction.
+fn staged_downscale(image: &image::DynamicImage, size: (u32, u32)) -> Option<image::DynamicImage> {
+    let mut staged: Option<image::DynamicImage> = None;
+    loop {
+        let current = staged.as_ref().unwrap_or(image);
+        if current.width() < size.0 * 2 || current.height() < size.1 * 2 {
+            return staged;
+        }
+        staged = Some(resize_exact(current, (current.width() / 2, current.height() / 2), image::imageops::FilterType::Triangle));
+    }
+}
+
+fn resize_by_size(image: &image::DynamicImage, size: (u32, u32), resample: Resample) -> image::DynamicImage {
+    if size.0 == 0 || size.1 == 0 {
+        panic!("Invalid target size: {}x{}", size.0, size.1);
+    }
+    let staged = if resample.staged { staged_downscale(image, size) } else { None };
+    let image = staged.as_ref().unwrap_or(image);
+    // each side is resampled with the filter for the way it changes, kept sides with either.
+    let horizontal = if size.0 > image.width() { resample.filters_up.0 } else { resample.filters_down.0 };
+    let vertical = if size.1 > image.height() { resample.filters_up.1 } else { resample.filters_down.1 };
+    if horizontal == vertical {
+        return resize_exact(image, size, horizontal);
+    }
+    // one pass per direction. the filters interpolate, so the direction a pass keeps is left
+    // as it is, except by gaussian, which softens it slightly.
+    let resized = resize_exact(image, (size.0, image.height()), horizontal);
+    resize_exact(&resized, size, vertical)
+}
+
+/// Resize to exactly `size`. Images with more than 8 bits per channel are resized in their own
+/// color type so they keep their precision, others as 8-bit RGBA.
+fn resize_exact(image: &image::DynamicImage, size: (u32, u32), filter: image::imageops::FilterType) -> image::DynamicImage {
+    match is_high_depth(image) {
+        true => image.resize_exact(size.0, size.1, filter),
+        false => image::DynamicImage::ImageRgba8(image::imageops::resize(image, size.0, size.1, filter)),
+    }
+}
+
+/// Whether an image has more than 8 bits per channel.
+fn is_high_depth(image: &image::DynamicImage) -> bool {
+    image.color().bytes_per_pixel() > image.color().channel_count()
+}
+
+/// A 8-bit color at 16 bits per channel.
+fn widen_color(color: image::Rgba<u8>) -> image::Rgba<u16> {
+    image::Rgba(color.0.map(|channel| channel as u16 * 257))
+}
+
+/// The RGB version of an RGBA image in the same bit depth, for results that are opaque because
+/// their source had no alpha.
+fn without_alpha(image: image::DynamicImage) -> image::DynamicImage {
+    match image {
+        image::DynamicImage::ImageRgba16(_) => image::DynamicImage::ImageRgb16(image.to_rgb16()),
+        image::DynamicImage::ImageRgba32F(_) => image::DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        image => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+    }
+}
+
+/// The image at 8 bits per channel when the format can't store more, keeping its channels.
+fn narrowed(image: &image::DynamicImage, format: image::ImageFormat) -> Option<image::DynamicImage> {
+    use image::ImageFormat::{Farbfeld, Hdr, OpenExr, Png, Pnm, Tiff};
+    if !is_high_depth(image) || matches!(format, Png | Tiff | Pnm | Farbfeld | OpenExr | Hdr) {
+        return None;
+    }
+    let color = image.color();
+    Some(match (color.has_color(), color.has_alpha()) {
+        (false, false) => image::DynamicImage::ImageLuma8(image.to_luma8()),
+        (false, true) => image::DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        (true, false) => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+        (true, true) => image::DynamicImage::ImageRgba8(image.to_rgba8()),
+    })
+}
+
+fn is_tiff(path: &std::path::Path) -> bool {
+    image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::Tiff)
+}
+
+fn is_png(path: &std::path::Path) -> bool {
+    image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::Png)
+}
+
+fn is_webp(path: &std::path::Path) -> bool {
+    image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::WebP)
+}
+
+/// Decode every frame of an animated PNG with its delay in milliseconds, and how many times the
+/// animation plays (0 for forever). `None` for still PNGs.
+fn decode_apng(bytes: &[u8]) -> Option<(Vec<(image::DynamicImage, u16)>, u32)> {
+    use image::AnimationDecoder;
+    let decoder = image::codecs::png::PngDecoder::new(std::io::Cursor::new(bytes)).ok()?;
+    if !decoder.is_apng() {
+        return None;
+    }
+    let frames = decoder.apng().into_frames().collect_frames().ok()?;
+    if frames.len() < 2 {
+        return None;
+    }
+    // the image decoder doesn't expose the play count, read it from the animation control chunk.
+    let plays = png::Decoder::new(std::io::Cursor::new(bytes))
+        .read_info()
+        .ok()
+        .and_then(|reader| reader.info().animation_control.map(|control| control.num_plays))
+        .unwrap_or(0);
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            let delay = (numerator as f64 / denominator.max(1) as f64).round().min(u16::MAX as f64) as u16;
+            (image::DynamicImage::ImageRgba8(frame.into_buffer()), delay)
+        })
+        .collect();
+    Some((frames, plays))
+}
+
+/// Encode full-canvas frames with delays in milliseconds as an animated PNG.
+fn encode_apng(frames: &[(image::DynamicImage, u16)], plays: u32) -> Vec<u8> {
+    let (width, height) = frames[0].0.dimensions();
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Best);
+    encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+    encoder.set_animated(frames.len() as u32, plays).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    for (frame, delay) in frames {
+        writer.set_frame_delay(*delay, 1000).unwrap();
+        writer.write_image_data(frame.to_rgba8().as_raw()).unwrap();
+    }
+    writer.finish().unwrap();
+    bytes
+}
+
+/// Decode every frame of an animated WebP with its delay in milliseconds, and how many times the
+/// animation plays (0 for forever). `None` for still WebPs.
+fn decode_animated_webp(bytes: &[u8]) -> Option<(Vec<(image::DynamicImage, u16)>, u32)> {
+    use image::AnimationDecoder;
+    let decoder = image::codecs::webp::WebPDecoder::new(std::io::Cursor::new(bytes)).ok()?;
+    if !decoder.has_animation() {
+        return None;
+    }
+    let frames = decoder.into_frames().collect_frames().ok()?;
+    if frames.len() < 2 {
+        return None;
+    }
+    // the image decoder doesn't expose the loop count, read it from the ANIM chunk.
+    let mut plays = 0;
+    let mut index = 12;
+    while index + 8 <= bytes.len() {
+        let size = u32::from_le_bytes(bytes[index + 4..index + 8].try_into().unwrap()) as usize;
+        if &bytes[index..index + 4] == b"ANIM" && index + 14 <= bytes.len() {
+            plays = u16::from_le_bytes([bytes[index + 12], bytes[index + 13]]) as u32;
+            break;
+        }
+        // chunks are padded to an even size.
+        index += 8 + size + size % 2;
+    }
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            let delay = (numerator as f64 / denominator.max(1) as f64).round().min(u16::MAX as f64) as u16;
+            (image::DynamicImage::ImageRgba8(frame.into_buffer()), delay)
+        })
+        .collect();
+    Some((frames, plays))
+}
+
+/// Encode full-canvas frames with delays in milliseconds as an animated WebP, lossy with the given
+/// quality or at the encoder's default quality.
+fn encode_animated_webp(frames: &[(image::DynamicImage, u16)], plays: u32, quality: Option<u8>) -> Vec<u8> {
+    let (width, height) = frames[0].0.dimensions();
+    let mut config = webp::WebPConfig::new().unwrap();
+    config.quality = quality.unwrap_or(image::codecs::webp::WebPQuality::DEFAULT) as f32;
+    let pixels: Vec<image::RgbaImage> = frames.iter().map(|(frame, _)| frame.to_rgba8()).collect();
+    let mut encoder = webp::AnimEncoder::new(width, height, &config);
+    encoder.set_loop_count(plays as i32);
+    // frames are placed by their start time.
+    let mut timestamp = 0;
+    for (pixels, (_, delay)) in pixels.iter().zip(frames) {
+        encoder.add_frame(webp::AnimFrame::from_rgba(pixels.as_raw(), width, height, timestamp));
+        timestamp += *delay as i32;
+    }
+    encoder.encode().to_vec()
+}
+
+/// Decode every page of a TIFF, or fail with the number of pages decoded before the one that
+/// couldn't be, and why.
+fn decode_tiff_pages(bytes: &[u8]) -> Result<Vec<image::DynamicImage>, (usize, String)> {
+    use tiff::decoder::DecodingResult;
+    use tiff::ColorType;
+
+    let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(bytes)).map_err(|error| (0, error.to_string()))?;
+    let mut pages = Vec::new();
+    loop {
+        let failed = |error: tiff::TiffError| (pages.len(), error.to_string());
+        let (width, height) = decoder.dimensions().map_err(failed)?;
+        let color_type = decoder.colortype().map_err(failed)?;
+        let data = decoder.read_image().map_err(failed)?;
+        let page = match (color_type, data) {
+            (ColorType::Gray(8), DecodingResult::U8(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageLuma8),
+            (ColorType::GrayA(8), DecodingResult::U8(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageLumaA8),
+            (ColorType::RGB(8), DecodingResult::U8(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageRgb8),
+            (ColorType::RGBA(8), DecodingResult::U8(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageRgba8),
+            (ColorType::Gray(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageLuma16),
+            (ColorType::GrayA(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageLumaA16),
+            (ColorType::RGB(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageRgb16),
+            (ColorType::RGBA(16), DecodingResult::U16(data)) => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageRgba16),
+            (color_type, _) => return Err((pages.len(), format!("unsupported color type {:?}", color_type))),
+        };
+        match page {
+            Some(page) => pages.push(page),
+            None => return Err((pages.len(), "image data ends early".to_string())),
+        }
+
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image().map_err(|error| (pages.len(), error.to_string()))?;
+    }
+    Ok(pages)
+}
+
+/// The pages of a TIFF, `None` when they can't all be decoded, so that the image is opened like
+/// any other and only its first page kept, or reported by `open_image` when it can't be read.
+fn tiff_pages(path: &std::path::Path, bytes: &[u8], run: &RunArgs) -> Option<Vec<image::DynamicImage>> {
+    match run.timed_decode(|| decode_tiff_pages(bytes)) {
+        Ok(pages) => Some(pages),
+        // a first page that can't be decoded this way is left to `open_image` to report.
+        Err((0, _)) => None,
+        Err((decoded, reason)) => {
+            log!("Warning: can't read page {} of {}: {}, keeping only its first page.", decoded + 1, path.display(), reason);
+            None
+        }
+    }
+}
+
+/// Encode pages into a single multi-page TIFF.
+fn encode_tiff_pages(pages: &[image::DynamicImage]) -> Vec<u8> {
+    use tiff::encoder::colortype;
+
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    let mut encoder = tiff::encoder::TiffEncoder::new(&mut bytes).unwrap();
+    for page in pages {
+        let (width, height) = page.dimensions();
+        match page {
+            image::DynamicImage::ImageLuma8(buffer) => encoder.write_image::<colortype::Gray8>(width, height, buffer.as_raw()),
+            image::DynamicImage::ImageRgb8(buffer) => encoder.write_image::<colortype::RGB8>(width, height, buffer.as_raw()),
+            image::DynamicImage::ImageLuma16(buffer) => encoder.write_image::<colortype::Gray16>(width, height, buffer.as_raw()),
+            image::DynamicImage::ImageRgb16(buffer) => encoder.write_image::<colortype::RGB16>(width, height, buffer.as_raw()),
+            image::DynamicImage::ImageRgba16(buffer) => encoder.write_image::<colortype::RGBA16>(width, height, buffer.as_raw()),
+            page => encoder.write_image::<colortype::RGBA8>(width, height, page.to_rgba8().as_raw()),
+        }
+        .unwrap();
+    }
+    bytes.into_inner()
+}
+
+/// Path of a single page written out of a multi-page TIFF: `name_p{n}.ext`.
+fn page_path(target_path: &std::path::Path, page: usize) -> std::path::PathBuf {
+    let stem = target_path.file_stem().unwrap().to_string_lossy();
+    let extension = target_path.extension().unwrap().to_string_lossy();
+    target_path.with_file_name(format!("{}_p{}.{}", stem, page, extension))
+}
+
+/// Parse `--map` rules like `png=webp,bmp=png` into (source, output) format pairs.
+fn parse_format_map(value: &str) -> Vec<(image::ImageFormat, image::ImageFormat)> {
+    value
+        .split(',')
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| {
+            let (from, to) = rule.split_once('=').unwrap_or_else(|| panic!("Invalid map rule: {}", rule));
+            let from_format = image::ImageFormat::from_extension(from).unwrap_or_else(|| panic!("Unknown format in map rule: {}", rule));
+            match image::ImageFormat::from_extension(to) {
+                Some(to_format) if to_format.can_write() => (from_format, to_format),
+                _ => panic!("Unsupported output format in map rule: {}", rule),
+            }
+        })
+        .collect()
+}
+
+/// Target path with the extension of the format `--map` assigns to the source's format, if any.
+fn mapped_target_path(source_path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) -> std::path::PathBuf {
+    let source_format = image::ImageFormat::from_path(source_path).ok();
+    match run.format_map.iter().find(|(from, _)| Some(*from) == source_format) {
+        Some((_, to)) => target_path.with_extension(to.extensions_str()[0]),
+        None => target_path.to_path_buf(),
+    }
+}
+
+/// A reader for an image file, with its format taken from the extension or, with `--by-content`,
+/// from its first bytes.
+fn image_reader(path: &std::path::Path, run: &RunArgs) -> image::ImageResult<image::io::Reader<std::io::BufReader<std::fs::File>>> {
+    let reader = image::io::Reader::open(path)?;
+    match run.by_content {
+        true => Ok(reader.with_guessed_format()?),
+        false => Ok(reader),
+    }
+}
+
+/// The width and height of an image from its header, or from the `--dimensions-cache` while the
+/// file is unchanged.
+fn image_dimensions(path: &std::path::Path, run: &RunArgs) -> image::ImageResult<(u32, u32)> {
+    if let Some(dimensions) = run.dimensions_cache.borrow().as_ref().and_then(|cache| cache.get(path)) {
+        return Ok(dimensions);
+    }
+    let reader = image_reader(path, run)?;
+    let format = reader.format();
+    let dimensions = reader.into_dimensions()?;
+    if let Some(cache) = run.dimensions_cache.borrow_mut().as_mut() {
+        cache.record(path, dimensions, format);
+    }
+    Ok(dimensions)
+}
+
+/// Bytes per pixel assumed for the memory an image takes decoded, that of 8-bit RGBA.
+const DECODED_PIXEL_BYTES: u64 = 4;
+
+/// Warn about an image whose decoded size, estimated from its header dimensions, is over the
+/// `--mem-budget`. Images are decoded one at a time, so there are no jobs to hold back and the
+/// image is still processed.
+fn warn_over_budget(path: &std::path::Path, mem_budget: u64, run: &RunArgs) {
+    // the decoder reports images whose header can't be read.
+    let Ok((width, height)) = image_dimensions(path, run) else {
+        return;
+    };
+    let estimate = width as u64 * height as u64 * DECODED_PIXEL_BYTES;
+    if estimate > mem_budget {
+        log!("Warning: {} takes about {} decoded, more than the --mem-budget of {}.", path.display(), format_bytes(estimate), format_bytes(mem_budget));
+    }
 }
 
-#[derive(Copy, Clone)]
-struct SizeArgs {
-    width: u32,
-    height: u32,
-    scale: f32,
-}
-
-fn process_directory(source_path: std::path::PathBuf, size: SizeArgs, filter: image::imageops::FilterType, executor: fn(std::path::PathBuf, SizeArgs, image::imageops::FilterType)) {
-    // the source path is a directory. iterate all children. for images, perform resize.
-    // iterate all children.
-    for entry in WalkDir::new(source_path) {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_file() {
-            // check if path is an image.
-            if let Some(extension) = path.extension() {
-                if let Some(extension) = extension.to_str() {
-                    if extension == "png" || extension == "jpg" || extension == "jpeg" {
-                        executor(path.to_path_buf(), size, filter);
-                    }
+/// The numbers of the pages of a PDF, which are rendered once for the image being processed.
+/// PDFs without a page that has images are unreadable.
+#[cfg(feature = "pdf")]
+fn pdf_page_numbers(path: &std::path::Path, run: &RunArgs) -> Result<Vec<usize>, Outcome> {
+    if !run.pdf_pages.borrow().contains_key(path) {
+        let bytes = run.read(path)?;
+        let pages = run.timed_decode(|| pdf::pages(path, &bytes, run.pdf_dpi));
+        if pages.is_empty() {
+            log!("Error: can't read {}: no pages with images (only scanned PDFs are supported)", path.display());
+            return Err(Outcome::Unreadable);
+        }
+        run.pdf_pages.borrow_mut().insert(path.to_path_buf(), pages);
+    }
+    Ok(run.pdf_pages.borrow()[path].iter().map(|(number, _)| *number).collect())
+}
+
+/// Open an image for a task, reporting it instead of failing the run when it can't be decoded.
+///
+/// Files whose header reads fine but whose data doesn't decode count as truncated, all others as
+/// unreadable. With `--tolerant`, truncated JPEGs and PNGs are decoded as far as their data goes.
+fn open_image(path: &std::path::Path, run: &RunArgs) -> Result<image::DynamicImage, Outcome> {
+    // the page the task is run on, or the first one for tasks that don't split pages.
+    #[cfg(feature = "pdf")]
+    if is_pdf(path) {
+        let numbers = pdf_page_numbers(path, run)?;
+        let number = run.pdf_page.get().unwrap_or(numbers[0]);
+        if run.pdf_page.get().is_none() && numbers.len() > 1 {
+            log!("Warning: only the first of the {} pages of {} is used.", numbers.len(), path.display());
+        }
+        return match run.pdf_pages.borrow()[path].iter().find(|(page_number, _)| *page_number == number) {
+            Some((_, page)) => Ok(page.clone()),
+            None => {
+                log!("Error: can't read {}: it has no page {} with images", path.display(), number);
+                Err(Outcome::Unreadable)
+            }
+        };
+    }
+    if let Some(mem_budget) = run.mem_budget {
+        warn_over_budget(path, mem_budget, run);
+    }
+    let decoded = run.with_retry(path, is_transient, || run.timed_decode(|| image_reader(path, run).and_then(|reader| reader.decode())));
+    let error = match decoded {
+        Ok(image) => return Ok(normalize_color(path, image, run)),
+        Err(error) => error,
+    };
+    if image_reader(path, run).and_then(|reader| reader.into_dimensions()).is_err() {
+        log!("Error: can't read {}: {}", path.display(), error);
+        return Err(Outcome::Unreadable);
+    }
+    if run.tolerant {
+        if let Some(image) = run.timed_decode(|| decode_partial(path)) {
+            log!("Warning: {} is truncated, using the part that could be decoded.", path.display());
+            return Ok(normalize_color(path, image, run));
+        }
+    }
+    log!("Error: {} is truncated: {}", path.display(), error);
+    Err(Outcome::Truncated)
+}
+
+/// Whether a decoding error may go away when retried: those from the file system may, those
+/// from the data won't.
+fn is_transient(error: &image::ImageError) -> bool {
+    matches!(error, image::ImageError::IoError(error) if error.kind() != std::io::ErrorKind::UnexpectedEof)
+}
+
+/// Where a `--preflight` run plans to write the outputs of an image: its target, or one for every
+/// page of a PDF.
+#[cfg(feature = "pdf")]
+fn planned_outputs(path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) -> Vec<std::path::PathBuf> {
+    match is_pdf(path) {
+        true => pdf_page_numbers(path, run).unwrap_or_default().into_iter().map(|number| page_path(target_path, number)).collect(),
+        false => vec![target_path.to_path_buf()],
+    }
+}
+
+#[cfg(not(feature = "pdf"))]
+fn planned_outputs(_path: &std::path::Path, target_path: &std::path::Path, _run: &RunArgs) -> Vec<std::path::PathBuf> {
+    vec![target_path.to_path_buf()]
+}
+
+/// Check that an image's header can be read, for `--preflight`.
+fn preflight_image(path: &std::path::Path, run: &RunArgs) -> Outcome {
+    #[cfg(feature = "pdf")]
+    if is_pdf(path) {
+        return match pdf_page_numbers(path, run) {
+            Ok(numbers) => {
+                if run.verbose {
+                    println!("{} {} pages", path.display(), numbers.len());
                 }
+                Outcome::Inspected
+            }
+            Err(outcome) => outcome,
+        };
+    }
+    match run.with_retry(path, is_transient, || image_reader(path, run).and_then(|reader| reader.into_dimensions())) {
+        Ok((width, height)) => {
+            if run.verbose {
+                println!("{} {}x{}", path.display(), width, height);
             }
+            Outcome::Inspected
+        }
+        Err(error) => {
+            log!("Error: can't read {}: {}", path.display(), error);
+            Outcome::Unreadable
         }
     }
 }
 
-fn resize_by_scale(source_path: std::path::PathBuf, scale: f32, filter: image::imageops::FilterType) {
-    let target_path = source_path.clone();
-    // open image.
-    let image = image::open(source_path).unwrap();
-    // get image dimensions.
-    let (width, height) = image.dimensions();
-    // calculate new dimensions.
-    let new_width = (width as f32 * scale) as u32;
-    let new_height = (height as f32 * scale) as u32;
-    // resize image.
-    let resized_image = image::imageops::resize(&image, new_width, new_height, filter);
-    // save resized image.
-    resized_image.save(target_path).unwrap();
-}
-
-fn resize_by_size(source_path: std::path::PathBuf, size: (u32, u32), filter: image::imageops::FilterType) {
-    let target_path = source_path.clone();
-    // open image.
-    let image = image::open(source_path).unwrap();
-    // resize image.
-    let resized_image = image::imageops::resize(&image, size.0, size.1, filter);
-    // save resized image.
-    resized_image.save(target_path).unwrap();
+/// The ICC profile embedded in an image, for the formats that can carry one.
+fn embedded_icc_profile(path: &std::path::Path, run: &RunArgs) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+    let format = image_reader(path, run).ok()?.format()?;
+    let file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+    match format {
+        image::ImageFormat::Png => image::codecs::png::PngDecoder::new(file).ok()?.icc_profile(),
+        image::ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(file).ok()?.icc_profile(),
+        image::ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(file).ok()?.icc_profile(),
+        image::ImageFormat::Tiff => image::codecs::tiff::TiffDecoder::new(file).ok()?.icc_profile(),
+        _ => None,
+    }
 }
 
-/// Resize images in a directory.
-///
-///  # Supported Options
+/// Convert a decoded image to the `--color-profile` color space using its embedded ICC profile.
 ///
-///  ## size (Required)
-///  - {width}x{height}
-///  - {percentage}%
+/// Images without a profile are assumed to be in sRGB already and returned as they are.
+fn normalize_color(path: &std::path::Path, image: image::DynamicImage, run: &RunArgs) -> image::DynamicImage {
+    if run.color_profile != Some(ColorProfile::Srgb) {
+        return image;
+    }
+    match embedded_icc_profile(path, run) {
+        Some(icc_profile) => run.timed_decode(|| icc_to_srgb(path, image, &icc_profile)),
+        None => {
+            if run.verbose {
+                println!("Notice: {} has no ICC profile, keeping its colors.", path.display());
+            }
+            image
+        }
+    }
+}
+
+/// Apply an ICC profile to convert an image to sRGB. Images are converted to 8 bits per channel;
+/// gray images become RGB unless their profile is a gray one.
+#[cfg(feature = "color-management")]
+fn icc_to_srgb(path: &std::path::Path, image: image::DynamicImage, icc_profile: &[u8]) -> image::DynamicImage {
+    use qcms::DataType;
+
+    let source = match qcms::Profile::new_from_slice(icc_profile, false) {
+        Some(source) => source,
+        None => {
+            log!("Warning: {} has an invalid ICC profile, keeping its colors.", path.display());
+            return image;
+        }
+    };
+    let srgb = qcms::Profile::new_sRGB();
+    let alpha = image.color().has_alpha();
+    let gray = matches!(image, image::DynamicImage::ImageLuma8(_) | image::DynamicImage::ImageLumaA8(_) | image::DynamicImage::ImageLuma16(_) | image::DynamicImage::ImageLumaA16(_));
+    let (width, height) = (image.width(), image.height());
+
+    // a gray profile converts gray data to RGB, other profiles convert RGB data in place.
+    let (source_type, target_type) = match alpha {
+        true => (DataType::GrayA8, DataType::RGBA8),
+        false => (DataType::Gray8, DataType::RGB8),
+    };
+    let gray_transform = match gray {
+        true => qcms::Transform::new_to(&source, &srgb, source_type, target_type, qcms::Intent::Perceptual),
+        false => None,
+    };
+    if let Some(transform) = gray_transform {
+        let samples = match alpha {
+            true => image.to_luma_alpha8().into_raw(),
+            false => image.to_luma8().into_raw(),
+        };
+        let mut converted = vec![0; width as usize * height as usize * target_type.bytes_per_pixel()];
+        transform.convert(&samples, &mut converted);
+        return match alpha {
+            true => image::DynamicImage::ImageRgba8(image::ImageBuffer::from_raw(width, height, converted).unwrap()),
+            false => image::DynamicImage::ImageRgb8(image::ImageBuffer::from_raw(width, height, converted).unwrap()),
+        };
+    }
+
+    let data_type = if alpha { DataType::RGBA8 } else { DataType::RGB8 };
+    let transform = match qcms::Transform::new(&source, &srgb, data_type, qcms::Intent::Perceptual) {
+        Some(transform) => transform,
+        None => {
+            log!("Warning: {} has an ICC profile that can't be applied, keeping its colors.", path.display());
+            return image;
+        }
+    };
+    if alpha {
+        let mut buffer = image.to_rgba8();
+        transform.apply(&mut buffer);
+        image::DynamicImage::ImageRgba8(buffer)
+    } else {
+        let mut buffer = image.to_rgb8();
+        transform.apply(&mut buffer);
+        image::DynamicImage::ImageRgb8(buffer)
+    }
+}
+
+#[cfg(not(feature = "color-management"))]
+fn icc_to_srgb(_path: &std::path::Path, image: image::DynamicImage, _icc_profile: &[u8]) -> image::DynamicImage {
+    image
+}
+
+/// Decode what's there of a truncated JPEG or PNG.
 ///
-///  ## filter
-///  - nearest
-///  - linear
-///  - cubic (default)
-///  - gaussian
-///  - lanczos3
+/// A JPEG gets an end-of-image marker appended, so the decoder fills the missing blocks instead of
+/// erroring. A (non-interlaced) PNG is read row by row until the data runs out, leaving the rest of
+/// the image blank.
+fn decode_partial(path: &std::path::Path) -> Option<image::DynamicImage> {
+    let mut bytes = std::fs::read(path).ok()?;
+    match image::ImageFormat::from_path(path).ok()? {
+        image::ImageFormat::Jpeg => {
+            bytes.extend_from_slice(&[0xFF, 0xD9]);
+            image::load_from_memory_with_format(&bytes, image::ImageFormat::Jpeg).ok()
+        }
+        image::ImageFormat::Png => {
+            let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+            decoder.set_transformations(png::Transformations::normalize_to_color8());
+            let mut reader = decoder.read_info().ok()?;
+            if reader.info().interlaced {
+                return None;
+            }
+            let (width, height) = (reader.info().width, reader.info().height);
+            let (color_type, _) = reader.output_color_type();
+            let row_length = width as usize * color_type.samples();
+            let mut data = vec![0; row_length * height as usize];
+            let mut offset = 0;
+            while let Ok(Some(row)) = reader.next_row() {
+                data[offset..offset + row_length].copy_from_slice(row.data());
+                offset += row_length;
+            }
+            match color_type {
+                png::ColorType::Grayscale => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageLuma8),
+                png::ColorType::GrayscaleAlpha => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageLumaA8),
+                png::ColorType::Rgb => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageRgb8),
+                png::ColorType::Rgba => image::ImageBuffer::from_raw(width, height, data).map(image::DynamicImage::ImageRgba8),
+                png::ColorType::Indexed => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Open an image, apply a transform and save the result.
 ///
-///  @param source_path Path to source directory.
-///  @param options Options for resizing.
-fn resize(source_path: std::path::PathBuf, options: std::collections::HashMap<&str, &str>) {
-    // initialize imageops filter type.
-    let mut filter = image::imageops::FilterType::CatmullRom;
-    // check options dictionary if filter is specified.
-    if options.contains_key("filter") {
-        // get filter value.
-        let filter_value = options.get("filter").unwrap();
-        // convert filter value to string.
-        let string_value = filter_value.to_string();
-
-        // check if filter value is valid.
-        match string_value.as_str() {
-            "default" => filter = image::imageops::FilterType::CatmullRom,
-            "nearest" => filter = image::imageops::FilterType::Nearest,
-            "linear" => filter = image::imageops::FilterType::Triangle,
-            "cubic" => filter = image::imageops::FilterType::CatmullRom,
-            "gaussian" => filter = image::imageops::FilterType::Gaussian,
-            "lanczos3" => filter = image::imageops::FilterType::Lanczos3,
-            _ => filter = image::imageops::FilterType::CatmullRom,
-        }
-    }
-
-    // check size option.
-    if !options.contains_key("size") {
-        panic!("Missing required option: size");
-    }
-
-    // get size value.
-    let size_value = options.get("size").unwrap();
-    // split size value by x.
-    let size: Vec<&str> = size_value.split("x").collect();
-    // check if size is valid.
-    if size.len() != 2 {
-        // check if size is specified in percentage.
-        if size_value.ends_with("%")
-        {
-            // strip percentage sign.
-            let size_value = size_value.strip_suffix("%").unwrap();
-            // parse size value to float.
-            let percentage: f32 = size_value.parse().unwrap();
-            // turn percentage into scale (0.0 - 1.0)
-            let scale = percentage / 100.0;
-            // Create new SizeArgs struct
-            let size = SizeArgs {
-                width: 0,
-                height: 0,
-                scale,
-            };
+/// Multi-page TIFFs have the transform applied to every page and are written back either as one
+/// multi-page file or as one file per page, depending on `--tiff-pages`. Animated PNGs saved as
+/// PNG and animated WebPs saved as WebP have it applied to every frame unless `--single-frame` is
+/// given.
+fn transform_image(source_path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs, save: &SaveArgs, transform: impl Fn(&image::DynamicImage) -> image::DynamicImage) -> Outcome {
+    let target_path = &mapped_target_path(source_path, target_path, run);
+    // images of formats with pages or frames are read whole, others are decoded from the file.
+    let is_animatable = (is_png(source_path) && is_png(target_path) && !run.single_frame) || is_webp(source_path);
+    let bytes = match is_tiff(source_path) || is_animatable {
+        true => match run.read(source_path) {
+            Ok(bytes) => bytes,
+            Err(outcome) => return outcome,
+        },
+        false => Vec::new(),
+    };
+    if is_tiff(source_path) {
+        let pages = tiff_pages(source_path, &bytes, run).unwrap_or_default();
+        if pages.len() > 1 {
+            let pages: Vec<image::DynamicImage> = pages.iter().map(&transform).collect();
+            match run.tiff_pages {
+                TiffPages::Combine => write_output(source_path, target_path, run.timed_encode(|| encode_tiff_pages(&pages)), run),
+                TiffPages::Split => {
+                    for (index, page) in pages.iter().enumerate() {
+                        save_image(page, source_path, &page_path(target_path, index + 1), run, save);
+                    }
+                }
+            }
+            return Outcome::Processed;
+        }
+    }
 
-            process_directory(source_path, size, filter, |path, size, filter| {
-                resize_by_scale(path, size.scale, filter);
-            });
-            return;
+    // animated PNGs written as PNG keep every frame.
+    if is_png(source_path) && is_png(target_path) && !run.single_frame {
+        if let Some((frames, plays)) = run.timed_decode(|| decode_apng(&bytes)) {
+            let frames: Vec<(image::DynamicImage, u16)> = frames.iter().map(|(frame, delay)| (transform(frame), *delay)).collect();
+            write_output(source_path, target_path, run.timed_encode(|| encode_apng(&frames, plays)), run);
+            return Outcome::Processed;
+        }
+    }
+
+    // so do animated WebPs written as WebP, others only keep the first frame.
+    if is_webp(source_path) {
+        if let Some((frames, plays)) = run.timed_decode(|| decode_animated_webp(&bytes)) {
+            if is_webp(target_path) && !run.single_frame {
+                let frames: Vec<(image::DynamicImage, u16)> = frames.iter().map(|(frame, delay)| (transform(frame), *delay)).collect();
+                write_output(source_path, target_path, run.timed_encode(|| encode_animated_webp(&frames, plays, save.quality)), run);
+                return Outcome::Processed;
+            }
+            if run.verbose {
+                println!("Keeping only the first of {} frames of {}.", frames.len(), source_path.display());
+            }
         }
     }
 
-    // parse size value to u32.
-    let width: u32 = size[0].parse().unwrap();
-    let height: u32 = size[1].parse().unwrap();
-    // Create new SizeArgs struct
-    let size = SizeArgs {
-        width,
-        height,
-        scale: 0.0,
+    // open image.
+    let image = match open_image(source_path, run) {
+        Ok(image) => image,
+        Err(outcome) => return outcome,
     };
+    // save transformed image.
+    save_image(&transform(&image), source_path, target_path, run, save);
+    Outcome::Processed
+}
 
-    process_directory(source_path.clone(), size, filter, |path, size, filter| {
-        resize_by_size(path, (size.width, size.height), filter);
+/// Encode an image into memory in the given format.
+///
+/// JPEGs are written with the given quality and PNGs with the best compression, everything else
+/// uses the format's default encoder.
+fn encode_image(image: &image::DynamicImage, format: image::ImageFormat, quality: u8) -> Vec<u8> {
+    let narrowed = narrowed(image, format);
+    let image = narrowed.as_ref().unwrap_or(image);
+    let mut bytes = Vec::new();
+    match format {
+        image::ImageFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality);
+            image.write_with_encoder(encoder).unwrap();
+        }
+        image::ImageFormat::Png => {
+            return encode_png(image, image::codecs::png::CompressionType::Best, image::codecs::png::FilterType::Adaptive);
+        }
+        _ => image.write_to(&mut std::io::Cursor::new(&mut bytes), format).unwrap(),
+    }
+    bytes
+}
+
+/// Encode an image as PNG with the given compression and filter.
+fn encode_png(image: &image::DynamicImage, compression: image::codecs::png::CompressionType, filter: image::codecs::png::FilterType) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let encoder = image::codecs::png::PngEncoder::new_with_quality(&mut bytes, compression, filter);
+    image.write_with_encoder(encoder).unwrap();
+    bytes
+}
+
+/// Whether `--output-quality-auto` applies to a format.
+fn is_lossy(format: image::ImageFormat) -> bool {
+    format == image::ImageFormat::Jpeg || format == image::ImageFormat::WebP
+}
+
+/// Encode a JPEG or WebP at the lowest quality that reaches the `--output-quality-auto` target,
+/// adding the quality to the report.
+fn encode_auto_quality(image: &image::DynamicImage, format: image::ImageFormat, run: &RunArgs) -> Vec<u8> {
+    let (quality, bytes) = quality::lowest_quality(image, run.quality_target.unwrap(), |quality| match format {
+        image::ImageFormat::WebP => encode_webp(image, Some(quality)),
+        _ => encode_image(image, format, quality),
     });
+    run.report_field("quality", quality);
+    bytes
+}
+
+/// Encode an image as WebP, lossy with the given quality or at the encoder's default quality.
+fn encode_webp(image: &image::DynamicImage, quality: Option<u8>) -> Vec<u8> {
+    let quality = quality.map(image::codecs::webp::WebPQuality::lossy).unwrap_or_default();
+    let mut bytes = Vec::new();
+    let encoder = image::codecs::webp::WebPEncoder::new_with_quality(&mut bytes, quality);
+    // the encoder only takes 8-bit RGB(A).
+    if image.color().has_alpha() {
+        image::DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder).unwrap();
+    } else {
+        image::DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder).unwrap();
+    }
+    bytes
+}
+
+/// A fixed palette that pixels are mapped onto, with an optional transparent entry.
+struct Palette {
+    colors: Vec<[u8; 3]>,
+    transparent: Option<usize>,
+    cache: std::cell::RefCell<std::collections::HashMap<[u8; 3], usize>>,
+}
+
+impl Palette {
+    fn new(colors: Vec<[u8; 3]>, transparent: Option<usize>) -> Palette {
+        Palette {
+            colors,
+            transparent,
+            cache: Default::default(),
+        }
+    }
+
+    /// Index of the closest palette color, by weighted RGB distance.
+    fn nearest(&self, color: [u8; 3]) -> usize {
+        if let Some(&index) = self.cache.borrow().get(&color) {
+            return index;
+        }
+        let distance = |candidate: &[u8; 3]| {
+            let d = |channel: usize| (color[channel] as i32 - candidate[channel] as i32).pow(2);
+            2 * d(0) + 4 * d(1) + 3 * d(2)
+        };
+        let index = (0..self.colors.len())
+            .filter(|&index| Some(index) != self.transparent)
+            .min_by_key(|&index| distance(&self.colors[index]))
+            .unwrap();
+        self.cache.borrow_mut().insert(color, index);
+        index
+    }
+}
+
+impl image::imageops::ColorMap for Palette {
+    type Color = image::Rgba<u8>;
+
+    fn index_of(&self, color: &image::Rgba<u8>) -> usize {
+        match self.transparent {
+            Some(transparent) if color[3] < 128 => transparent,
+            _ => self.nearest([color[0], color[1], color[2]]),
+        }
+    }
+
+    fn map_color(&self, color: &mut image::Rgba<u8>) {
+        let index = self.index_of(color);
+        *color = if Some(index) == self.transparent {
+            image::Rgba([0, 0, 0, 0])
+        } else {
+            let [r, g, b] = self.colors[index];
+            image::Rgba([r, g, b, 255])
+        };
+    }
+}
+
+/// Pick `colors` representative colors with the median cut algorithm.
+///
+/// The box of colors with the widest channel range is repeatedly split at the median of that
+/// channel, and each final box contributes its average color.
+fn median_cut(pixels: &[[u8; 3]], colors: usize) -> Vec<[u8; 3]> {
+    if pixels.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+    let range = |pixels: &[[u8; 3]], channel: usize| {
+        let (min, max) = pixels.iter().fold((255, 0), |(min, max), p| (p[channel].min(min), p[channel].max(max)));
+        max.saturating_sub(min)
+    };
+
+    let mut boxes = vec![pixels.to_vec()];
+    while boxes.len() < colors {
+        // split the box with the widest range along that channel.
+        let candidate = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, pixels)| pixels.len() > 1)
+            .map(|(index, pixels)| {
+                let (channel, width) = (0..3).map(|channel| (channel, range(pixels, channel))).max_by_key(|c| c.1).unwrap();
+                (index, channel, width)
+            })
+            .max_by_key(|c| c.2);
+        let (index, channel) = match candidate {
+            Some((index, channel, width)) if width > 0 => (index, channel),
+            _ => break,
+        };
+        let mut pixels = boxes.swap_remove(index);
+        pixels.sort_unstable_by_key(|p| p[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|pixels| {
+            let mut sum = [0u64; 3];
+            for p in pixels {
+                for channel in 0..3 {
+                    sum[channel] += p[channel] as u64;
+                }
+            }
+            let count = pixels.len() as u64;
+            [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8]
+        })
+        .collect()
+}
+
+/// Encode an image as a GIF with a median cut palette of at most `colors` entries.
+///
+/// Pixels that are mostly transparent get a dedicated transparent palette entry. With `dither` the
+/// quantization error is spread using Floyd-Steinberg dithering.
+fn encode_gif(image: &image::DynamicImage, colors: usize, dither: bool) -> Vec<u8> {
+    let mut rgba = image.to_rgba8();
+    let has_transparency = rgba.pixels().any(|p| p[3] < 128);
+
+    // sample at most about a million pixels to build the palette.
+    let step = (rgba.len() / 4 / 1_000_000).max(1);
+    let pixels: Vec<[u8; 3]> = rgba.pixels().step_by(step).filter(|p| p[3] >= 128).map(|p| [p[0], p[1], p[2]]).collect();
+    let mut palette_colors = median_cut(&pixels, if has_transparency { colors - 1 } else { colors });
+    let transparent = if has_transparency {
+        palette_colors.push([0, 0, 0]);
+        Some(palette_colors.len() - 1)
+    } else {
+        None
+    };
+    let palette = Palette::new(palette_colors, transparent);
+
+    if dither && rgba.width() > 1 && rgba.height() > 1 {
+        image::imageops::dither(&mut rgba, &palette);
+    }
+    let indices = image::imageops::index_colors(&rgba, &palette);
+
+    let mut bytes = Vec::new();
+    {
+        let flat_palette: Vec<u8> = palette.colors.iter().flatten().copied().collect();
+        let mut encoder = gif::Encoder::new(&mut bytes, rgba.width() as u16, rgba.height() as u16, &flat_palette).unwrap();
+        let frame = gif::Frame {
+            width: rgba.width() as u16,
+            height: rgba.height() as u16,
+            buffer: std::borrow::Cow::Borrowed(indices.as_raw()),
+            transparent: transparent.map(|index| index as u8),
+            ..Default::default()
+        };
+        encoder.write_frame(&frame).unwrap();
+    }
+    bytes
+}
+
+/// Stop a `--safe` run before a source is overwritten or removed.
+fn refuse_unsafe(source_path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs) {
+    if run.safe && canonical_path(target_path) == canonical_path(source_path) {
+        panic!("Refusing to replace {} in place (--safe), give a --dest or pass --in-place", source_path.display());
+    }
+}
+
+/// Remove an original that was replaced by a differently named output, to the trash with
+/// `--trash` or permanently otherwise. One that can't be removed is kept, and its image failed.
+fn remove_original(source_path: &std::path::Path, run: &RunArgs) {
+    refuse_unsafe(source_path, source_path, run);
+    let removed = match run.trash {
+        true => trash::delete(source_path).map_err(|error| error.to_string()),
+        false => std::fs::remove_file(source_path).map_err(|error| error.to_string()),
+    };
+    if let Err(error) = removed {
+        log!("Warning: can't remove the original {}, keeping it: {}", source_path.display(), error);
+        run.unremoved.set(true);
+    }
+}
+
+/// Parse `{width}x{height}` with both sides above zero.
+fn parse_dimensions(value: &str) -> Option<(u32, u32)> {
+    match value.split_once('x').map(|(width, height)| (width.parse(), height.parse())) {
+        Some((Ok(width), Ok(height))) if width > 0 && height > 0 => Some((width, height)),
+        _ => None,
+    }
+}
+
+/// Split an options string on commas and newlines outside of double quotes.
+fn split_options(options: &str) -> Vec<&str> {
+    let mut entries = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, character) in options.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ',' | '\n' if !quoted => {
+                entries.push(&options[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        panic!("Unterminated quote in options: {}", options);
+    }
+    entries.push(&options[start..]);
+    entries
+}
+
+/// An option value without the double quotes around it, so `text="Hello, World"` keeps its comma.
+fn unquote(value: &str) -> &str {
+    match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        Some(value) => value,
+        None => value,
+    }
+}
+
+/// Parse task options, `key=value` entries separated by commas (or newlines, from an options file).
+fn parse_options(options: &str) -> std::collections::HashMap<&str, &str> {
+    // trim whitespace and skip empty entries.
+    let options: Vec<&str> = split_options(options).into_iter().map(str::trim).filter(|option| !option.is_empty()).collect();
+    // split each option on its first equal sign, the value may contain more of them
+    let mut options_map = std::collections::HashMap::new();
+    for option in options {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key.trim(), unquote(value.trim())),
+            None => panic!("Invalid option: {}", option),
+        };
+        // if key or value is empty, error out
+        if key.is_empty() || value.is_empty() {
+            panic!("Invalid option: {}", option);
+        }
+        options_map.insert(key, value);
+    }
+    options_map
+}
+
+/// Keys of the given options that are not in the specs, sorted.
+fn unknown_options<'a>(specs: &[&tasks::OptionSpec], options: &std::collections::HashMap<&'a str, &str>) -> Vec<&'a str> {
+    let mut unknown: Vec<&str> = options.keys().copied().filter(|key| !specs.iter().any(|spec| spec.key == *key)).collect();
+    unknown.sort();
+    unknown
+}
+
+/// Print the options a task accepts and check the given ones against them.
+fn explain(name: &str, task: &dyn tasks::Task, options: &std::collections::HashMap<&str, &str>) {
+    let specs = task.options();
+    println!("Options for {}:", name);
+    let width = specs.iter().map(|spec| spec.key.len()).max().unwrap_or(0);
+    for spec in &specs {
+        println!("  {:width$}  {} (default: {})", spec.key, spec.values, spec.default, width = width);
+    }
+
+    let unknown = unknown_options(&specs, options);
+    if unknown.is_empty() {
+        println!("All given options are recognized.");
+    } else {
+        for key in unknown {
+            println!("Unrecognized option: {}", key);
+        }
+    }
 }
 
 fn main() {
     // Parse command line arguments
-    let cli = CLI::parse();
+    let cli = Cli::parse();
+    if let Some(log_path) = &cli.log_file {
+        log_file::open(log_path, cli.log_max_size.as_deref().map(parse_bytes));
+        log_file::write(&format!("Started: {}", std::env::args().collect::<Vec<_>>().join(" ")));
+    }
 
-    // Get source path
-    let source_path = cli.source_path;
     // Get task name
-    let task = cli.task;
+    let task_name = cli.task.clone();
+    let task = match tasks::find(&task_name, &cli) {
+        Some(task) => task,
+        None => panic!("Unknown task: {} (available: {})", task_name, tasks::names().join(", ")),
+    };
+    // Get source path
+    let mut source_path = cli.source_path;
     // get task options
-    let options = cli.options;
+    let options = cli
+        .options_file
+        .map(|path| std::fs::read_to_string(&path).unwrap_or_else(|error| panic!("Can't read options file {}: {}", path.display(), error)))
+        .or(cli.options)
+        .unwrap_or_else(|| if task_name == "resize" { "size=128x128".to_string() } else { String::new() });
+    // get output settings
+    let work_path = work_path();
+    let zip_dest = cli.dest_path.clone().filter(|dest_path| archive::is_zip(dest_path));
+    let dest_path = match zip_dest {
+        Some(_) => Some(work_path.join("dest")),
+        None => cli.dest_path.clone(),
+    };
+    let dest_path = match &cli.output_subdir {
+        Some(subdir) if subdir.is_absolute() || subdir.components().any(|c| c == std::path::Component::ParentDir) => {
+            panic!("Invalid output subdir (expected a relative path inside the dest): {}", subdir.display())
+        }
+        Some(subdir) => dest_path.map(|dest_path| dest_path.join(subdir)),
+        None => dest_path,
+    };
+    let run = RunArgs {
+        dest_path,
+        safe: cli.safe && !cli.in_place,
+        flatten: cli.flatten,
+        tiff_pages: cli.tiff_pages,
+        #[cfg(feature = "pdf")]
+        pdf_dpi: cli.pdf_dpi.inspect(|dpi| {
+            if !(*dpi > 0.0 && dpi.is_finite()) {
+                panic!("Invalid PDF DPI: {}", dpi);
+            }
+        }),
+        strip_gps: cli.strip_gps,
+        reorients: task.reorients(),
+        splits_pages: task.splits_pages(),
+        trash: cli.trash,
+        output_dirs: output_dirs(&source_path, cli.dest_path.iter().chain(&cli.exclude_output_dirs)),
+        exclude_dirs: cli
+            .exclude_dirs
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern).unwrap_or_else(|_| panic!("Invalid exclude-dir pattern: {}", pattern)))
+            .collect(),
+        ignore_rules: ignore_file::IgnoreRules::new(cli.ignore_file.as_deref()),
+        format_map: cli.format_map.as_deref().map(parse_format_map).unwrap_or_default(),
+        watch: cli.watch,
+        files: cli.files_from.as_deref().map(read_file_list),
+        orientation: cli.orientation,
+        verbose: cli.verbose,
+        tolerant: cli.tolerant,
+        timings: cli.timings,
+        by_content: cli.by_content,
+        color_profile: cli.color_profile,
+        report: cli.report_path.is_some(),
+        output_map: cli.output_map_path.is_some(),
+        report_base: cli.report_base.as_deref().map(canonical_path),
+        normalize_ext: cli.normalize_ext,
+        single_frame: cli.single_frame,
+        quality_target: cli.quality_target.as_deref().map(quality::parse_target),
+        retries: cli.retries,
+        retry_delay: std::time::Duration::from_millis(cli.retry_delay),
+        deadline: cli.max_runtime.as_deref().map(|runtime| std::time::Instant::now() + parse_runtime(runtime)),
+        run_log: cli.run_log.clone(),
+        link_unchanged: cli.link_unchanged,
+        srgb_chunk: !cli.no_srgb_chunk,
+        preserve_mode: cli.preserve_mode,
+        preserve_owner: cli.preserve_owner,
+        verify: cli.verify,
+        baseline: cli.baseline.clone().map(|baseline| (baseline, quality::parse_threshold(&cli.baseline_threshold))),
+        mem_budget: cli.mem_budget.as_deref().map(parse_bytes),
+        name_template: cli.name_template.clone(),
+        include_exts: cli.include_exts.clone(),
+        exclude_exts: cli.exclude_exts.clone(),
+        copy_others: cli.copy_others,
+        on_conflict: cli.on_conflict,
+        progress_json: cli.progress_json,
+        deterministic: cli.deterministic,
+        sample: cli.sample.inspect(|sample| {
+            if *sample == 0 {
+                panic!("Invalid sample: 0");
+            }
+        }),
+        done: match (&cli.run_log, cli.resume) {
+            (Some(run_log), true) if run_log.exists() => std::fs::read_to_string(run_log).unwrap().lines().map(std::path::PathBuf::from).collect(),
+            _ => Default::default(),
+        },
+        ..Default::default()
+    };
+
+    if cfg!(not(feature = "color-management")) && run.color_profile.is_some() {
+        panic!("--color-profile needs rsimg built with the color-management feature");
+    }
+    if cfg!(not(feature = "pdf")) && cli.pdf_dpi.is_some() {
+        panic!("--pdf-dpi needs rsimg built with the pdf feature");
+    }
+
+    let options_map = parse_options(&options);
+
+    // only describe the task when asked to.
+    if cli.explain {
+        explain(&task_name, task.as_ref(), &options_map);
+        return;
+    }
+
+    // options a task doesn't read are most likely typos, make them visible.
+    for key in unknown_options(&task.options(), &options_map) {
+        if cli.strict {
+            panic!("Unrecognized option for {}: {}", task_name, key);
+        }
+        log!("Warning: unrecognized option for {}: {} (ignored)", task_name, key);
+    }
+
+    if archive::is_zip(&source_path) && source_path.is_file() {
+        if run.dest_path.is_none() {
+            panic!("A zip source needs a --dest directory or zip archive to write to");
+        }
+        let extract_path = work_path.join("source");
+        archive::extract(&source_path, &extract_path, |path| run.by_content || is_image(path) || path.file_name() == Some(std::ffi::OsStr::new(CONFIG_FILE)) || path.file_name() == Some(std::ffi::OsStr::new(ignore_file::IGNORE_FILE)));
+        source_path = extract_path;
+    }
 
     // check if source path is a directory.
     if !source_path.is_dir() {
         panic!("Source path is not a directory: {}", source_path.to_str().unwrap());
     }
 
-    // parse options
-    // split options by comma
-    let options: Vec<&str> = options.split(",").collect();
-    // split each option by equal sign
-    let mut options_map = std::collections::HashMap::new();
-    for option in options {
-        let option: Vec<&str> = option.split("=").collect();
-        if option.len() != 2 {
-            panic!("Invalid option: {}", option.join("="));
+    if let Some(cache_path) = &cli.dimensions_cache_path {
+        run.dimensions_cache.replace(Some(dimensions_cache::DimensionsCache::load(cache_path)));
+    }
+    let save_dimensions_cache = |run: &RunArgs| {
+        if let (Some(cache_path), Some(cache)) = (&cli.dimensions_cache_path, run.dimensions_cache.borrow().as_ref()) {
+            cache.save(cache_path);
+        }
+    };
+
+    // validate the batch without writing or logging anything.
+    if cli.preflight {
+        let preflight_run = RunArgs { preflight: true, watch: false, run_log: None, splits_pages: false, ..run.clone() };
+        let summary = process_directory(source_path, &preflight_run, |path, target_path| {
+            let outcome = preflight_image(&path, &preflight_run);
+            if preflight_run.output_map && outcome == Outcome::Inspected {
+                preflight_run.outputs.borrow_mut().extend(planned_outputs(&path, &target_path, &preflight_run));
+            }
+            outcome
+        });
+        save_dimensions_cache(&preflight_run);
+        if let Some(output_map_path) = &cli.output_map_path {
+            let output_map = serde_json::json!({ "planned": true, "files": summary.outputs });
+            std::fs::write(output_map_path, serde_json::to_string_pretty(&output_map).unwrap()).unwrap();
+        }
+        if work_path.exists() {
+            std::fs::remove_dir_all(&work_path).unwrap();
+        }
+        let failed = summary.files() - summary.inspected;
+        println!("Preflight: {} images readable, {} failed.", summary.inspected, failed);
+        if failed > 0 {
+            std::process::exit(1);
         }
-        // if option is empty, error out
-        if option[0].is_empty() {
-            panic!("Invalid option: {}", option.join("="));
+        return;
+    }
+
+    if cli.count {
+        let count_run = RunArgs { dest_path: None, watch: false, run_log: None, ..run.clone() };
+        let summary = process_directory(source_path, &count_run, |_, _| Outcome::Inspected);
+        save_dimensions_cache(&count_run);
+        if work_path.exists() {
+            std::fs::remove_dir_all(&work_path).unwrap();
+        }
+        println!("{} images match ({}).", summary.files(), format_bytes(summary.source_bytes));
+        return;
+    }
+
+    // a run that doesn't resume starts the log over.
+    if let (Some(run_log), false) = (&run.run_log, cli.resume) {
+        std::fs::write(run_log, "").unwrap();
+    }
+    if let Some(manifest_path) = &cli.manifest_path {
+        run.manifest.replace(Some(manifest::Manifest::load(manifest_path)));
+    }
+    // images only count as unchanged for runs of the same task with the same options.
+    let set_manifest_params = |options: &std::collections::HashMap<&str, &str>| {
+        if let Some(manifest) = run.manifest.borrow_mut().as_mut() {
+            let mut options: Vec<String> = options.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+            options.sort();
+            manifest.set_params(format!("{} {}", task_name, options.join(",")));
+        }
+    };
+
+    // directories with a .rsimg config are processed in their own pass, with the options of the
+    // configs from the source down to them layered over the command line ones.
+    let config_dirs = config_dirs(&source_path, &run);
+    let summary = if config_dirs.is_empty() {
+        set_manifest_params(&options_map);
+        task.run(source_path.clone(), &run, options_map)
+    } else {
+        if run.watch {
+            panic!("--watch doesn't support {} configs", CONFIG_FILE);
         }
-        // if option is empty, error out
-        if option[1].is_empty() {
-            panic!("Invalid option: {}", option.join("="));
+        let mut scopes = vec![source_path.clone()];
+        scopes.extend(config_dirs.into_iter().filter(|directory| *directory != source_path));
+        let mut summary = Summary::default();
+        for scope in scopes {
+            let configs: Vec<std::path::PathBuf> = scope.ancestors().take_while(|directory| directory.starts_with(&source_path)).map(|directory| directory.join(CONFIG_FILE)).filter(|path| path.is_file()).collect();
+            let overrides: Vec<(std::path::PathBuf, Vec<(String, String)>)> = configs.into_iter().rev().map(|path| (path.clone(), read_config(&path, &task_name))).collect();
+            let mut scoped_options = options_map.clone();
+            for (path, options) in &overrides {
+                for (key, value) in options {
+                    if !task.options().iter().any(|spec| spec.key == key) {
+                        if cli.strict {
+                            panic!("Unrecognized option for {} in {}: {}", task_name, path.display(), key);
+                        }
+                        log!("Warning: unrecognized option for {} in {}: {} (ignored)", task_name, path.display(), key);
+                    }
+                    scoped_options.insert(key, value);
+                }
+            }
+            run.scope.replace(Some(scope));
+
+            // defaults by output format, of the configs from the source down to the scope.
+            let mut format_defaults: Vec<(image::ImageFormat, Vec<(String, String)>)> = Vec::new();
+            for (path, _) in &overrides {
+                for (format, defaults) in read_format_defaults(path) {
+                    match format_defaults.iter_mut().find(|(known, _)| *known == format) {
+                        Some((_, known)) => known.extend(defaults),
+                        None => format_defaults.push((format, defaults)),
+                    }
+                }
+            }
+            let with_defaults = |format: image::ImageFormat| {
+                let mut options = scoped_options.clone();
+                for (key, value) in format_defaults.iter().filter(|(known, _)| *known == format).flat_map(|(_, defaults)| defaults) {
+                    if task.options().iter().any(|spec| spec.key == key) {
+                        options.entry(key).or_insert(value);
+                    }
+                }
+                options
+            };
+            // a task that picks the output format gets the defaults of that format, others have
+            // a pass per format the images are written in.
+            let mut passes = Vec::new();
+            match scoped_options.get("format").and_then(image::ImageFormat::from_extension) {
+                Some(format) => passes.push((None, with_defaults(format))),
+                None if format_defaults.is_empty() => passes.push((None, scoped_options.clone())),
+                None => {
+                    passes.extend(format_defaults.iter().map(|(format, _)| (Some(FormatPass::Only(*format)), with_defaults(*format))));
+                    let formats = format_defaults.iter().map(|(format, _)| *format).collect();
+                    passes.push((Some(FormatPass::Except(formats)), scoped_options.clone()));
+                }
+            }
+            for (format_pass, options) in passes {
+                set_manifest_params(&options);
+                run.format_pass.replace(format_pass);
+                summary.merge(task.run(source_path.clone(), &run, options));
+            }
         }
-        options_map.insert(option[0], option[1]);
+        summary
+    };
+    if let (Some(manifest_path), Some(manifest)) = (&cli.manifest_path, run.manifest.borrow().as_ref()) {
+        manifest.save(manifest_path, &source_path);
+    }
+    save_dimensions_cache(&run);
+    if let Some(zip_dest) = zip_dest {
+        // the dest only exists once something was written to it.
+        std::fs::create_dir_all(run.dest_path.as_ref().unwrap()).unwrap();
+        archive::create(&work_path.join("dest"), &zip_dest);
+    }
+    if work_path.exists() {
+        std::fs::remove_dir_all(&work_path).unwrap();
+    }
+    if let Some(report_path) = cli.report_path {
+        std::fs::write(&report_path, serde_json::to_string_pretty(&summary.report()).unwrap()).unwrap();
+    }
+    if let Some(output_map_path) = cli.output_map_path {
+        let output_map = serde_json::json!({ "files": summary.outputs });
+        std::fs::write(&output_map_path, serde_json::to_string_pretty(&output_map).unwrap()).unwrap();
+    }
+    let totals: Vec<String> = summary.totals().iter().map(|(key, value)| format!("{} {}", key, value)).collect();
+    log_file::write(&format!("Finished: {}", totals.join(", ")));
+    summary.print_as(cli.summary_format);
+    if summary.regressed > 0 {
+        std::process::exit(1);
+    }
+    if summary.remaining > 0 {
+        std::process::exit(EXIT_OUT_OF_TIME);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_options_keeps_quoted_commas() {
+        assert_eq!(split_options(r#"text="Hello, World",size=10"#), vec![r#"text="Hello, World""#, "size=10"]);
+        assert_eq!(split_options(r#"a="1,2",b="3,4""#), vec![r#"a="1,2""#, r#"b="3,4""#]);
+    }
+
+    #[test]
+    fn split_options_splits_on_newlines() {
+        // as read from an --options-file, with blank lines and a quoted newline.
+        let text = "size=64x64\r\nfilter=lanczos3,quality=80\n\ntext=\"two\nlines\"\n";
+        assert_eq!(split_options(text), vec!["size=64x64\r", "filter=lanczos3", "quality=80", "", "text=\"two\nlines\"", ""]);
+        let options = parse_options(text);
+        assert_eq!(options["size"], "64x64");
+        assert_eq!(options["quality"], "80");
+        assert_eq!(options["text"], "two\nlines");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unterminated quote in options")]
+    fn split_options_refuses_unterminated_quote() {
+        split_options(r#"text="Hello, size=10"#);
+    }
+
+    #[test]
+    fn parse_options_splits_on_first_equal_sign() {
+        let options = parse_options("size=64x64, filter = lanczos3,expr=a=b==c");
+        assert_eq!(options.len(), 3);
+        assert_eq!(options["size"], "64x64");
+        assert_eq!(options["filter"], "lanczos3");
+        assert_eq!(options["expr"], "a=b==c");
+    }
+
+    #[test]
+    fn parse_options_unquotes_values() {
+        let options = parse_options(r#"text="a=b", path = " /tmp/a b ",inner=a"b"c"#);
+        assert_eq!(options["text"], "a=b");
+        assert_eq!(options["path"], " /tmp/a b ");
+        // only quotes around the whole value are taken off.
+        assert_eq!(options["inner"], r#"a"b"c"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid option: size")]
+    fn parse_options_refuses_entries_without_value() {
+        parse_options("size");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid option: size=")]
+    fn parse_options_refuses_empty_values() {
+        parse_options("size=");
+    }
+
+    #[test]
+    fn median_cut_of_no_pixels_is_black() {
+        assert_eq!(median_cut(&[], 16), vec![[0, 0, 0]]);
+    }
+
+    #[test]
+    fn median_cut_of_one_color_is_that_color() {
+        // a box of one color can't be split, however many colors are asked for.
+        assert_eq!(median_cut(&[[10, 20, 30]; 100], 16), vec![[10, 20, 30]]);
+        assert_eq!(median_cut(&[[10, 20, 30]], 1), vec![[10, 20, 30]]);
+    }
+
+    #[test]
+    fn median_cut_splits_widest_channel_at_median() {
+        let pixels = [[0, 0, 0], [0, 0, 10], [255, 0, 0], [255, 0, 10]];
+        let mut palette = median_cut(&pixels, 2);
+        palette.sort();
+        assert_eq!(palette, vec![[0, 0, 5], [255, 0, 5]]);
+        let mut palette = median_cut(&pixels, 4);
+        palette.sort();
+        assert_eq!(palette, pixels.to_vec());
+    }
+
+    #[test]
+    fn parse_runtime_adds_up_units() {
+        assert_eq!(parse_runtime("90s"), std::time::Duration::from_secs(90));
+        assert_eq!(parse_runtime("5m"), std::time::Duration::from_secs(300));
+        assert_eq!(parse_runtime("1h30m"), std::time::Duration::from_secs(5400));
+        assert_eq!(parse_runtime("2m15s"), std::time::Duration::from_secs(135));
+        assert_eq!(parse_runtime("1.5m"), std::time::Duration::from_secs(90));
+    }
+
+    #[test]
+    fn parse_runtime_takes_bare_numbers_as_seconds() {
+        assert_eq!(parse_runtime("45"), std::time::Duration::from_secs(45));
+        assert_eq!(parse_runtime(" 0.5 "), std::time::Duration::from_millis(500));
+        // a trailing number after units is seconds as well.
+        assert_eq!(parse_runtime("1m30"), std::time::Duration::from_secs(90));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid duration (expected e.g. 90s, 5m or 1h30m): 5d")]
+    fn parse_runtime_refuses_unknown_units() {
+        parse_runtime("5d");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid duration")]
+    fn parse_runtime_refuses_units_without_number() {
+        parse_runtime("m");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid duration")]
+    fn parse_runtime_refuses_malformed_numbers() {
+        parse_runtime("1.2.3s");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid duration")]
+    fn parse_runtime_refuses_zero() {
+        parse_runtime("0s");
     }
 
-    // if task is equal to resize
-    if task == "resize" {
-        resize(source_path, options_map);
+    #[test]
+    #[should_panic(expected = "Invalid duration")]
+    fn parse_runtime_refuses_empty_values() {
+        parse_runtime("");
     }
 }
@@ -0,0 +1,76 @@
+// Content hashes of the images handled by earlier runs, for `--manifest`.
+//
+// Modification times don't survive restores and copies, so reruns compare a hash of each source
+// instead: an image whose content, task parameters and output are the same as recorded is
+// skipped. The manifest is a JSON file keyed by source path relative to the source directory,
+// with outputs relative to the dest (or the source in place), so entries still apply when zip
+// archives are extracted into another working directory.
+
+/// 64-bit FNV-1a hash of some bytes, as hex.
+pub fn hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Where an output went, relative to the directory outputs are written to.
+fn output_path(root: &std::path::Path, target_path: &std::path::Path) -> String {
+    target_path.strip_prefix(root).unwrap_or(target_path).to_string_lossy().to_string()
+}
+
+/// The entries of a manifest, together with the parameters of the current run.
+#[derive(Clone, Default)]
+pub struct Manifest {
+    entries: serde_json::Map<String, serde_json::Value>,
+    params: String,
+}
+
+impl Manifest {
+    /// Load a manifest, or start an empty one when the file doesn't exist yet.
+    pub fn load(path: &std::path::Path) -> Manifest {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(serde_json::Value::Object(manifest)) => match manifest.get("files") {
+                    Some(serde_json::Value::Object(entries)) => entries.clone(),
+                    _ => panic!("Invalid manifest {}: missing files", path.display()),
+                },
+                _ => panic!("Invalid manifest {}", path.display()),
+            },
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+            Err(error) => panic!("Can't read manifest {}: {}", path.display(), error),
+        };
+        Manifest { entries, params: String::new() }
+    }
+
+    /// Describe what the run does to the images that follow; entries recorded with other
+    /// parameters don't count as unchanged.
+    pub fn set_params(&mut self, params: String) {
+        self.params = params;
+    }
+
+    /// Whether an image with the given hash was handled by a run with the same parameters into an
+    /// output that still exists under `root`.
+    pub fn is_unchanged(&self, key: &str, hash: &str, root: &std::path::Path, target_path: &std::path::Path) -> bool {
+        let Some(entry) = self.entries.get(key) else {
+            return false;
+        };
+        entry["hash"] == hash && entry["params"] == self.params.as_str() && entry["output"] == output_path(root, target_path) && target_path.exists()
+    }
+
+    /// Record the hash an image has after the run, and where under `root` its output went.
+    pub fn record(&mut self, key: String, hash: String, root: &std::path::Path, target_path: &std::path::Path) {
+        let entry = serde_json::json!({ "hash": hash, "params": self.params, "output": output_path(root, target_path) });
+        self.entries.insert(key, entry);
+    }
+
+    /// Write the manifest, dropping the entries of images that no longer exist in the source.
+    pub fn save(&self, path: &std::path::Path, source_path: &std::path::Path) {
+        let entries: serde_json::Map<String, serde_json::Value> =
+            self.entries.iter().filter(|(key, _)| source_path.join(key).exists()).map(|(key, entry)| (key.clone(), entry.clone())).collect();
+        let manifest = serde_json::json!({ "files": entries });
+        std::fs::write(path, serde_json::to_string_pretty(&manifest).unwrap()).unwrap();
+    }
+}
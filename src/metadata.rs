@@ -0,0 +1,253 @@
+// EXIF handling for JPEG files, and color space chunks for PNGs.
+//
+// The image crate drops all metadata when encoding, so these helpers work directly on the JPEG
+// segments: they pull the EXIF (TIFF) payload out of one file and put a rewritten copy into another.
+// PNG chunks are added the same way, since its encoder doesn't write them either.
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// Byte ranges of the marker segments in front of the image data, as (marker, start, end).
+fn segments(jpeg: &[u8]) -> Vec<(u8, usize, usize)> {
+    let mut segments = Vec::new();
+    if jpeg.len() < 2 || jpeg[0] != 0xFF || jpeg[1] != 0xD8 {
+        return segments;
+    }
+    let mut index = 2;
+    while index + 4 <= jpeg.len() && jpeg[index] == 0xFF {
+        let marker = jpeg[index + 1];
+        // the image data starts at the start-of-scan marker.
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([jpeg[index + 2], jpeg[index + 3]]) as usize;
+        let end = (index + 2 + length).min(jpeg.len());
+        segments.push((marker, index, end));
+        index = end;
+    }
+    segments
+}
+
+fn is_exif_segment(jpeg: &[u8], marker: u8, start: usize, end: usize) -> bool {
+    marker == 0xE1 && end >= start + 4 && jpeg[start + 4..end].starts_with(EXIF_HEADER)
+}
+
+/// The EXIF (TIFF) payload of a JPEG, if it has one.
+pub fn jpeg_exif(jpeg: &[u8]) -> Option<Vec<u8>> {
+    segments(jpeg)
+        .into_iter()
+        .find(|&(marker, start, end)| is_exif_segment(jpeg, marker, start, end))
+        .map(|(_, start, end)| jpeg[start + 4 + EXIF_HEADER.len()..end].to_vec())
+}
+
+/// Rewrite an EXIF payload keeping only the fields accepted by `keep`, plus an `added` one.
+///
+/// The embedded JPEG thumbnail is carried over. Returns `None` when the payload can't be parsed or
+/// nothing is left to write.
+fn rewrite_exif(exif_data: Vec<u8>, keep: impl Fn(&exif::Field) -> bool, added: Option<&exif::Field>) -> Option<Vec<u8>> {
+    let exif = exif::Reader::new().read_raw(exif_data).ok()?;
+    let mut writer = exif::experimental::Writer::new();
+    let mut kept = 0;
+    for field in exif.fields() {
+        if keep(field) {
+            writer.push_field(field);
+            kept += 1;
+        }
+    }
+    if let Some(added) = added {
+        writer.push_field(added);
+        kept += 1;
+    }
+    if kept == 0 {
+        return None;
+    }
+
+    // carry over the thumbnail, which is referenced by offset into the payload.
+    let offset = exif.get_field(exif::Tag::JPEGInterchangeFormat, exif::In::THUMBNAIL).and_then(|f| f.value.get_uint(0));
+    let length = exif.get_field(exif::Tag::JPEGInterchangeFormatLength, exif::In::THUMBNAIL).and_then(|f| f.value.get_uint(0));
+    if let (Some(offset), Some(length)) = (offset, length) {
+        if let Some(thumbnail) = exif.buf().get(offset as usize..(offset + length) as usize) {
+            writer.set_jpeg(thumbnail, exif::In::THUMBNAIL);
+        }
+    }
+
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    writer.write(&mut bytes, exif.little_endian()).ok()?;
+    Some(bytes.into_inner())
+}
+
+/// Remove every GPS field from an EXIF payload, keeping everything else.
+pub fn without_gps(exif_data: Vec<u8>) -> Option<Vec<u8>> {
+    rewrite_exif(exif_data, |field| field.tag.context() != exif::Context::Gps, None)
+}
+
+/// Remove the Orientation field from an EXIF payload, for images whose pixels were turned
+/// upright (or otherwise transformed) so that viewers don't rotate them a second time.
+pub fn without_orientation(exif_data: Vec<u8>) -> Option<Vec<u8>> {
+    rewrite_exif(exif_data, |field| field.tag != exif::Tag::Orientation, None)
+}
+
+/// The Orientation field of an EXIF payload, 1 (upright) to 8.
+pub fn orientation(exif_data: &[u8]) -> Option<u16> {
+    let exif = exif::Reader::new().read_raw(exif_data.to_vec()).ok()?;
+    let field = exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?;
+    field.value.get_uint(0).map(|orientation| orientation as u16).filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Set the Orientation field of an EXIF payload, or create a payload with only that field for
+/// images without one, so viewers show the pixels turned without them being re-encoded.
+pub fn with_orientation(exif_data: Option<Vec<u8>>, orientation: u16) -> Option<Vec<u8>> {
+    let field = exif::Field { tag: exif::Tag::Orientation, ifd_num: exif::In::PRIMARY, value: exif::Value::Short(vec![orientation]) };
+    if let Some(exif_data) = exif_data {
+        if let Some(exif_data) = rewrite_exif(exif_data, |field| field.tag != exif::Tag::Orientation, Some(&field)) {
+            return Some(exif_data);
+        }
+    }
+    let mut writer = exif::experimental::Writer::new();
+    writer.push_field(&field);
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    writer.write(&mut bytes, false).ok()?;
+    Some(bytes.into_inner())
+}
+
+/// When an image was taken according to its EXIF DateTimeOriginal field, read from any container
+/// kamadak-exif understands (JPEG, TIFF, PNG, WebP, HEIF). The value is local time without a
+/// time zone, as cameras write it.
+pub fn capture_date(path: &std::path::Path) -> Option<exif::DateTime> {
+    let file = std::fs::File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file)).ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Ascii(values) => exif::DateTime::from_ascii(values.first()?).ok(),
+        _ => None,
+    }
+}
+
+/// Replace the EXIF segment of a JPEG with the given payload (or remove it when `None`).
+///
+/// The new segment goes right after the JFIF header when there is one, otherwise right after the
+/// start-of-image marker.
+pub fn with_exif(jpeg: &[u8], exif_data: Option<&[u8]>) -> Vec<u8> {
+    let segments = segments(jpeg);
+    let mut insert_at = 2;
+    if let Some(&(0xE0, _, end)) = segments.first() {
+        insert_at = end;
+    }
+
+    let mut bytes = Vec::with_capacity(jpeg.len() + exif_data.map_or(0, |e| e.len() + 10));
+    bytes.extend_from_slice(&jpeg[..insert_at]);
+    if let Some(exif_data) = exif_data {
+        let length = 2 + EXIF_HEADER.len() + exif_data.len();
+        // a segment can't hold more than 64k, drop metadata that doesn't fit.
+        if length <= u16::MAX as usize {
+            bytes.extend_from_slice(&[0xFF, 0xE1]);
+            bytes.extend_from_slice(&(length as u16).to_be_bytes());
+            bytes.extend_from_slice(EXIF_HEADER);
+            bytes.extend_from_slice(exif_data);
+        }
+    }
+    // copy the rest, skipping any existing EXIF segment.
+    let mut index = insert_at;
+    for &(marker, start, end) in segments.iter().filter(|&&(_, start, _)| start >= insert_at) {
+        bytes.extend_from_slice(&jpeg[index..start]);
+        if !is_exif_segment(jpeg, marker, start, end) {
+            bytes.extend_from_slice(&jpeg[start..end]);
+        }
+        index = end;
+    }
+    bytes.extend_from_slice(&jpeg[index..]);
+    bytes
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// CRC-32 of a PNG chunk's type and data.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Types of the chunks of a PNG, in order.
+fn png_chunk_types(png: &[u8]) -> Vec<[u8; 4]> {
+    let mut types = Vec::new();
+    let mut index = PNG_SIGNATURE.len();
+    while index + 8 <= png.len() {
+        let length = u32::from_be_bytes(png[index..index + 4].try_into().unwrap()) as usize;
+        types.push(png[index + 4..index + 8].try_into().unwrap());
+        index += 12 + length;
+    }
+    types
+}
+
+/// Mark a PNG as sRGB with an sRGB chunk (perceptual intent) and the matching gAMA chunk for
+/// viewers that only know gamma, right after the header.
+///
+/// PNGs that don't start with a header, or already describe their color space with an sRGB,
+/// iCCP or gAMA chunk, are returned unchanged.
+pub fn with_srgb(png: Vec<u8>) -> Vec<u8> {
+    if !png.starts_with(PNG_SIGNATURE) {
+        return png;
+    }
+    let types = png_chunk_types(&png);
+    if types.first() != Some(b"IHDR") || types.iter().any(|chunk_type| [b"sRGB", b"iCCP", b"gAMA"].contains(&chunk_type)) {
+        return png;
+    }
+    // the header chunk always holds 13 bytes.
+    let insert_at = PNG_SIGNATURE.len() + 12 + 13;
+    let mut bytes = Vec::with_capacity(png.len() + 29);
+    bytes.extend_from_slice(&png[..insert_at]);
+    // a gamma of 1/2.2, stored times 100000.
+    for (chunk_type, data) in [(b"sRGB", &[0u8][..]), (b"gAMA", &45455u32.to_be_bytes()[..])] {
+        let mut chunk = chunk_type.to_vec();
+        chunk.extend_from_slice(data);
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&chunk);
+        bytes.extend_from_slice(&crc32(&chunk).to_be_bytes());
+    }
+    bytes.extend_from_slice(&png[insert_at..]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1x1 PNG as the image crate writes it.
+    fn png() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::RgbImage::from_pixel(1, 1, image::Rgb([255, 0, 0])).write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageOutputFormat::Png).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn crc32_matches_known_values() {
+        // the check value of CRC-32, and the CRC of every PNG's IEND chunk.
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn with_srgb_adds_chunks_after_header() {
+        let original = png();
+        let marked = with_srgb(original.clone());
+        let insert_at = PNG_SIGNATURE.len() + 12 + 13;
+        assert_eq!(marked[..insert_at], original[..insert_at]);
+        // the chunks as the PNG specification gives them, CRCs included.
+        let chunks: &[u8] = b"\x00\x00\x00\x01sRGB\x00\xae\xce\x1c\xe9\x00\x00\x00\x04gAMA\x00\x00\xb1\x8f\x0b\xfc\x61\x05";
+        assert_eq!(&marked[insert_at..insert_at + chunks.len()], chunks);
+        assert_eq!(marked[insert_at + chunks.len()..], original[insert_at..]);
+        assert_eq!(image::load_from_memory(&marked).unwrap().to_rgb8().get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+    }
+
+    #[test]
+    fn with_srgb_keeps_described_and_other_files() {
+        let marked = with_srgb(png());
+        assert_eq!(with_srgb(marked.clone()), marked);
+        assert_eq!(with_srgb(b"GIF89a".to_vec()), b"GIF89a");
+    }
+}
@@ -0,0 +1,1189 @@
+// PDFs as sources, with the pdf feature.
+//
+// Pages are taken in the order of the page tree and drawn from the images they place, through
+// the transformations of their content streams and form XObjects, onto a white page at a chosen
+// DPI, by default the resolution of the page's largest image so that scans keep every pixel. This
+// covers scans, which are one or a few images per page, but nothing else is rendered: text and
+// vector graphics are warned about and left out, pages without an image are left out, and PDFs
+// without any fail as unreadable. Images may be JPEG, Flate, CCITT fax or uncompressed data,
+// JBIG2 and JPEG 2000 images have no decoder here and are warned about.
+
+/// Largest page drawn, in pixels, about 16000x16000.
+const MAX_PAGE_PIXELS: u64 = 1 << 28;
+
+/// How deeply form XObjects may nest, which also stops forms that draw themselves.
+const MAX_FORM_DEPTH: usize = 8;
+
+/// The dictionary before a stream, with the number of its object and where its data starts.
+struct StreamObject {
+    number: Option<u32>,
+    dictionary: String,
+    data_start: usize,
+}
+
+/// The value of a key in a dictionary, as the text up to the next key or the end, with arrays
+/// and nested dictionaries kept whole.
+fn value<'a>(dictionary: &'a str, key: &str) -> Option<&'a str> {
+    let mut search = 0;
+    while let Some(found) = dictionary[search..].find(key) {
+        let start = search + found + key.len();
+        search = start;
+        // the key must not be the start of a longer name, e.g. /Length in /Length1.
+        if dictionary[start..].starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+        let rest = dictionary[start..].trim_start();
+        let end = match rest.chars().next()? {
+            '[' => rest.find(']').map(|end| end + 1)?,
+            '<' => matching_end(rest)?,
+            '/' => rest[1..].find(|c: char| c.is_whitespace() || "/[]<>()".contains(c)).map_or(rest.len(), |end| end + 1),
+            _ => rest.find(['/', '>']).unwrap_or(rest.len()),
+        };
+        return Some(rest[..end].trim());
+    }
+    None
+}
+
+/// Where the dictionary at the start of `text` ends, after its `>>`.
+fn matching_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let bytes = text.as_bytes();
+    let mut index = 0;
+    while index + 1 < bytes.len() {
+        match &bytes[index..index + 2] {
+            b"<<" => {
+                depth += 1;
+                index += 2;
+            }
+            b">>" => {
+                depth -= 1;
+                index += 2;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => index += 1,
+        }
+    }
+    None
+}
+
+/// The object number of an indirect value (`12 0 R`).
+fn reference(value: &str) -> Option<u32> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts[..] {
+        [number, _generation, "R"] => number.parse().ok(),
+        _ => None,
+    }
+}
+
+/// The object numbers of an array of indirect values, e.g. the `/Kids` of a page tree node.
+fn references(array: &str) -> Vec<u32> {
+    let array = array.trim_start_matches('[').trim_end_matches(']').replace('/', " /");
+    let parts: Vec<&str> = array.split_whitespace().collect();
+    parts.windows(3).filter(|window| window[2] == "R").filter_map(|window| window[0].parse().ok()).collect()
+}
+
+/// The numbers of an array, e.g. a `/MediaBox`.
+fn numbers(array: &str) -> Vec<f64> {
+    array.trim_start_matches('[').trim_end_matches(']').split_whitespace().filter_map(|number| number.parse().ok()).collect()
+}
+
+/// The entries of a dictionary that are indirect values, by key, e.g. the images of `/XObject`
+/// resources.
+fn referenced_entries(dictionary: &str) -> Vec<(String, u32)> {
+    // names may follow values without a space, as in `/Im0 5 0 R/Im1 6 0 R`.
+    let inner = dictionary.trim().trim_start_matches("<<").trim_end_matches(">>").replace('/', " /");
+    let parts: Vec<&str> = inner.split_whitespace().collect();
+    parts.windows(4).filter(|window| window[0].starts_with('/') && window[3] == "R").filter_map(|window| Some((window[0].to_string(), window[1].parse().ok()?))).collect()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Where the text of an object ends: at its `endobj`, or at the `stream` keyword after its
+/// dictionary so that stream data isn't read as text.
+fn object_end(rest: &[u8]) -> usize {
+    for index in 0..rest.len() {
+        if rest[index..].starts_with(b"endobj") {
+            return index;
+        }
+        if rest[index..].starts_with(b"stream") && rest[..index].trim_ascii_end().ends_with(b">>") {
+            return index;
+        }
+    }
+    rest.len()
+}
+
+/// Where the text of each object starts (after `12 0 obj`) by object number, the latest one when
+/// an incremental update stored an object again.
+fn object_starts(bytes: &[u8]) -> std::collections::HashMap<u32, usize> {
+    let mut starts = std::collections::HashMap::new();
+    let mut search = 0;
+    while let Some(found) = find(&bytes[search..], b" obj") {
+        let keyword = search + found;
+        search = keyword + 4;
+        let header = String::from_utf8_lossy(bytes[keyword.saturating_sub(24)..keyword].trim_ascii_start()).to_string();
+        let numbers: Vec<&str> = header.split_whitespace().rev().take(2).collect();
+        if let [_generation, number] = numbers[..] {
+            if let Ok(number) = number.parse() {
+                starts.insert(number, search);
+            }
+        }
+    }
+    starts
+}
+
+/// Every stream of a PDF with its dictionary, in the order they are stored.
+fn stream_objects(bytes: &[u8]) -> Vec<StreamObject> {
+    let mut objects = Vec::new();
+    let mut search = 0;
+    while let Some(found) = find(&bytes[search..], b"stream") {
+        let keyword = search + found;
+        search = keyword + 6;
+        let Some(data_start) = data_start(bytes, keyword) else {
+            continue;
+        };
+        let before = bytes[..keyword].trim_ascii_end();
+        if !before.ends_with(b">>") {
+            continue;
+        }
+        let Some(start) = dictionary_start(before) else {
+            continue;
+        };
+        let dictionary = String::from_utf8_lossy(&before[start..]).to_string();
+        // `12 0 obj` before the dictionary gives the object number.
+        let header = String::from_utf8_lossy(before[start.saturating_sub(32)..start].trim_ascii_end()).to_string();
+        let number = header.strip_suffix("obj").and_then(|header| header.split_whitespace().rev().nth(1)).and_then(|number| number.parse().ok());
+        objects.push(StreamObject { number, dictionary, data_start });
+    }
+    objects
+}
+
+/// Where the data of a stream starts, after its `stream` keyword and the end of line following it.
+fn data_start(bytes: &[u8], keyword: usize) -> Option<usize> {
+    let after = keyword + 6;
+    match bytes.get(after..)? {
+        [b'\r', b'\n', ..] => Some(after + 2),
+        [b'\n', ..] | [b'\r', ..] => Some(after + 1),
+        _ => None,
+    }
+}
+
+/// Where the dictionary that `before` ends with starts, going back over nested dictionaries.
+fn dictionary_start(before: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = before.len();
+    while index >= 2 {
+        match &before[index - 2..index] {
+            b">>" => {
+                depth += 1;
+                index -= 2;
+            }
+            b"<<" => {
+                depth -= 1;
+                index -= 2;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => index -= 1,
+        }
+    }
+    None
+}
+
+/// The objects of a PDF, found once: those stored as text in the file and those compressed into
+/// object streams (`/Type /ObjStm`).
+struct Document<'a> {
+    bytes: &'a [u8],
+    starts: std::collections::HashMap<u32, usize>,
+    compressed: std::collections::HashMap<u32, String>,
+}
+
+impl<'a> Document<'a> {
+    fn new(bytes: &'a [u8]) -> Document<'a> {
+        let mut document = Document { bytes, starts: object_starts(bytes), compressed: std::collections::HashMap::new() };
+        let mut compressed = std::collections::HashMap::new();
+        for object in stream_objects(bytes).iter().filter(|object| value(&object.dictionary, "/Type") == Some("/ObjStm")) {
+            let Ok(data) = document.decompressed(&object.dictionary, document.stream_data(&object.dictionary, object.data_start)) else {
+                continue;
+            };
+            let (Some(count), Some(first)) = (document.integer(&object.dictionary, "/N"), document.integer(&object.dictionary, "/First")) else {
+                continue;
+            };
+            // pairs of object number and offset from `/First`, then the objects.
+            let header = String::from_utf8_lossy(&data[..(first as usize).min(data.len())]).to_string();
+            let pairs: Vec<usize> = header.split_whitespace().filter_map(|number| number.parse().ok()).take(count as usize * 2).collect();
+            for (index, pair) in pairs.chunks_exact(2).enumerate() {
+                let start = (first as usize + pair[1]).min(data.len());
+                let end = pairs.get(index * 2 + 3).map_or(data.len(), |next| (first as usize + next).min(data.len()));
+                compressed.insert(pair[0] as u32, String::from_utf8_lossy(&data[start..end.max(start)]).trim().to_string());
+            }
+        }
+        // objects stored in the file again, by an incremental update, are the newer ones.
+        compressed.retain(|number, _| !document.starts.contains_key(number));
+        document.compressed = compressed;
+        document
+    }
+
+    /// The text of an object, without the data of a stream.
+    fn object(&self, number: u32) -> Option<String> {
+        if let Some(text) = self.compressed.get(&number) {
+            return Some(text.clone());
+        }
+        let rest = &self.bytes[*self.starts.get(&number)?..];
+        Some(String::from_utf8_lossy(&rest[..object_end(rest)]).trim().to_string())
+    }
+
+    /// The value of a key in a dictionary, following an indirect value to its object.
+    fn get(&self, dictionary: &str, key: &str) -> Option<String> {
+        let value = value(dictionary, key)?;
+        match reference(value) {
+            Some(number) => self.object(number),
+            None => Some(value.to_string()),
+        }
+    }
+
+    /// An integer value of a dictionary, following an indirect value to its object.
+    fn integer(&self, dictionary: &str, key: &str) -> Option<u32> {
+        self.get(dictionary, key)?.parse().ok()
+    }
+
+    /// The dictionary and data of a stream object.
+    fn stream(&self, number: u32) -> Option<(String, &'a [u8])> {
+        let start = *self.starts.get(&number)?;
+        let rest = &self.bytes[start..];
+        let end = object_end(rest);
+        if !rest[end..].starts_with(b"stream") {
+            return None;
+        }
+        let dictionary = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+        let data_start = data_start(self.bytes, start + end)?;
+        Some((dictionary.clone(), self.stream_data(&dictionary, data_start)))
+    }
+
+    /// The data of a stream, by its `/Length` or else up to `endstream`.
+    fn stream_data(&self, dictionary: &str, data_start: usize) -> &'a [u8] {
+        let rest = &self.bytes[data_start..];
+        match self.integer(dictionary, "/Length") {
+            Some(length) if length as usize <= rest.len() => &rest[..length as usize],
+            _ => &rest[..find(rest, b"endstream").unwrap_or(rest.len())],
+        }
+    }
+
+    /// The filters of a stream, in the order they are undone.
+    fn filters(&self, dictionary: &str) -> Vec<String> {
+        let filter = self.get(dictionary, "/Filter").unwrap_or_default();
+        filter.trim_start_matches('[').trim_end_matches(']').split_whitespace().map(str::to_string).collect()
+    }
+
+    /// Undo the Flate compression of stream data, the only general purpose filter scanners use.
+    fn decompressed(&self, dictionary: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut data = data.to_vec();
+        for filter in self.filters(dictionary) {
+            match filter.as_str() {
+                "/FlateDecode" | "/Fl" => data = inflate(&data)?,
+                filter => return Err(format!("{} isn't supported", filter)),
+            }
+        }
+        Ok(data)
+    }
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    let mut inflated = Vec::new();
+    flate2::read::ZlibDecoder::new(data).read_to_end(&mut inflated).map_err(|error| error.to_string())?;
+    Ok(inflated)
+}
+
+/// Undo the PNG predictors (`/Predictor` 10 and up) of Flate encoded samples, row by row.
+fn unpredict(data: &[u8], row_bytes: usize, pixel_bytes: usize) -> Result<Vec<u8>, String> {
+    let mut rows: Vec<u8> = Vec::with_capacity(data.len());
+    let mut previous = vec![0u8; row_bytes];
+    for row in data.chunks(row_bytes + 1) {
+        let (filter, row) = row.split_first().ok_or("empty row")?;
+        let mut current = row.to_vec();
+        current.resize(row_bytes, 0);
+        for index in 0..row_bytes {
+            let left = if index >= pixel_bytes { current[index - pixel_bytes] } else { 0 };
+            let up = previous[index];
+            let up_left = if index >= pixel_bytes { previous[index - pixel_bytes] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => {
+                    let estimate = left as i16 + up as i16 - up_left as i16;
+                    let (a, b, c) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+                    if a <= b && a <= c {
+                        left
+                    } else if b <= c {
+                        up
+                    } else {
+                        up_left
+                    }
+                }
+                _ => return Err(format!("unknown PNG predictor {}", filter)),
+            };
+            current[index] = current[index].wrapping_add(predicted);
+        }
+        rows.extend_from_slice(&current);
+        previous = current;
+    }
+    Ok(rows)
+}
+
+/// The number of channels of a color space, `None` for those that aren't supported.
+fn channels(document: &Document, color_space: &str) -> Option<usize> {
+    let color_space = match reference(color_space) {
+        Some(number) => document.object(number)?,
+        None => color_space.to_string(),
+    };
+    let name = color_space.trim_start_matches('[').split_whitespace().next()?.to_string();
+    match name.as_str() {
+        "/DeviceGray" | "/CalGray" | "/G" => Some(1),
+        "/DeviceRGB" | "/CalRGB" | "/RGB" => Some(3),
+        "/DeviceCMYK" | "/CMYK" => Some(4),
+        "/Indexed" | "/I" => None,
+        // an ICC profile stream tells its number of channels.
+        "/ICCBased" => {
+            let number = reference(color_space.trim_start_matches('[').trim_end_matches(']').trim_start_matches("/ICCBased").trim())?;
+            let (dictionary, _) = document.stream(number)?;
+            document.integer(&dictionary, "/N").map(|channels| channels as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Samples of `bits` bits each, scaled to 0-255, rows starting on a byte.
+fn unpacked(samples: &[u8], bits: u32, values_per_row: usize, height: usize) -> Vec<u8> {
+    let row_bytes = (values_per_row * bits as usize).div_ceil(8);
+    let mut values = Vec::with_capacity(values_per_row * height);
+    for row in samples.chunks(row_bytes).take(height) {
+        for index in 0..values_per_row {
+            let value = match bits {
+                8 => row[index],
+                16 => row[index * 2],
+                bits => {
+                    let bit = index * bits as usize;
+                    let sample = (row[bit / 8] >> (8 - bits as usize - bit % 8)) & ((1 << bits) - 1);
+                    (sample as u32 * 255 / ((1 << bits) - 1)) as u8
+                }
+            };
+            values.push(value);
+        }
+    }
+    values
+}
+
+/// Apply a `/Decode` array to samples of 0-255, e.g. `[1 0]` to invert them.
+fn apply_decode(values: &mut [u8], decode: &[f64], channels: usize) {
+    if decode.len() < channels * 2 {
+        return;
+    }
+    for (index, value) in values.iter_mut().enumerate() {
+        let (low, high) = (decode[index % channels * 2], decode[index % channels * 2 + 1]);
+        *value = ((low + *value as f64 / 255.0 * (high - low)).clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+}
+
+/// An image from samples of 0-255 in gray, RGB or CMYK.
+fn from_values(values: Vec<u8>, width: u32, height: u32, channels: usize) -> Option<image::DynamicImage> {
+    match channels {
+        1 => image::GrayImage::from_raw(width, height, values).map(image::DynamicImage::ImageLuma8),
+        3 => image::RgbImage::from_raw(width, height, values).map(image::DynamicImage::ImageRgb8),
+        4 => {
+            let rgb = values
+                .chunks(4)
+                .flat_map(|cmyk| {
+                    let k = 255 - cmyk[3] as u16;
+                    [0, 1, 2].map(|channel| ((255 - cmyk[channel] as u16) * k / 255) as u8)
+                })
+                .collect();
+            image::RgbImage::from_raw(width, height, rgb).map(image::DynamicImage::ImageRgb8)
+        }
+        _ => None,
+    }
+}
+
+/// Decode CCITT fax data into one value of 0 (black) or 255 per pixel, as the `/DecodeParms` of
+/// the filter describe it.
+fn decode_fax(document: &Document, data: &[u8], parameters: &str, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let k = document.get(parameters, "/K").and_then(|k| k.parse::<i32>().ok()).unwrap_or(0);
+    let columns = document.integer(parameters, "/Columns").unwrap_or(width);
+    let black_is_1 = document.get(parameters, "/BlackIs1").as_deref() == Some("true");
+    if columns != width || width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(format!("fax data of {} columns for an image {} wide isn't supported", columns, width));
+    }
+    let mut values = Vec::with_capacity(width as usize * height as usize);
+    let mut add_line = |transitions: &[u16]| {
+        if values.len() < width as usize * height as usize {
+            // 0 bits are black unless `/BlackIs1`, black is 0 under the default `/Decode`.
+            values.extend(fax::decoder::pels(transitions, width as u16).map(|color| if (color == fax::Color::Black) == black_is_1 { 255 } else { 0 }));
+        }
+    };
+    let decoded = match k {
+        k if k < 0 => fax::decoder::decode_g4(data.iter().copied(), width as u16, Some(height as u16), &mut add_line),
+        0 => fax::decoder::decode_g3(data.iter().copied(), &mut add_line),
+        _ => return Err("two-dimensional group 3 fax data isn't supported".to_string()),
+    };
+    if decoded.is_none() && values.is_empty() {
+        return Err("invalid fax data".to_string());
+    }
+    // lines missing at the end are white.
+    let white = if black_is_1 { 0 } else { 255 };
+    values.resize(width as usize * height as usize, white);
+    Ok(values)
+}
+
+/// Decode an image XObject of a PDF, `Err` with the reason when its encoding isn't supported.
+/// Stencil masks (`/ImageMask true`) decode to a gray image where black is painted.
+fn decode_image(document: &Document, dictionary: &str, data: &[u8]) -> Result<image::DynamicImage, String> {
+    let (Some(width), Some(height)) = (document.integer(dictionary, "/Width"), document.integer(dictionary, "/Height")) else {
+        return Err("no width or height".to_string());
+    };
+    let is_mask = document.get(dictionary, "/ImageMask").as_deref() == Some("true");
+    let decode = document.get(dictionary, "/Decode").map(|decode| numbers(&decode)).unwrap_or_default();
+    let mut filters = document.filters(dictionary);
+    // the parameters of the last filter, which decodes the image.
+    let parameters = document.get(dictionary, "/DecodeParms").unwrap_or_default();
+    let parameters = match parameters.strip_prefix('[') {
+        Some(array) => array.rfind("<<").map_or(String::new(), |start| array[start..].trim_end_matches(']').trim().to_string()),
+        None => match reference(&parameters) {
+            Some(number) => document.object(number).unwrap_or_default(),
+            None => parameters,
+        },
+    };
+    let codec = match filters.last().map(String::as_str) {
+        Some("/DCTDecode" | "/DCT" | "/CCITTFaxDecode" | "/CCF" | "/JBIG2Decode" | "/JPXDecode") => filters.pop(),
+        _ => None,
+    };
+    let mut data = data.to_vec();
+    for filter in &filters {
+        match filter.as_str() {
+            "/FlateDecode" | "/Fl" => data = inflate(&data)?,
+            filter => return Err(format!("{} isn't supported", filter)),
+        }
+    }
+
+    let channels = match is_mask {
+        true => 1,
+        false => document.get(dictionary, "/ColorSpace").and_then(|color_space| channels(document, &color_space)).unwrap_or(0),
+    };
+    match codec.as_deref() {
+        Some("/DCTDecode" | "/DCT") => {
+            let image = image::load_from_memory_with_format(&data, image::ImageFormat::Jpeg).map_err(|error| error.to_string())?;
+            // CMYK JPEGs are inverted by the decoder already when they need to be.
+            if decode.is_empty() || channels == 4 {
+                return Ok(image);
+            }
+            let (image_channels, mut values) = match image.color().channel_count() {
+                1 => (1, image.to_luma8().into_raw()),
+                _ => (3, image.to_rgb8().into_raw()),
+            };
+            apply_decode(&mut values, &decode, image_channels);
+            from_values(values, image.width(), image.height(), image_channels).ok_or_else(|| "image data ends early".to_string())
+        }
+        Some("/CCITTFaxDecode" | "/CCF") => {
+            let mut values = decode_fax(document, &data, &parameters, width, height)?;
+            if !is_mask {
+                apply_decode(&mut values, &decode, 1);
+            } else {
+                // painted where the sample is 0, or 1 with a `/Decode` of [1 0].
+                let painted_value = if decode.first() == Some(&1.0) { 255 } else { 0 };
+                values.iter_mut().for_each(|value| *value = if *value == painted_value { 0 } else { 255 });
+            }
+            from_values(values, width, height, 1).ok_or_else(|| "image data ends early".to_string())
+        }
+        Some("/JBIG2Decode") => Err("JBIG2 images aren't supported".to_string()),
+        Some(_) => Err("JPEG 2000 images aren't supported".to_string()),
+        None => {
+            let bits = match is_mask {
+                true => 1,
+                false => document.integer(dictionary, "/BitsPerComponent").unwrap_or(8),
+            };
+            if ![1, 2, 4, 8, 16].contains(&bits) {
+                return Err(format!("{} bits per channel aren't supported", bits));
+            }
+            // other color spaces are judged by the amount of data.
+            let channels = match channels {
+                0 => [1usize, 3, 4].into_iter().find(|channels| data.len() >= (width as usize * channels * bits as usize).div_ceil(8) * height as usize).unwrap_or(1),
+                channels => channels,
+            };
+            let row_bytes = (width as usize * channels * bits as usize).div_ceil(8);
+            if document.integer(&parameters, "/Predictor").is_some_and(|predictor| predictor >= 10) {
+                data = unpredict(&data, row_bytes, (channels * bits as usize).div_ceil(8))?;
+            }
+            if data.len() < row_bytes * height as usize {
+                return Err("image data ends early".to_string());
+            }
+            let mut values = unpacked(&data, bits, width as usize * channels, height as usize);
+            if is_mask {
+                // painted where the sample is 0, or 1 with a `/Decode` of [1 0].
+                let painted_value = if decode.first() == Some(&1.0) { 255 } else { 0 };
+                values.iter_mut().for_each(|value| *value = if *value == painted_value { 0 } else { 255 });
+            } else {
+                apply_decode(&mut values, &decode, channels);
+            }
+            from_values(values, width, height, channels).ok_or_else(|| format!("{} channels aren't supported", channels))
+        }
+    }
+}
+
+/// A transformation matrix `[a b c d e f]`, mapping (x, y) to (ax + cy + e, bx + dy + f).
+type Matrix = [f64; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// `first` followed by `second`.
+fn multiply(first: &Matrix, second: &Matrix) -> Matrix {
+    [
+        first[0] * second[0] + first[1] * second[2],
+        first[0] * second[1] + first[1] * second[3],
+        first[2] * second[0] + first[3] * second[2],
+        first[2] * second[1] + first[3] * second[3],
+        first[4] * second[0] + first[5] * second[2] + second[4],
+        first[4] * second[1] + first[5] * second[3] + second[5],
+    ]
+}
+
+/// A page of the page tree with what it inherits from its ancestors.
+struct Page {
+    /// The visible area, the `/CropBox` or else the `/MediaBox`, as left, bottom, right and top.
+    area: [f64; 4],
+    resources: String,
+    /// Clockwise quarter turns when shown.
+    turns: u32,
+    contents: Vec<u8>,
+}
+
+/// What pages inherit from the nodes of the page tree above them.
+#[derive(Clone, Default)]
+struct Inherited {
+    media_box: Option<String>,
+    crop_box: Option<String>,
+    resources: Option<String>,
+    rotate: Option<String>,
+}
+
+/// The catalog's page tree root, from the last `/Root` entry, which that of the latest trailer or
+/// cross-reference stream is.
+fn page_tree_root(document: &Document) -> Option<u32> {
+    let mut end = document.bytes.len();
+    while let Some(found) = document.bytes[..end].windows(5).rposition(|window| window == b"/Root") {
+        end = found;
+        let after = String::from_utf8_lossy(&document.bytes[found + 5..(found + 40).min(document.bytes.len())]).to_string();
+        let parts: Vec<&str> = after.split_whitespace().take(3).collect();
+        let Some(catalog) = parts.get(2).filter(|part| part.starts_with('R')).and_then(|_| parts[0].parse().ok()) else {
+            continue;
+        };
+        if let Some(pages) = document.object(catalog).and_then(|catalog| value(&catalog, "/Pages").and_then(reference)) {
+            return Some(pages);
+        }
+    }
+    None
+}
+
+/// The pages below a node of the page tree, in order.
+fn collect_pages(document: &Document, number: u32, inherited: &Inherited, visited: &mut std::collections::HashSet<u32>, pages: &mut Vec<Page>) {
+    if !visited.insert(number) {
+        return;
+    }
+    let Some(node) = document.object(number) else {
+        return;
+    };
+    let inherited = Inherited {
+        media_box: document.get(&node, "/MediaBox").or(inherited.media_box.clone()),
+        crop_box: document.get(&node, "/CropBox").or(inherited.crop_box.clone()),
+        resources: document.get(&node, "/Resources").or(inherited.resources.clone()),
+        rotate: document.get(&node, "/Rotate").or(inherited.rotate.clone()),
+    };
+    if let Some(kids) = document.get(&node, "/Kids") {
+        for kid in references(&kids) {
+            collect_pages(document, kid, &inherited, visited, pages);
+        }
+        return;
+    }
+    let area = inherited.crop_box.as_deref().or(inherited.media_box.as_deref()).map(numbers).filter(|area| area.len() == 4);
+    // US Letter when the box is missing.
+    let area = area.map_or([0.0, 0.0, 612.0, 792.0], |area| [area[0].min(area[2]), area[1].min(area[3]), area[0].max(area[2]), area[1].max(area[3])]);
+    let turns = inherited.rotate.and_then(|rotate| rotate.parse::<i64>().ok()).unwrap_or(0).rem_euclid(360) as u32 / 90;
+    let contents = match value(&node, "/Contents") {
+        Some(contents) if contents.starts_with('[') => references(contents),
+        Some(contents) => reference(contents).into_iter().collect(),
+        None => Vec::new(),
+    };
+    // an array of streams is one content stream split up.
+    let contents = contents
+        .into_iter()
+        .filter_map(|number| document.stream(number))
+        .filter_map(|(dictionary, data)| document.decompressed(&dictionary, data).ok())
+        .fold(Vec::new(), |mut all, data| {
+            all.extend_from_slice(&data);
+            all.push(b'\n');
+            all
+        });
+    pages.push(Page { area, resources: inherited.resources.unwrap_or_default(), turns, contents });
+}
+
+/// A token of a content stream.
+enum Token {
+    Number(f64),
+    Name(String),
+    Operator(String),
+    /// Strings, arrays and dictionaries, which the operators drawn here don't take.
+    Other,
+}
+
+/// Split a content stream into tokens, skipping the data of inline images.
+fn tokens(content: &[u8]) -> Vec<Token> {
+    let is_delimiter = |byte: u8| byte.is_ascii_whitespace() || b"()<>[]{}/%".contains(&byte);
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < content.len() {
+        let byte = content[index];
+        match byte {
+            byte if byte.is_ascii_whitespace() => index += 1,
+            b'%' => index += content[index..].iter().position(|&byte| byte == b'\n' || byte == b'\r').unwrap_or(content.len() - index),
+            b'(' => {
+                // strings nest parentheses and escape them with backslashes.
+                let mut depth = 0;
+                while index < content.len() {
+                    match content[index] {
+                        b'\\' => index += 1,
+                        b'(' => depth += 1,
+                        b')' => depth -= 1,
+                        _ => {}
+                    }
+                    index += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                tokens.push(Token::Other);
+            }
+            b'<' if content.get(index + 1) != Some(&b'<') => {
+                index += content[index..].iter().position(|&byte| byte == b'>').map_or(content.len() - index, |end| end + 1);
+                tokens.push(Token::Other);
+            }
+            b'<' | b'>' => {
+                index += 2;
+                tokens.push(Token::Other);
+            }
+            b'[' | b']' | b'{' | b'}' | b')' => {
+                index += 1;
+                tokens.push(Token::Other);
+            }
+            b'/' => {
+                let end = content[index + 1..].iter().position(|&byte| is_delimiter(byte)).map_or(content.len(), |end| index + 1 + end);
+                tokens.push(Token::Name(String::from_utf8_lossy(&content[index..end]).to_string()));
+                index = end;
+            }
+            _ => {
+                let end = content[index..].iter().position(|&byte| is_delimiter(byte)).map_or(content.len(), |end| index + end);
+                let word = String::from_utf8_lossy(&content[index..end.max(index + 1)]).to_string();
+                index = end.max(index + 1);
+                match word.parse::<f64>() {
+                    Ok(number) => tokens.push(Token::Number(number)),
+                    Err(_) => {
+                        // the data of an inline image runs from `ID` to `EI`.
+                        if word == "ID" {
+                            index += 1;
+                            while index < content.len() && !(content[index..].starts_with(b"EI") && content[index - 1].is_ascii_whitespace() && content.get(index + 2).is_none_or(|&byte| is_delimiter(byte))) {
+                                index += 1;
+                            }
+                        }
+                        tokens.push(Token::Operator(word));
+                    }
+                }
+            }
+        }
+    }
+    tokens
+}
+
+/// An image placed on a page: its object, fill color for stencil masks, and the matrix that maps
+/// the unit square onto the page.
+struct Placement {
+    number: u32,
+    fill: [u8; 3],
+    matrix: Matrix,
+}
+
+/// What the content stream of a page draws.
+#[derive(Default)]
+struct Drawing {
+    placements: Vec<Placement>,
+    /// Whether there is text or vector graphics, which aren't rendered.
+    has_unrendered: bool,
+    inline_images: usize,
+}
+
+/// Graphics state kept by `q` and restored by `Q`, as far as drawing images needs it.
+#[derive(Clone, Copy)]
+struct GraphicsState {
+    matrix: Matrix,
+    fill: [u8; 3],
+    /// Whether text is drawn at all, not with render mode 3 as in the hidden OCR text of scans.
+    is_text_visible: bool,
+}
+
+/// Follow a content stream, collecting the images it places.
+fn interpret(document: &Document, content: &[u8], resources: &str, matrix: Matrix, depth: usize, drawing: &mut Drawing) {
+    let resources = match reference(resources) {
+        Some(number) => document.object(number).unwrap_or_default(),
+        None => resources.to_string(),
+    };
+    let xobjects = document.get(&resources, "/XObject").unwrap_or_default();
+    let xobjects = referenced_entries(&xobjects);
+    let mut state = GraphicsState { matrix, fill: [0, 0, 0], is_text_visible: true };
+    let mut saved = Vec::new();
+    let mut operands: Vec<f64> = Vec::new();
+    let mut name: Option<String> = None;
+    for token in tokens(content) {
+        let operator = match token {
+            Token::Number(number) => {
+                operands.push(number);
+                continue;
+            }
+            Token::Name(token) => {
+                name = Some(token);
+                continue;
+            }
+            Token::Other => continue,
+            Token::Operator(operator) => operator,
+        };
+        let to_byte = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let (operands, name) = (std::mem::take(&mut operands), name.take());
+        match (operator.as_str(), &operands[..]) {
+            ("q", _) => saved.push(state),
+            ("Q", _) => state = saved.pop().unwrap_or(state),
+            ("cm", &[a, b, c, d, e, f]) => state.matrix = multiply(&[a, b, c, d, e, f], &state.matrix),
+            ("Tr", &[mode]) => state.is_text_visible = mode != 3.0,
+            ("g" | "sc" | "scn", &[gray]) => state.fill = [to_byte(gray); 3],
+            ("rg" | "sc" | "scn", &[r, g, b]) => state.fill = [to_byte(r), to_byte(g), to_byte(b)],
+            ("k" | "sc" | "scn", &[c, m, y, k]) => state.fill = [c, m, y].map(|channel| to_byte((1.0 - channel) * (1.0 - k))),
+            ("Tj" | "TJ" | "'" | "\"", _) => drawing.has_unrendered |= state.is_text_visible,
+            ("S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" | "sh", _) => drawing.has_unrendered = true,
+            ("BI", _) => drawing.inline_images += 1,
+            ("Do", _) => {
+                let Some(number) = name.as_deref().and_then(|name| xobjects.iter().find(|(key, _)| key == name)).map(|(_, number)| *number) else {
+                    continue;
+                };
+                let Some((dictionary, data)) = document.stream(number) else {
+                    continue;
+                };
+                match value(&dictionary, "/Subtype") {
+                    Some("/Image") => drawing.placements.push(Placement { number, fill: state.fill, matrix: state.matrix }),
+                    Some("/Form") if depth < MAX_FORM_DEPTH => {
+                        let form_matrix = document.get(&dictionary, "/Matrix").map(|matrix| numbers(&matrix)).filter(|matrix| matrix.len() == 6);
+                        let form_matrix = form_matrix.map_or(IDENTITY, |matrix| [matrix[0], matrix[1], matrix[2], matrix[3], matrix[4], matrix[5]]);
+                        let form_resources = value(&dictionary, "/Resources").map_or(resources.clone(), str::to_string);
+                        if let Ok(content) = document.decompressed(&dictionary, data) {
+                            interpret(document, &content, &form_resources, multiply(&form_matrix, &state.matrix), depth + 1, drawing);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// An image placed on a page, decoded, with its soft mask if it has one.
+struct Decoded {
+    image: image::DynamicImage,
+    alpha: Option<image::GrayImage>,
+    is_mask: bool,
+}
+
+/// Decode an image XObject with its soft mask.
+fn decode_placed(document: &Document, number: u32) -> Result<Decoded, String> {
+    let (dictionary, data) = document.stream(number).ok_or("missing image")?;
+    let image = decode_image(document, &dictionary, data)?;
+    let is_mask = document.get(&dictionary, "/ImageMask").as_deref() == Some("true");
+    let alpha = value(&dictionary, "/SMask").and_then(reference).and_then(|mask| {
+        let (mask_dictionary, mask_data) = document.stream(mask)?;
+        let alpha = decode_image(document, &mask_dictionary, mask_data).ok()?.to_luma8();
+        match alpha.dimensions() == (image.width(), image.height()) {
+            true => Some(alpha),
+            false => Some(image::imageops::resize(&alpha, image.width(), image.height(), image::imageops::FilterType::Triangle)),
+        }
+    });
+    Ok(Decoded { image, alpha, is_mask })
+}
+
+/// The resolution an image is placed at, in pixels per inch across its larger side.
+fn placed_dpi(image: &image::DynamicImage, matrix: &Matrix) -> f64 {
+    let width_points = matrix[0].hypot(matrix[1]);
+    let height_points = matrix[2].hypot(matrix[3]);
+    (image.width() as f64 * 72.0 / width_points.max(f64::EPSILON)).max(image.height() as f64 * 72.0 / height_points.max(f64::EPSILON))
+}
+
+/// Draw an image onto the page through the matrix mapping the unit square onto the canvas.
+fn draw(canvas: &mut image::RgbaImage, decoded: &Decoded, fill: [u8; 3], matrix: &Matrix) {
+    let (width, height) = (decoded.image.width(), decoded.image.height());
+    // stencil masks are painted in the fill color where they are black.
+    let pixels: image::RgbaImage = match decoded.is_mask {
+        true => image::ImageBuffer::from_fn(width, height, |x, y| {
+            let painted = decoded.image.as_luma8().map_or(0, |mask| 255 - mask.get_pixel(x, y).0[0]);
+            image::Rgba([fill[0], fill[1], fill[2], painted])
+        }),
+        false => {
+            let mut pixels = decoded.image.to_rgba8();
+            if let Some(alpha) = &decoded.alpha {
+                pixels.pixels_mut().zip(alpha.pixels()).for_each(|(pixel, alpha)| pixel.0[3] = alpha.0[0]);
+            }
+            pixels
+        }
+    };
+    let determinant = matrix[0] * matrix[3] - matrix[1] * matrix[2];
+    if determinant.abs() < f64::EPSILON {
+        return;
+    }
+    // the bounds of the placed unit square on the canvas.
+    let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)].map(|(u, v)| (matrix[0] * u + matrix[2] * v + matrix[4], matrix[1] * u + matrix[3] * v + matrix[5]));
+    let (left, right) = corners.iter().fold((f64::MAX, f64::MIN), |(low, high), corner| (low.min(corner.0), high.max(corner.0)));
+    let (top, bottom) = corners.iter().fold((f64::MAX, f64::MIN), |(low, high), corner| (low.min(corner.1), high.max(corner.1)));
+
+    // upright placements, as nearly all pages of scans are, are resampled as a whole.
+    if matrix[1].abs() < 1e-9 && matrix[2].abs() < 1e-9 {
+        let (x, y) = (left.round(), top.round());
+        let (target_width, target_height) = ((right.round() - x).max(1.0) as u32, (bottom.round() - y).max(1.0) as u32);
+        let mut placed = match (target_width, target_height) == (width, height) {
+            true => pixels,
+            false => image::imageops::resize(&pixels, target_width, target_height, image::imageops::FilterType::Triangle),
+        };
+        // images are drawn with their first row at the top of the unit square.
+        if matrix[0] < 0.0 {
+            placed = image::imageops::flip_horizontal(&placed);
+        }
+        if matrix[3] > 0.0 {
+            placed = image::imageops::flip_vertical(&placed);
+        }
+        image::imageops::overlay(canvas, &placed, x as i64, y as i64);
+        return;
+    }
+    // others are sampled pixel by pixel through the inverse matrix.
+    let (start_x, end_x) = (left.floor().max(0.0) as u32, (right.ceil().max(0.0) as u32).min(canvas.width()));
+    let (start_y, end_y) = (top.floor().max(0.0) as u32, (bottom.ceil().max(0.0) as u32).min(canvas.height()));
+    for y in start_y..end_y {
+        for x in start_x..end_x {
+            let (dx, dy) = (x as f64 + 0.5 - matrix[4], y as f64 + 0.5 - matrix[5]);
+            let u = (dx * matrix[3] - dy * matrix[2]) / determinant;
+            let v = (dy * matrix[0] - dx * matrix[1]) / determinant;
+            if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                continue;
+            }
+            let source = pixels.get_pixel((u * width as f64) as u32, ((1.0 - v) * height as f64).min(height as f64 - 1.0) as u32);
+            image::Pixel::blend(canvas.get_pixel_mut(x, y), source);
+        }
+    }
+}
+
+/// Numbers of the pages with content that isn't rendered, warned about once per PDF.
+#[derive(Default)]
+struct Unrendered {
+    text_or_vectors: Vec<usize>,
+    inline_images: Vec<usize>,
+}
+
+/// Render the images of a page onto a white page, at `dpi` or the resolution of its largest
+/// image. `Err` with the reason when the page has no image to draw.
+fn render_page(document: &Document, page: &Page, dpi: Option<f64>, path: &std::path::Path, number: usize, unrendered: &mut Unrendered) -> Result<image::DynamicImage, String> {
+    let mut drawing = Drawing::default();
+    interpret(document, &page.contents, &page.resources, IDENTITY, 0, &mut drawing);
+    if drawing.has_unrendered {
+        unrendered.text_or_vectors.push(number);
+    }
+    if drawing.inline_images > 0 {
+        unrendered.inline_images.push(number);
+    }
+    let mut images: std::collections::HashMap<u32, Decoded> = std::collections::HashMap::new();
+    let mut placements = Vec::new();
+    for placement in &drawing.placements {
+        if let std::collections::hash_map::Entry::Vacant(entry) = images.entry(placement.number) {
+            match decode_placed(document, placement.number) {
+                Ok(decoded) => {
+                    entry.insert(decoded);
+                }
+                Err(reason) => {
+                    log!("Warning: leaving out an image of page {} of {}: {}", number, path.display(), reason);
+                    continue;
+                }
+            }
+        }
+        placements.push(placement);
+    }
+    if placements.is_empty() {
+        return Err("no images".to_string());
+    }
+
+    let area_of = |matrix: &Matrix| (matrix[0] * matrix[3] - matrix[1] * matrix[2]).abs();
+    let dpi = dpi.unwrap_or_else(|| {
+        let largest = placements.iter().max_by(|a, b| area_of(&a.matrix).total_cmp(&area_of(&b.matrix))).unwrap();
+        placed_dpi(&images[&largest.number].image, &largest.matrix)
+    });
+    let scale = dpi / 72.0;
+    let [left, bottom, right, top] = page.area;
+    let (width, height) = (((right - left) * scale).round().max(1.0) as u32, ((top - bottom) * scale).round().max(1.0) as u32);
+    if width as u64 * height as u64 > MAX_PAGE_PIXELS {
+        return Err(format!("{}x{} pixels at {:.0} dpi are too many", width, height, dpi));
+    }
+    // from points, with the origin at the bottom left, to pixels from the top left.
+    let to_canvas = [scale, 0.0, 0.0, -scale, -left * scale, top * scale];
+    let mut canvas = image::RgbaImage::from_pixel(width, height, image::Rgba([255, 255, 255, 255]));
+    for placement in &placements {
+        draw(&mut canvas, &images[&placement.number], placement.fill, &multiply(&placement.matrix, &to_canvas));
+    }
+    let canvas = image::DynamicImage::ImageRgba8(canvas);
+    let canvas = match page.turns {
+        1 => canvas.rotate90(),
+        2 => canvas.rotate180(),
+        3 => canvas.rotate270(),
+        _ => canvas,
+    };
+    // pages of gray scans stay gray.
+    let is_gray = placements.iter().all(|placement| {
+        let decoded = &images[&placement.number];
+        match decoded.is_mask {
+            true => placement.fill.iter().all(|&channel| channel == placement.fill[0]),
+            false => matches!(decoded.image.color(), image::ColorType::L8 | image::ColorType::L16),
+        }
+    });
+    Ok(match is_gray {
+        true => image::DynamicImage::ImageLuma8(canvas.to_luma8()),
+        false => image::DynamicImage::ImageRgb8(canvas.to_rgb8()),
+    })
+}
+
+/// Object numbers referenced by a key in any dictionary, e.g. the soft masks of images, which
+/// aren't pages of their own.
+fn referenced_objects(objects: &[StreamObject], key: &str) -> std::collections::HashSet<u32> {
+    objects.iter().filter_map(|object| value(&object.dictionary, key)).filter_map(reference).collect()
+}
+
+/// Every image a PDF stores that isn't the mask or thumbnail of another, in the order they are
+/// stored, for PDFs without a page tree.
+fn stored_images(document: &Document, path: &std::path::Path) -> Vec<image::DynamicImage> {
+    let objects = stream_objects(document.bytes);
+    let mut hidden = referenced_objects(&objects, "/SMask");
+    hidden.extend(referenced_objects(&objects, "/Mask"));
+    hidden.extend(referenced_objects(&objects, "/Thumb"));
+    let mut images = Vec::new();
+    for object in &objects {
+        if value(&object.dictionary, "/Subtype") != Some("/Image") || value(&object.dictionary, "/ImageMask") == Some("true") {
+            continue;
+        }
+        // an incremental update may store a newer version of an object further on.
+        if object.number.is_some_and(|number| hidden.contains(&number) || document.starts.get(&number).is_some_and(|start| *start > object.data_start)) {
+            continue;
+        }
+        match decode_image(document, &object.dictionary, document.stream_data(&object.dictionary, object.data_start)) {
+            Ok(image) => images.push(image),
+            Err(reason) => log!("Warning: skipping an image of {}: {}", path.display(), reason),
+        }
+    }
+    images
+}
+
+/// The pages of a PDF with their numbers, in the order of its page tree, drawn at `dpi` or at the
+/// resolution of their largest image. Pages without an image that can be drawn are warned about
+/// and left out. PDFs without a page tree have every image they store taken as a page.
+pub fn pages(path: &std::path::Path, bytes: &[u8], dpi: Option<f64>) -> Vec<(usize, image::DynamicImage)> {
+    let document = Document::new(bytes);
+    let mut tree = Vec::new();
+    if let Some(root) = page_tree_root(&document) {
+        collect_pages(&document, root, &Inherited::default(), &mut std::collections::HashSet::new(), &mut tree);
+    }
+    if tree.is_empty() {
+        return stored_images(&document, path).into_iter().enumerate().map(|(index, image)| (index + 1, image)).collect();
+    }
+    let mut pages = Vec::new();
+    let mut left_out = Vec::new();
+    let mut unrendered = Unrendered::default();
+    for (index, page) in tree.iter().enumerate() {
+        match render_page(&document, page, dpi, path, index + 1, &mut unrendered) {
+            Ok(image) => pages.push((index + 1, image)),
+            Err(reason) => left_out.push(format!("{} ({})", index + 1, reason)),
+        }
+    }
+    let list = |numbers: &[usize]| format!("{} {}", if numbers.len() == 1 { "page" } else { "pages" }, numbers.iter().map(usize::to_string).collect::<Vec<String>>().join(", "));
+    if !unrendered.text_or_vectors.is_empty() {
+        log!("Warning: {} has text or vector graphics on {}, only images are rendered.", path.display(), list(&unrendered.text_or_vectors));
+    }
+    if !unrendered.inline_images.is_empty() {
+        log!("Warning: {} has inline images on {}, which aren't supported and are left out.", path.display(), list(&unrendered.inline_images));
+    }
+    // without any the PDF fails as a whole.
+    if !pages.is_empty() && !left_out.is_empty() {
+        log!("Warning: leaving out pages of {} that can't be drawn: {}.", path.display(), left_out.join(", "));
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PDF of the given objects, numbered from 1, with the catalog as object 1.
+    fn pdf(objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"%PDF-1.5\n".to_vec();
+        for (index, object) in objects.iter().enumerate() {
+            bytes.extend_from_slice(format!("{} 0 obj\n", index + 1).as_bytes());
+            bytes.extend_from_slice(object);
+            bytes.extend_from_slice(b"\nendobj\n");
+        }
+        bytes.extend_from_slice(b"trailer << /Root 1 0 R >>\n%%EOF\n");
+        bytes
+    }
+
+    /// A stream object with the given dictionary entries.
+    fn stream(entries: &str, data: &[u8]) -> Vec<u8> {
+        let mut object = format!("<< {} /Length {} >>\nstream\n", entries, data.len()).into_bytes();
+        object.extend_from_slice(data);
+        object.extend_from_slice(b"\nendstream");
+        object
+    }
+
+    /// A Flate compressed RGB image of one color.
+    fn rgb_image(width: u32, height: u32, color: [u8; 3]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&color.repeat((width * height) as usize)).unwrap();
+        stream(&format!("/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /FlateDecode", width, height), &encoder.finish().unwrap())
+    }
+
+    fn page(media_box: &str, xobjects: &str, contents: u32) -> Vec<u8> {
+        format!("<< /Type /Page /Parent 2 0 R /MediaBox [{}] /Resources << /XObject << {} >> >> /Contents {} 0 R >>", media_box, xobjects, contents).into_bytes()
+    }
+
+    fn rendered(bytes: &[u8], dpi: Option<f64>) -> Vec<(usize, image::DynamicImage)> {
+        pages(std::path::Path::new("test.pdf"), bytes, dpi)
+    }
+
+    #[test]
+    fn pages_follow_the_page_tree() {
+        // the second page is stored first.
+        let bytes = pdf(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [4 0 R 3 0 R] /Count 2 >>".to_vec(),
+            page("0 0 4 4", "/Im0 6 0 R", 5),
+            page("0 0 4 4", "/Im0 7 0 R", 5),
+            stream("", b"q 4 0 0 4 0 0 cm /Im0 Do Q"),
+            rgb_image(4, 4, [0, 0, 255]),
+            rgb_image(4, 4, [255, 0, 0]),
+        ]);
+        let pages = rendered(&bytes, None);
+        let colors: Vec<(usize, [u8; 3])> = pages.iter().map(|(number, page)| (*number, page.to_rgb8().get_pixel(0, 0).0)).collect();
+        assert_eq!(colors, vec![(1, [255, 0, 0]), (2, [0, 0, 255])]);
+    }
+
+    #[test]
+    fn images_of_a_page_are_drawn_onto_it() {
+        // two halves of 10x10 pixels each on a page of 20x10 points, the right one flipped.
+        let bytes = pdf(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+            page("0 0 20 10", "/Left 5 0 R /Right 6 0 R", 4),
+            stream("", b"q 10 0 0 10 0 0 cm /Left Do Q q -10 0 0 10 20 0 cm /Right Do Q"),
+            rgb_image(10, 10, [255, 0, 0]),
+            rgb_image(10, 10, [0, 255, 0]),
+        ]);
+        let pages = rendered(&bytes, None);
+        assert_eq!(pages.len(), 1);
+        let page = pages[0].1.to_rgb8();
+        assert_eq!(page.dimensions(), (20, 10));
+        assert_eq!(page.get_pixel(2, 5).0, [255, 0, 0]);
+        assert_eq!(page.get_pixel(17, 5).0, [0, 255, 0]);
+    }
+
+    #[test]
+    fn pages_are_drawn_at_the_dpi_and_turned() {
+        let bytes = pdf(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 /Rotate 90 >>".to_vec(),
+            page("0 0 72 36", "/Im0 5 0 R", 4),
+            stream("", b"q 72 0 0 36 0 0 cm /Im0 Do Q"),
+            rgb_image(20, 10, [255, 0, 0]),
+        ]);
+        // by default the resolution of the image, 20 pixels per inch.
+        let page = &rendered(&bytes, None)[0].1;
+        assert_eq!((page.width(), page.height()), (10, 20));
+        let page = &rendered(&bytes, Some(144.0))[0].1;
+        assert_eq!((page.width(), page.height()), (72, 144));
+    }
+
+    #[test]
+    fn pages_without_images_are_left_out() {
+        let bytes = pdf(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 /MediaBox [0 0 4 4] >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Contents 5 0 R >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /Resources << /XObject << /Im0 6 0 R >> >> /Contents 7 0 R >>".to_vec(),
+            stream("", b"BT /F1 12 Tf (Hello) Tj ET"),
+            rgb_image(4, 4, [255, 0, 0]),
+            stream("", b"q 4 0 0 4 0 0 cm /Im0 Do Q"),
+        ]);
+        let numbers: Vec<usize> = rendered(&bytes, None).iter().map(|(number, _)| *number).collect();
+        assert_eq!(numbers, vec![2]);
+    }
+
+    #[test]
+    fn objects_in_object_streams_are_found() {
+        use std::io::Write;
+        let objects = ["<< /Type /Catalog /Pages 2 0 R >>", "<< /Type /Pages /Kids [3 0 R] /Count 1 >>"];
+        let offsets = [0, objects[0].len() + 1];
+        let header = format!("1 {} 2 {} ", offsets[0], offsets[1]);
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(format!("{}{} {}", header, objects[0], objects[1]).as_bytes()).unwrap();
+        let object_stream = stream(&format!("/Type /ObjStm /N 2 /First {} /Filter /FlateDecode", header.len()), &encoder.finish().unwrap());
+        // the object stream takes the place of objects 1 and 2.
+        let mut bytes = pdf(&[b"null".to_vec(), b"null".to_vec(), page("0 0 4 4", "/Im0 5 0 R", 4), stream("", b"4 0 0 4 0 0 cm /Im0 Do"), rgb_image(4, 4, [0, 0, 255])]);
+        for number in ["1", "2"] {
+            let header = format!("{} 0 obj\nnull\nendobj\n", number);
+            let start = bytes.windows(header.len()).position(|window| window == header.as_bytes()).unwrap();
+            bytes.drain(start..start + header.len());
+        }
+        bytes.extend_from_slice(b"6 0 obj\n");
+        bytes.extend_from_slice(&object_stream);
+        bytes.extend_from_slice(b"\nendobj\n");
+        let pages = rendered(&bytes, None);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].1.to_rgb8().get_pixel(0, 0).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn decode_arrays_invert_samples() {
+        let dictionary = "<< /Width 2 /Height 1 /ColorSpace /DeviceGray /BitsPerComponent 8 /Decode [1 0] >>";
+        let document = Document::new(b"");
+        let image = decode_image(&document, dictionary, &[0, 200]).unwrap();
+        assert_eq!(image.to_luma8().into_raw(), vec![255, 55]);
+    }
+
+    #[test]
+    fn fax_images_are_decoded() {
+        // a black square in the middle of a white 16x4 image.
+        let rows: Vec<Vec<bool>> = (0..4).map(|y| (0..16).map(|x| (1..3).contains(&y) && (6..10).contains(&x)).collect()).collect();
+        let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+        for row in &rows {
+            encoder.encode_line(row.iter().map(|&black| if black { fax::Color::Black } else { fax::Color::White }), 16).unwrap();
+        }
+        let data = encoder.finish().unwrap().finish();
+        let dictionary = "<< /Width 16 /Height 4 /ColorSpace /DeviceGray /BitsPerComponent 1 /Filter /CCITTFaxDecode /DecodeParms << /K -1 /Columns 16 >> >>";
+        let image = decode_image(&Document::new(b""), dictionary, &data).unwrap().to_luma8();
+        let expected: Vec<u8> = rows.iter().flatten().map(|&black| if black { 0 } else { 255 }).collect();
+        assert_eq!(image.into_raw(), expected);
+    }
+
+    #[test]
+    fn unsupported_codecs_are_named() {
+        let dictionary = "<< /Width 1 /Height 1 /ColorSpace /DeviceGray /BitsPerComponent 1 /Filter /JBIG2Decode >>";
+        assert_eq!(decode_image(&Document::new(b""), dictionary, &[]).err().unwrap(), "JBIG2 images aren't supported");
+    }
+}
@@ -0,0 +1,126 @@
+// Perceptual quality targets for lossy encoders.
+//
+// Instead of a fixed quality number, `--output-quality-auto ssim=0.98` searches for the lowest
+// quality whose re-decoded output still has at least the given SSIM against the original.
+// The same metrics tell whether outputs still match a `--baseline`.
+
+/// Parse a quality target like `ssim=0.98`.
+pub fn parse_target(value: &str) -> f64 {
+    match value.split_once('=').map(|(metric, threshold)| (metric, threshold.parse::<f64>())) {
+        Some(("ssim", Ok(threshold))) if threshold > 0.0 && threshold <= 1.0 => threshold,
+        _ => panic!("Invalid quality target (expected ssim=0.0-1.0): {}", value),
+    }
+}
+
+/// How close an output has to stay to its `--baseline`, as a lowest SSIM or PSNR.
+#[derive(Copy, Clone)]
+pub enum Threshold {
+    Ssim(f64),
+    /// In decibels.
+    Psnr(f64),
+}
+
+/// Parse a baseline threshold like `ssim=0.99` or `psnr=40`.
+pub fn parse_threshold(value: &str) -> Threshold {
+    match value.split_once('=').map(|(metric, threshold)| (metric, threshold.parse::<f64>())) {
+        Some(("ssim", Ok(threshold))) if threshold > 0.0 && threshold <= 1.0 => Threshold::Ssim(threshold),
+        Some(("psnr", Ok(threshold))) if threshold > 0.0 => Threshold::Psnr(threshold),
+        _ => panic!("Invalid baseline threshold (expected ssim=0.0-1.0 or psnr={{decibels}}): {}", value),
+    }
+}
+
+impl Threshold {
+    /// The metric of two images with the same dimensions, by name, with whether it reaches the
+    /// threshold.
+    pub fn measure(&self, original: &image::DynamicImage, candidate: &image::DynamicImage) -> (&'static str, f64, bool) {
+        match *self {
+            Threshold::Ssim(threshold) => {
+                let ssim = ssim(original, candidate);
+                ("ssim", ssim, ssim >= threshold)
+            }
+            Threshold::Psnr(threshold) => {
+                let psnr = psnr(original, candidate);
+                ("psnr", psnr, psnr >= threshold)
+            }
+        }
+    }
+}
+
+/// Peak signal-to-noise ratio over the RGBA channels of two images with the same dimensions, in
+/// decibels, infinite for identical images.
+pub fn psnr(original: &image::DynamicImage, candidate: &image::DynamicImage) -> f64 {
+    let (original, candidate) = (original.to_rgba8(), candidate.to_rgba8());
+    let squares: u64 = original.as_raw().iter().zip(candidate.as_raw()).map(|(a, b)| (a.abs_diff(*b) as u64).pow(2)).sum();
+    let mse = squares as f64 / original.as_raw().len().max(1) as f64;
+    if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0 * 255.0 / mse).log10() }
+}
+
+/// Mean structural similarity of the luma of two images with the same dimensions, from 0 to 1.
+///
+/// Computed over 8x8 windows that overlap by half.
+pub fn ssim(original: &image::DynamicImage, candidate: &image::DynamicImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let original = original.to_luma8();
+    let candidate = candidate.to_luma8();
+    let (width, height) = original.dimensions();
+    // images smaller than a window are compared as a single window.
+    let (window_width, window_height) = (WINDOW.min(width), WINDOW.min(height));
+    let mut total = 0.0;
+    let mut windows = 0;
+    let mut y = 0;
+    while y + window_height <= height {
+        let mut x = 0;
+        while x + window_width <= width {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for dy in 0..window_height {
+                for dx in 0..window_width {
+                    let a = original.get_pixel(x + dx, y + dy)[0] as f64;
+                    let b = candidate.get_pixel(x + dx, y + dy)[0] as f64;
+                    sum_a += a;
+                    sum_b += b;
+                    sum_aa += a * a;
+                    sum_bb += b * b;
+                    sum_ab += a * b;
+                }
+            }
+            let n = (window_width * window_height) as f64;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let variance_a = sum_aa / n - mean_a * mean_a;
+            let variance_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+            x += (window_width / 2).max(1);
+        }
+        y += (window_height / 2).max(1);
+    }
+    total / windows as f64
+}
+
+/// Encode with the lowest quality (1-100) whose output reaches the SSIM target, returning the
+/// quality and the encoded bytes.
+///
+/// Falls back to quality 100 when no quality reaches the target. SSIM is assumed to grow with
+/// the quality, so the search is a bisection.
+pub fn lowest_quality(image: &image::DynamicImage, target: f64, encode: impl Fn(u8) -> Vec<u8>) -> (u8, Vec<u8>) {
+    let (mut low, mut high) = (1, 100);
+    let mut best = None;
+    while low <= high {
+        let quality = (low + high) / 2;
+        let bytes = encode(quality);
+        let reaches_target = match image::load_from_memory(&bytes) {
+            Ok(candidate) => ssim(image, &candidate) >= target,
+            Err(_) => false,
+        };
+        if reaches_target {
+            best = Some((quality, bytes));
+            high = quality - 1;
+        } else {
+            low = quality + 1;
+        }
+    }
+    best.unwrap_or_else(|| (100, encode(100)))
+}
@@ -0,0 +1,264 @@
+// The atlas task.
+
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, open_image, parse_dimensions, parse_save_args, process_directory, save_image, write_output};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "max", values: "{width}x{height}, largest atlas", default: "2048x2048" },
+    OptionSpec { key: "padding", values: "{pixels} between sprites", default: "2" },
+    OptionSpec { key: "name", values: "file name of the atlas and its map, without extension", default: "atlas" },
+];
+
+/// A rectangle of the atlas, in pixels.
+#[derive(Copy, Clone)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.y >= self.y && other.right() <= self.right() && other.bottom() <= self.bottom()
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        other.x < self.right() && self.x < other.right() && other.y < self.bottom() && self.y < other.bottom()
+    }
+}
+
+/// Rectangle packing by MaxRects: the free area is kept as maximal, possibly overlapping,
+/// rectangles and every sprite goes where it leaves the shortest leftover side.
+struct MaxRects {
+    free: Vec<Rect>,
+}
+
+impl MaxRects {
+    fn new(width: u32, height: u32) -> MaxRects {
+        MaxRects { free: vec![Rect { x: 0, y: 0, width, height }] }
+    }
+
+    /// Place a rectangle of the given size, `None` when it doesn't fit anymore.
+    fn insert(&mut self, width: u32, height: u32) -> Option<Rect> {
+        let placed = self
+            .free
+            .iter()
+            .filter(|free| free.width >= width && free.height >= height)
+            .min_by_key(|free| ((free.width - width).min(free.height - height), (free.width - width).max(free.height - height), free.y, free.x))
+            .map(|free| Rect { x: free.x, y: free.y, width, height })?;
+
+        // split every free rectangle the placed one overlaps into the parts around it.
+        let mut free = Vec::with_capacity(self.free.len() + 4);
+        for rect in &self.free {
+            if !rect.intersects(&placed) {
+                free.push(*rect);
+                continue;
+            }
+            if placed.x > rect.x {
+                free.push(Rect { width: placed.x - rect.x, ..*rect });
+            }
+            if placed.right() < rect.right() {
+                free.push(Rect { x: placed.right(), width: rect.right() - placed.right(), ..*rect });
+            }
+            if placed.y > rect.y {
+                free.push(Rect { height: placed.y - rect.y, ..*rect });
+            }
+            if placed.bottom() < rect.bottom() {
+                free.push(Rect { y: placed.bottom(), height: rect.bottom() - placed.bottom(), ..*rect });
+            }
+        }
+        // drop free rectangles that lie within others, keeping one of any duplicates.
+        let mut index = 0;
+        while index < free.len() {
+            let is_contained = free.iter().enumerate().any(|(other, rect)| other != index && rect.contains(&free[index]) && (other < index || !free[index].contains(rect)));
+            if is_contained {
+                free.swap_remove(index);
+            } else {
+                index += 1;
+            }
+        }
+        self.free = free;
+        Some(placed)
+    }
+}
+
+/// The frames of the atlas by sprite name, in the JSON hash layout most game engines and sprite
+/// tools import.
+fn atlas_map(frames: &[(String, Rect)], image_name: &str, size: (u32, u32)) -> serde_json::Value {
+    let frames: serde_json::Map<String, serde_json::Value> = frames
+        .iter()
+        .map(|(name, rect)| {
+            let frame = serde_json::json!({
+                "frame": { "x": rect.x, "y": rect.y, "w": rect.width, "h": rect.height },
+                "rotated": false,
+                "trimmed": false,
+                "sourceSize": { "w": rect.width, "h": rect.height },
+            });
+            (name.clone(), frame)
+        })
+        .collect();
+    serde_json::json!({
+        "frames": frames,
+        "meta": { "image": image_name, "size": { "w": size.0, "h": size.1 } },
+    })
+}
+
+/// Pack the images in a directory into a single texture atlas, a PNG with every image at 1:1,
+/// and a JSON map of their rectangles, both written into `--dest`. Sprites are named by their
+/// path relative to the source, biggest are packed first.
+///
+///  # Supported Options
+///
+///  ## max
+///  - 2048x2048 (default), the largest the atlas may get, it is cropped to what the sprites use
+///
+///  ## padding
+///  - 2 (default), transparent pixels between sprites, against bleeding when filtered
+///
+///  ## name
+///  - atlas (default), writes `atlas.png` and `atlas.json`
+///
+///  Sprites that don't fit are warned about and listed under `overflow` in the map. Trim them
+///  first with `trim-alpha` to waste less space.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, of which only the PNG ones and
+///  premultiply apply to atlases.
+pub struct Atlas;
+
+impl Task for Atlas {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn splits_pages(&self) -> bool {
+        false
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let max_value = options.get("max").copied().unwrap_or("2048x2048");
+        let max = parse_dimensions(max_value).unwrap_or_else(|| panic!("Invalid max: {}", max_value));
+        let padding = match options.get("padding") {
+            None => 2,
+            Some(padding_value) => padding_value.parse::<u32>().unwrap_or_else(|_| panic!("Invalid padding: {}", padding_value)),
+        };
+        let name = options.get("name").copied().unwrap_or("atlas");
+        if name.is_empty() || name.contains(['/', '\\']) {
+            panic!("Invalid name: {}", name);
+        }
+        let Some(dest_path) = &run.dest_path else {
+            panic!("The atlas task needs a --dest to write the atlas to");
+        };
+        let sprites = std::cell::RefCell::new(Vec::new());
+
+        let summary = process_directory(source_path.clone(), run, |path, _| {
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            let sprite_name = path.strip_prefix(&source_path).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+            sprites.borrow_mut().push((path, sprite_name, image.to_rgba8()));
+            Outcome::Inspected
+        });
+
+        let mut sprites = sprites.into_inner();
+        if sprites.is_empty() {
+            println!("No images to pack.");
+            return summary;
+        }
+        sprites.sort_by_key(|(_, sprite_name, sprite)| (std::cmp::Reverse((sprite.width().max(sprite.height()), sprite.width() * sprite.height())), sprite_name.clone()));
+
+        // every sprite takes its padding to the right and bottom, which may hang over the edge.
+        let mut packer = MaxRects::new(max.0 + padding, max.1 + padding);
+        let mut frames = Vec::new();
+        let mut overflow = Vec::new();
+        for (index, (_, sprite_name, sprite)) in sprites.iter().enumerate() {
+            match packer.insert(sprite.width() + padding, sprite.height() + padding) {
+                Some(rect) => frames.push((index, Rect { width: sprite.width(), height: sprite.height(), ..rect })),
+                None => {
+                    log!("Warning: {} ({}x{}) doesn't fit into the {}x{} atlas.", sprite_name, sprite.width(), sprite.height(), max.0, max.1);
+                    overflow.push(sprite_name.clone());
+                }
+            }
+        }
+        if frames.is_empty() {
+            panic!("No image fits into the {}x{} atlas", max.0, max.1);
+        }
+
+        let size = frames.iter().fold((0, 0), |(width, height), (_, rect)| (width.max(rect.right()), height.max(rect.bottom())));
+        let mut atlas = image::RgbaImage::new(size.0, size.1);
+        for (index, rect) in &frames {
+            image::imageops::replace(&mut atlas, &sprites[*index].2, rect.x as i64, rect.y as i64);
+        }
+        let image_name = format!("{}.png", name);
+        let named_frames: Vec<(String, Rect)> = frames.iter().map(|(index, rect)| (sprites[*index].1.clone(), *rect)).collect();
+        let mut map = atlas_map(&named_frames, &image_name, size);
+        if !overflow.is_empty() {
+            map["overflow"] = overflow.clone().into();
+        }
+
+        // the atlas takes the permissions and owner of its first sprite.
+        let first_path = &sprites[frames[0].0].0;
+        save_image(&image::DynamicImage::ImageRgba8(atlas), first_path, &dest_path.join(&image_name), run, &parse_save_args(&options));
+        write_output(first_path, &dest_path.join(format!("{}.json", name)), serde_json::to_vec_pretty(&map).unwrap(), run);
+        println!("Packed {} images into a {}x{} atlas, {} didn't fit.", frames.len(), size.0, size.1, overflow.len());
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_rects_places_without_overlap() {
+        let bounds = Rect { x: 0, y: 0, width: 256, height: 256 };
+        let mut packer = MaxRects::new(bounds.width, bounds.height);
+        let mut placed: Vec<Rect> = Vec::new();
+        // sizes of 1-40 pixels from a linear congruential generator, largest first as the task
+        // packs them.
+        let mut seed = 12345u32;
+        let mut sizes: Vec<(u32, u32)> = (0..60)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (1 + (seed >> 16) % 40, 1 + (seed >> 8) % 40)
+            })
+            .collect();
+        sizes.sort_by_key(|&(width, height)| std::cmp::Reverse((width.max(height), width * height)));
+        for (width, height) in sizes {
+            let rect = packer.insert(width, height).unwrap();
+            assert_eq!((rect.width, rect.height), (width, height));
+            assert!(bounds.contains(&rect));
+            assert!(placed.iter().all(|other| !other.intersects(&rect)));
+            placed.push(rect);
+        }
+    }
+
+    #[test]
+    fn max_rects_fills_exactly_then_overflows() {
+        let mut packer = MaxRects::new(100, 100);
+        let mut corners: Vec<(u32, u32)> = (0..4).map(|_| packer.insert(50, 50).map(|rect| (rect.x, rect.y)).unwrap()).collect();
+        corners.sort();
+        assert_eq!(corners, vec![(0, 0), (0, 50), (50, 0), (50, 50)]);
+        assert!(packer.insert(1, 1).is_none());
+    }
+
+    #[test]
+    fn max_rects_skips_what_doesnt_fit() {
+        let mut packer = MaxRects::new(100, 50);
+        assert!(packer.insert(101, 10).is_none());
+        assert!(packer.insert(10, 51).is_none());
+        // a rectangle that doesn't fit takes no space.
+        let rect = packer.insert(100, 50).unwrap();
+        assert_eq!((rect.x, rect.y), (0, 0));
+        assert!(packer.insert(60, 10).is_none());
+    }
+}
@@ -0,0 +1,119 @@
+// The blurhash task.
+
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, open_image, parse_dimensions, process_directory};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "components", values: "{x}x{y}, 1-9 each", default: "4x3" },
+];
+
+/// Encode a value as `length` base 83 digits, as BlurHash does.
+fn base83(value: u32, length: u32) -> String {
+    const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+    (1..=length).map(|i| DIGITS[(value / 83u32.pow(length - i) % 83) as usize] as char).collect()
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+/// The BlurHash of an image with `components` cosine components across and down.
+///
+/// The image is shrunk first, the hash only keeps its lowest frequencies anyway.
+fn blurhash(image: &image::DynamicImage, components: (u32, u32)) -> String {
+    let small = image.thumbnail(32, 32).to_rgb8();
+    let (width, height) = small.dimensions();
+    let linear: Vec<[f64; 3]> = small.pixels().map(|p| [srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2])]).collect();
+
+    // project the image onto each cosine basis function.
+    let mut factors = Vec::with_capacity((components.0 * components.1) as usize);
+    for j in 0..components.1 {
+        for i in 0..components.0 {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis = normalisation
+                        * (std::f64::consts::PI * i as f64 * x as f64 / width as f64).cos()
+                        * (std::f64::consts::PI * j as f64 * y as f64 / height as f64).cos();
+                    let pixel = linear[(y * width + x) as usize];
+                    for channel in 0..3 {
+                        factor[channel] += basis * pixel[channel];
+                    }
+                }
+            }
+            let scale = 1.0 / (width * height) as f64;
+            factors.push(factor.map(|value| value * scale));
+        }
+    }
+
+    let (dc, ac) = factors.split_first().unwrap();
+    let mut hash = base83((components.0 - 1) + (components.1 - 1) * 9, 1);
+    let maximum = if ac.is_empty() {
+        hash.push_str(&base83(0, 1));
+        1.0
+    } else {
+        let actual = ac.iter().flatten().fold(0.0f64, |max, value| max.max(value.abs()));
+        let quantised = ((actual * 166.0 - 0.5).floor()).clamp(0.0, 82.0) as u32;
+        hash.push_str(&base83(quantised, 1));
+        (quantised + 1) as f64 / 166.0
+    };
+    hash.push_str(&base83((linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]), 4));
+    for factor in ac {
+        let quantise = |value: f64| {
+            let value = value / maximum;
+            (value.signum() * value.abs().sqrt() * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+        };
+        hash.push_str(&base83(quantise(factor[0]) * 19 * 19 + quantise(factor[1]) * 19 + quantise(factor[2]), 2));
+    }
+    hash
+}
+
+/// Compute the BlurHash placeholder string of images in a directory. Hashes are printed and added
+/// to the `--report`.
+///
+///  # Supported Options
+///
+///  ## components
+///  - {x}x{y}, cosine components across and down, 1-9 each (default 4x3)
+pub struct Blurhash;
+
+impl Task for Blurhash {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        OPTIONS.iter().collect()
+    }
+
+    fn splits_pages(&self) -> bool {
+        false
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let components_value = options.get("components").copied().unwrap_or("4x3");
+        let components = match parse_dimensions(components_value) {
+            Some((x, y)) if x <= 9 && y <= 9 => (x, y),
+            _ => panic!("Invalid components: {}", components_value),
+        };
+
+        process_directory(source_path, run, |path, _| {
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            let hash = blurhash(&image, components);
+            println!("{} {}", path.display(), hash);
+            run.report_field("blurhash", hash);
+            Outcome::Inspected
+        })
+    }
+}
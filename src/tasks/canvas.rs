@@ -0,0 +1,132 @@
+// The canvas task.
+
+use super::rotate::parse_color;
+use super::patches::{Pad, padded_index, parse_pad};
+use super::smart_thumb::parse_anchor;
+use super::{OptionSpec, Task};
+use crate::{RunArgs, Summary, is_high_depth, parse_dimensions, parse_save_args, process_directory, transform_image, widen_color, without_alpha};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "size", values: "{width}x{height}", default: "required" },
+    OptionSpec { key: "anchor", values: "center | top | bottom | left | right | top-left | top-right | bottom-left | bottom-right", default: "center" },
+    OptionSpec { key: "bg", values: "{rrggbb} | {rrggbbaa} | transparent", default: "transparent with alpha, white otherwise" },
+    OptionSpec { key: "pad", values: "constant | reflect | edge | wrap, fill for the added area", default: "constant" },
+];
+
+/// Place an image at 1:1 on a canvas of `size`, filled with `background` where the image doesn't
+/// reach and cropping what doesn't fit, with the content positioned by the anchor.
+pub fn resize_canvas(image: &image::DynamicImage, size: (u32, u32), anchor: (f64, f64), background: image::Rgba<u8>) -> image::DynamicImage {
+    let offset = |canvas: u32, side: u32, anchor: f64| ((canvas as f64 - side as f64) * anchor).round() as i64;
+    let x = offset(size.0, image.width(), anchor.0);
+    let y = offset(size.1, image.height(), anchor.1);
+    // 16-bit images are placed on a 16-bit canvas so they keep their precision.
+    let canvas = if is_high_depth(image) {
+        let mut canvas = image::ImageBuffer::from_pixel(size.0, size.1, widen_color(background));
+        image::imageops::replace(&mut canvas, &image.to_rgba16(), x, y);
+        image::DynamicImage::ImageRgba16(canvas)
+    } else {
+        let mut canvas = image::RgbaImage::from_pixel(size.0, size.1, background);
+        image::imageops::replace(&mut canvas, &image.to_rgba8(), x, y);
+        image::DynamicImage::ImageRgba8(canvas)
+    };
+
+    // keep images without alpha opaque when the background is.
+    if !image.color().has_alpha() && background[3] == 255 {
+        without_alpha(canvas)
+    } else {
+        canvas
+    }
+}
+
+/// Place an image at 1:1 on a canvas of `size` like `resize_canvas`, filling the area the image
+/// doesn't reach from its own pixels as `pad` says instead of a solid color.
+fn extend_canvas(image: &image::DynamicImage, size: (u32, u32), anchor: (f64, f64), pad: Pad) -> image::DynamicImage {
+    let offset = |canvas: u32, side: u32, anchor: f64| ((canvas as f64 - side as f64) * anchor).round() as i64;
+    let x = offset(size.0, image.width(), anchor.0);
+    let y = offset(size.1, image.height(), anchor.1);
+    // 16-bit images are extended at 16 bits so they keep their precision.
+    let canvas = if is_high_depth(image) {
+        image::DynamicImage::ImageRgba16(extended(&image.to_rgba16(), size, (x, y), pad))
+    } else {
+        image::DynamicImage::ImageRgba8(extended(&image.to_rgba8(), size, (x, y), pad))
+    };
+    // the added area comes from the image, so it is as opaque as the image.
+    if image.color().has_alpha() {
+        canvas
+    } else {
+        without_alpha(canvas)
+    }
+}
+
+/// The pixels of `extend_canvas`, with the image at `(x, y)`, in the pixel type of the image.
+fn extended<P: image::Pixel>(image: &image::ImageBuffer<P, Vec<P::Subpixel>>, size: (u32, u32), (x, y): (i64, i64), pad: Pad) -> image::ImageBuffer<P, Vec<P::Subpixel>> {
+    image::ImageBuffer::from_fn(size.0, size.1, |cx, cy| {
+        let sx = padded_index(cx as i64 - x, image.width(), pad).unwrap();
+        let sy = padded_index(cy as i64 - y, image.height(), pad).unwrap();
+        *image.get_pixel(sx, sy)
+    })
+}
+
+/// Change the canvas size of images in a directory without scaling them: a bigger canvas pads
+/// them with the background, a smaller one crops them.
+///
+///  # Supported Options
+///
+///  ## size (Required)
+///  - {width}x{height}
+///
+///  ## anchor
+///  - center (default)
+///  - top, bottom, left, right
+///  - top-left, top-right, bottom-left, bottom-right
+///
+///  ## bg
+///  - {rrggbb} or {rrggbbaa}, fill for the added area
+///  - transparent (default for images with alpha, white otherwise)
+///
+///  ## pad
+///  - constant (default), fill the added area with the background
+///  - reflect, mirror the image into it
+///  - edge, repeat the edge pixels
+///  - wrap, continue with the opposite side of the image, as when it is tiled
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct Canvas;
+
+impl Task for Canvas {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let size_value = match options.get("size") {
+            Some(size_value) => size_value,
+            None => panic!("Missing required option: size"),
+        };
+        let size = parse_dimensions(size_value).unwrap_or_else(|| panic!("Invalid size: {}", size_value));
+        let anchor = parse_anchor(options.get("anchor").copied().unwrap_or("center"));
+        let background = options.get("bg").map(|bg| parse_color(bg));
+        let pad = match parse_pad(options.get("pad").copied().unwrap_or("constant")) {
+            Pad::Drop => panic!("Invalid pad: drop"),
+            pad => pad,
+        };
+        if pad != Pad::Zero && background.is_some() {
+            panic!("bg only applies to pad=constant");
+        }
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            transform_image(&path, &target_path, run, &save, |image| {
+                if pad != Pad::Zero {
+                    return extend_canvas(image, size, anchor, pad);
+                }
+                let background = background.unwrap_or(if image.color().has_alpha() {
+                    image::Rgba([0, 0, 0, 0])
+                } else {
+                    image::Rgba([255, 255, 255, 255])
+                });
+                resize_canvas(image, size, anchor, background)
+            })
+        })
+    }
+}
@@ -0,0 +1,130 @@
+// The color task.
+
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, median_cut, open_image, parse_save_args, process_directory, save_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "mode", values: "average | dominant", default: "average" },
+    OptionSpec { key: "k", values: "1-16, clusters for mode=dominant", default: "3" },
+    OptionSpec { key: "placeholder", values: "true | false, write a 1x1 PNG of the color", default: "false" },
+];
+
+/// Opaque-enough pixels of a small copy of an image, enough to judge its colors.
+fn color_samples(image: &image::DynamicImage) -> Vec<[u8; 3]> {
+    let small = image.thumbnail(64, 64).to_rgba8();
+    small.pixels().filter(|p| p[3] >= 128).map(|p| [p[0], p[1], p[2]]).collect()
+}
+
+/// Average of the sampled colors.
+fn average_color(samples: &[[u8; 3]]) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    for p in samples {
+        for channel in 0..3 {
+            sum[channel] += p[channel] as u64;
+        }
+    }
+    let count = samples.len().max(1) as u64;
+    [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8]
+}
+
+/// The colors of `k` clusters found with k-means, seeded with median cut, and how many samples
+/// each has. Largest cluster first.
+fn dominant_colors(samples: &[[u8; 3]], k: usize) -> Vec<([u8; 3], usize)> {
+    let distance = |a: &[u8; 3], b: &[u8; 3]| (0..3).map(|c| (a[c] as i32 - b[c] as i32).pow(2)).sum::<i32>();
+    let mut centers = median_cut(samples, k);
+    let mut clusters = vec![Vec::new(); centers.len()];
+    for _ in 0..10 {
+        clusters = vec![Vec::new(); centers.len()];
+        for sample in samples {
+            let nearest = (0..centers.len()).min_by_key(|&index| distance(sample, &centers[index])).unwrap();
+            clusters[nearest].push(*sample);
+        }
+        let moved: Vec<[u8; 3]> = clusters
+            .iter()
+            .zip(&centers)
+            .map(|(cluster, center)| if cluster.is_empty() { *center } else { average_color(cluster) })
+            .collect();
+        if moved == centers {
+            break;
+        }
+        centers = moved;
+    }
+    let mut colors: Vec<([u8; 3], usize)> = centers.into_iter().zip(clusters.iter().map(|cluster| cluster.len())).collect();
+    colors.sort_by_key(|color| std::cmp::Reverse(color.1));
+    colors
+}
+
+fn hex_color(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Find the average or dominant color of images in a directory, e.g. for placeholders shown while
+/// images load. Colors are printed and added to the `--report`.
+///
+///  # Supported Options
+///
+///  ## mode
+///  - average (default)
+///  - dominant, the largest of `k` k-means clusters
+///
+///  ## k
+///  - 1-16, number of clusters for mode=dominant (default 3)
+///
+///  ## placeholder
+///  - false (default)
+///  - true, also write a 1x1 PNG of the color as `name_placeholder.png`
+pub struct Color;
+
+impl Task for Color {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        OPTIONS.iter().collect()
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let dominant = match options.get("mode").copied() {
+            None | Some("average") => false,
+            Some("dominant") => true,
+            Some(mode) => panic!("Invalid mode: {}", mode),
+        };
+        let k = match options.get("k") {
+            None => 3,
+            Some(value) => match value.parse::<usize>() {
+                Ok(k) if (1..=16).contains(&k) => k,
+                _ => panic!("Invalid k: {}", value),
+            },
+        };
+        let placeholder = match options.get("placeholder").copied() {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(placeholder) => panic!("Invalid placeholder: {}", placeholder),
+        };
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            let samples = color_samples(&image);
+            let color = if dominant {
+                let colors = dominant_colors(&samples, k);
+                run.report_field("palette", colors.iter().map(|(color, _)| hex_color(*color)).collect::<Vec<_>>());
+                colors[0].0
+            } else {
+                average_color(&samples)
+            };
+            println!("{} {}", path.display(), hex_color(color));
+            run.report_field("color", hex_color(color));
+
+            if !placeholder {
+                return Outcome::Inspected;
+            }
+            // always a PNG, `--map` only changes outputs written in the format of their source.
+            let stem = target_path.file_stem().unwrap().to_string_lossy();
+            let placeholder_path = target_path.with_file_name(format!("{}_placeholder.png", stem));
+            let pixel = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb(color)));
+            save_image(&pixel, &path, &placeholder_path, run, &save);
+            Outcome::Processed
+        })
+    }
+}
@@ -0,0 +1,92 @@
+// The color-matrix task.
+
+use super::{OptionSpec, Task};
+use crate::{RunArgs, Summary, is_high_depth, parse_save_args, process_directory, transform_image};
+
+const OPTIONS: &[OptionSpec] = &[OptionSpec { key: "matrix", values: "\"a,b,c,d,e,f,g,h,i\" (3x3) or 12 values (3x4 with offsets)", default: "required" }];
+
+/// A color matrix by rows, each with the weights of red, green and blue and an offset.
+type Matrix = [[f32; 4]; 3];
+
+/// Parse a 3x3 matrix of 9 values, or a 3x4 matrix of 12 whose last column holds offsets as
+/// fractions of the full channel value. Values are separated by commas or whitespace.
+fn parse_matrix(value: &str) -> Matrix {
+    let values: Vec<f32> = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<f32>().ok().filter(|v| v.is_finite()).unwrap_or_else(|| panic!("Invalid matrix value: {}", part)))
+        .collect();
+    let columns = match values.len() {
+        9 => 3,
+        12 => 4,
+        count => panic!("Invalid matrix (expected 9 values for 3x3 or 12 for 3x4, got {}): {}", count, value),
+    };
+    let mut matrix = [[0.0; 4]; 3];
+    for (row, weights) in matrix.iter_mut().zip(values.chunks(columns)) {
+        row[..columns].copy_from_slice(weights);
+    }
+    matrix
+}
+
+/// Multiply every pixel of an RGBA buffer whose channels go up to `max` by the matrix, clamping
+/// the results. Alpha is kept as it is.
+fn apply_matrix<S: Copy + Into<f32>>(buffer: &mut image::ImageBuffer<image::Rgba<S>, Vec<S>>, matrix: &Matrix, max: f32, from_f32: impl Fn(f32) -> S)
+where
+    image::Rgba<S>: image::Pixel<Subpixel = S>,
+{
+    for pixel in buffer.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let (r, g, b) = (r.into() / max, g.into() / max, b.into() / max);
+        let channel = |row: &[f32; 4]| from_f32(((row[0] * r + row[1] * g + row[2] * b + row[3]) * max).round().clamp(0.0, max));
+        *pixel = image::Rgba([channel(&matrix[0]), channel(&matrix[1]), channel(&matrix[2]), a]);
+    }
+}
+
+fn color_matrix(image: &image::DynamicImage, matrix: &Matrix) -> image::DynamicImage {
+    let has_alpha = image.color().has_alpha();
+    // 16-bit images are multiplied at 16 bits so they keep their precision.
+    if is_high_depth(image) {
+        let mut buffer = image.to_rgba16();
+        apply_matrix(&mut buffer, matrix, 65535.0, |v| v as u16);
+        let image = image::DynamicImage::ImageRgba16(buffer);
+        return if has_alpha { image } else { image::DynamicImage::ImageRgb16(image.to_rgb16()) };
+    }
+    let mut buffer = image.to_rgba8();
+    apply_matrix(&mut buffer, matrix, 255.0, |v| v as u8);
+    let image = image::DynamicImage::ImageRgba8(buffer);
+    if has_alpha { image } else { image::DynamicImage::ImageRgb8(image.to_rgb8()) }
+}
+
+/// Adjust the colors of images in a directory with a color matrix, for channel mixing, tints and
+/// calibrated corrections. Each output channel is a weighted sum of the red, green and blue input
+/// channels plus an optional offset, clamped to the valid range.
+///
+///  # Supported Options
+///
+///  ## matrix (Required)
+///  - "a,b,c,d,e,f,g,h,i", a 3x3 matrix by rows: red = a*r + b*g + c*b, and so on
+///  - 12 values, a 3x4 matrix whose last column adds an offset (-1.0 to 1.0 of the full value)
+///
+///  Quote the matrix since options are separated by commas, or separate its values by spaces.
+///  Grayscale images come out as RGB.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct ColorMatrix;
+
+impl Task for ColorMatrix {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let matrix = match options.get("matrix") {
+            Some(matrix_value) => parse_matrix(matrix_value),
+            None => panic!("Missing required option: matrix"),
+        };
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            transform_image(&path, &target_path, run, &save, |image| color_matrix(image, &matrix))
+        })
+    }
+}
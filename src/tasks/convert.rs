@@ -0,0 +1,95 @@
+// The convert task.
+
+use super::{OptionSpec, Task};
+use crate::{RunArgs, Summary, mapped_target_path, parse_save_args, process_directory, remove_original, transform_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "format", values: "png | jpg | gif | bmp | tiff | webp | ...", default: "required without --map" },
+    OptionSpec { key: "keep_original", values: "true | false, when converting in place", default: "true" },
+    OptionSpec { key: "keep_extension", values: "true | false, append the new extension to the old one", default: "false" },
+];
+
+/// The target path with the extension of the new format appended to the name, e.g.
+/// `photo.jpg.webp`, to tell the format an image was converted from.
+fn with_appended_extension(target_path: &std::path::Path, converted_path: &std::path::Path) -> std::path::PathBuf {
+    match converted_path.extension() {
+        Some(extension) if target_path.extension() != Some(extension) => {
+            let mut name = target_path.file_name().unwrap().to_os_string();
+            name.push(".");
+            name.push(extension);
+            target_path.with_file_name(name)
+        }
+        _ => converted_path.to_path_buf(),
+    }
+}
+
+/// Convert images in a directory to another format.
+///
+///  # Supported Options
+///
+///  ## format (Required unless `--map` is given)
+///  - png, jpg, gif, bmp, tiff, webp, ...
+///
+///  ## keep_original
+///  - true (default)
+///  - false, remove the original after converting in place
+///
+///  ## keep_extension
+///  - false (default), the new extension replaces the old one: `photo.jpg` becomes `photo.webp`
+///  - true, the new extension is appended: `photo.jpg` becomes `photo.jpg.webp`
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+///  GIFs are quantized to `colors` with median cut and dithered unless `dither=off`.
+pub struct Convert;
+
+impl Task for Convert {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        // formats from --map take precedence, unmapped images keep their format without a format option.
+        let extension = match options.get("format") {
+            Some(format_value) => match image::ImageFormat::from_extension(format_value) {
+                Some(format) if format.can_write() => Some(format.extensions_str()[0]),
+                _ => panic!("Unsupported output format: {}", format_value),
+            },
+            None if !run.format_map.is_empty() => None,
+            None => panic!("Missing required option: format"),
+        };
+        let keep_original = match options.get("keep_original").copied() {
+            None | Some("true") => true,
+            Some("false") => false,
+            Some(keep_original) => panic!("Invalid keep_original: {}", keep_original),
+        };
+        let keep_extension = match options.get("keep_extension").copied() {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(keep_extension) => panic!("Invalid keep_extension: {}", keep_extension),
+        };
+        let save = parse_save_args(&options);
+        // appended extensions can't collide, replaced ones are made unique before any is written.
+        run.converted_extension.set(match keep_extension {
+            true => None,
+            false => Some(extension),
+        });
+
+        process_directory(source_path, run, |path, target_path| {
+            let converted_path = match extension {
+                Some(extension) => target_path.with_extension(extension),
+                None => target_path.clone(),
+            };
+            let converted_path = mapped_target_path(&path, &converted_path, run);
+            let target_path = match keep_extension {
+                true => with_appended_extension(&target_path, &converted_path),
+                false => converted_path,
+            };
+            let outcome = transform_image(&path, &target_path, run, &save, |image| image.clone());
+            // only originals converted in place are removed.
+            if !keep_original && run.dest_path.is_none() && target_path != path {
+                remove_original(&path, run);
+            }
+            outcome
+        })
+    }
+}
@@ -0,0 +1,148 @@
+// The deskew task.
+
+use super::rotate::{parse_color, rotate_image};
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, keep_original, mapped_target_path, open_image, parse_save_args, process_directory, save_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "max_angle", values: "0.1-45, largest skew in degrees", default: "10" },
+    OptionSpec { key: "bg", values: "{rrggbb} | {rrggbbaa} | transparent", default: "ffffff" },
+    OptionSpec { key: "crop", values: "true | false, keep the canvas size", default: "true" },
+];
+
+/// Estimate how far the lines of a scan are skewed, in degrees clockwise.
+///
+/// Edge pixels of near-horizontal structures (text baselines, table rules) vote in a Hough
+/// accumulator for every candidate angle within `max_angle`. The angle whose accumulator row is
+/// the most concentrated wins. Returns `None` when there are too few edges or the winner doesn't
+/// stand out from the other angles, so photos without dominant lines are left alone.
+fn detect_skew(image: &image::DynamicImage, max_angle: f64) -> Option<f64> {
+    // analyze a reduced grayscale copy, the angle doesn't depend on resolution.
+    let longest = image.width().max(image.height());
+    let gray = if longest > 1000 {
+        image.resize(1000, 1000, image::imageops::FilterType::Triangle).to_luma8()
+    } else {
+        image.to_luma8()
+    };
+    let (width, height) = gray.dimensions();
+    if width < 8 || height < 8 {
+        return None;
+    }
+
+    // collect strong edges whose gradient is mostly vertical, i.e. horizontal lines.
+    let at = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let mut magnitudes = Vec::new();
+    let mut total = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1) - at(x - 1, y - 1) - 2.0 * at(x - 1, y) - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1) - at(x - 1, y - 1) - 2.0 * at(x, y - 1) - at(x + 1, y - 1);
+            total += gx.abs().max(gy.abs());
+            if gy.abs() > gx.abs() {
+                magnitudes.push((x, y, gy.abs()));
+            }
+        }
+    }
+    let mean = total / ((width - 2) * (height - 2)) as f64;
+    let edges: Vec<(f64, f64)> = magnitudes
+        .iter()
+        .filter(|m| m.2 > (mean * 2.0).max(64.0))
+        .map(|m| (m.0 as f64, m.1 as f64))
+        .collect();
+    if edges.len() < 100 {
+        return None;
+    }
+
+    // vote for every angle in 0.1 degree steps and score how peaked each accumulator row is.
+    let steps = (max_angle * 10.0).round() as i64;
+    let diagonal = ((width * width + height * height) as f64).sqrt().ceil() as usize;
+    let mut scores = Vec::with_capacity(steps as usize * 2 + 1);
+    for step in -steps..=steps {
+        let angle = step as f64 / 10.0;
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let mut accumulator = vec![0u32; diagonal * 2 + 1];
+        for &(x, y) in &edges {
+            let rho = y * cos - x * sin;
+            accumulator[(rho.round() as i64 + diagonal as i64) as usize] += 1;
+        }
+        let score: f64 = accumulator.iter().map(|&votes| (votes as f64) * (votes as f64)).sum();
+        scores.push((angle, score));
+    }
+
+    let (angle, best) = scores.iter().copied().fold((0.0, 0.0), |best, score| if score.1 > best.1 { score } else { best });
+    let average = scores.iter().map(|score| score.1).sum::<f64>() / scores.len() as f64;
+    if best < average * 1.5 {
+        return None;
+    }
+    Some(angle)
+}
+
+/// Straighten skewed scans in a directory.
+///
+///  # Supported Options
+///
+///  ## max_angle
+///  - largest skew in degrees to look for (default 10)
+///
+///  ## bg
+///  - {rrggbb} or {rrggbbaa}, fill for exposed corners (default ffffff)
+///  - transparent
+///
+///  ## crop
+///  - true (default), keep the original canvas size
+///  - false, grow the canvas to fit the rotated image
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+///
+///  Images without a confidently detected skew are left untouched.
+pub struct Deskew;
+
+impl Task for Deskew {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn reorients(&self) -> bool {
+        true
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let max_angle: f64 = match options.get("max_angle") {
+            None => 10.0,
+            Some(value) => match value.parse() {
+                Ok(max_angle) if (0.1..=45.0).contains(&max_angle) => max_angle,
+                _ => panic!("Invalid max_angle: {}", value),
+            },
+        };
+        let background = parse_color(options.get("bg").copied().unwrap_or("ffffff"));
+        let crop = match options.get("crop").copied() {
+            None | Some("true") => true,
+            Some("false") => false,
+            Some(crop) => panic!("Invalid crop: {}", crop),
+        };
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            let mapped_path = mapped_target_path(&path, &target_path, run);
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            match detect_skew(&image, max_angle) {
+                Some(angle) if angle != 0.0 => {
+                    save_image(&rotate_image(&image, -angle, background, crop), &path, &mapped_path, run, &save);
+                    Outcome::Processed
+                }
+                // an image `--map` gives another format is converted even when it's left straight.
+                _ if mapped_path != target_path => {
+                    save_image(&image, &path, &mapped_path, run, &save);
+                    Outcome::LowConfidence
+                }
+                _ => {
+                    keep_original(&path, &target_path, run);
+                    Outcome::LowConfidence
+                }
+            }
+        })
+    }
+}
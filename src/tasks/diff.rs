@@ -0,0 +1,158 @@
+// The diff task.
+
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, encode_png, open_image, process_directory, quality, write_output};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "against", values: "directory to compare with, matched by relative path", default: "required" },
+    OptionSpec { key: "heatmap", values: "true | false, write difference images to the dest", default: "false" },
+];
+
+/// How an image compares with its counterpart.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Difference {
+    Identical,
+    Different,
+    Dimensions,
+    Missing,
+}
+
+impl Difference {
+    /// Name of the difference in the report.
+    fn name(&self) -> &'static str {
+        match self {
+            Difference::Identical => "identical",
+            Difference::Different => "different",
+            Difference::Dimensions => "dimensions",
+            Difference::Missing => "missing",
+        }
+    }
+}
+
+/// Largest and mean difference over every RGBA channel of two images with the same dimensions,
+/// and the PSNR in dB (infinite for identical images).
+fn pixel_deltas(a: &image::RgbaImage, b: &image::RgbaImage) -> (u8, f64, f64) {
+    let mut max = 0;
+    let mut sum = 0u64;
+    let mut squares = 0u64;
+    for (a, b) in a.as_raw().iter().zip(b.as_raw()) {
+        let delta = a.abs_diff(*b);
+        max = max.max(delta);
+        sum += delta as u64;
+        squares += delta as u64 * delta as u64;
+    }
+    let count = a.as_raw().len().max(1) as f64;
+    let mse = squares as f64 / count;
+    let psnr = if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0 * 255.0 / mse).log10() };
+    (max, sum as f64 / count, psnr)
+}
+
+/// Difference image where every pixel shows the largest channel difference, from black (none)
+/// through red and yellow to white (a difference of 64 or more).
+fn heatmap(a: &image::RgbaImage, b: &image::RgbaImage) -> image::DynamicImage {
+    let heatmap = image::RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let delta = a.get_pixel(x, y).0.iter().zip(b.get_pixel(x, y).0).map(|(a, b)| a.abs_diff(b)).max().unwrap();
+        let heat = (delta as u32 * 4).min(255) * 3;
+        image::Rgb([heat.min(255) as u8, heat.saturating_sub(255).min(255) as u8, heat.saturating_sub(510) as u8])
+    });
+    image::DynamicImage::ImageRgb8(heatmap)
+}
+
+/// Compare images in a directory with the images at the same relative paths in another one,
+/// printing the differences and adding them to the `--report`. The images themselves are not
+/// changed.
+///
+///  # Supported Options
+///
+///  ## against (Required)
+///  - directory to compare with, images only found there are not reported
+///
+///  ## heatmap
+///  - false (default)
+///  - true, write a PNG difference image for every image that differs into `--dest`
+///
+///  Images report their largest and mean channel difference (0-255), PSNR and SSIM. Images whose
+///  dimensions differ or that have no counterpart are reported as such.
+pub struct Diff;
+
+impl Task for Diff {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        OPTIONS.iter().collect()
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let against = match options.get("against") {
+            Some(against) => std::path::PathBuf::from(against),
+            None => panic!("Missing required option: against"),
+        };
+        if !against.is_dir() {
+            panic!("Invalid against (not a directory): {}", against.display());
+        }
+        let heatmaps = match options.get("heatmap").copied() {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(heatmap) => panic!("Invalid heatmap: {}", heatmap),
+        };
+        if heatmaps && run.dest_path.is_none() {
+            panic!("heatmap=true needs a --dest to write the difference images to");
+        }
+        let differences = std::cell::RefCell::new(Vec::new());
+
+        let summary = process_directory(source_path.clone(), run, |path, target_path| {
+            let other_path = against.join(path.strip_prefix(&source_path).unwrap_or(&path));
+            if !other_path.is_file() {
+                println!("{} missing from {}", path.display(), against.display());
+                run.report_field("difference", Difference::Missing.name());
+                differences.borrow_mut().push(Difference::Missing);
+                return Outcome::Inspected;
+            }
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            let other = match open_image(&other_path, run) {
+                Ok(other) => other,
+                Err(outcome) => return outcome,
+            };
+            if image.width() != other.width() || image.height() != other.height() {
+                println!("{} dimensions differ: {}x{} vs {}x{}", path.display(), image.width(), image.height(), other.width(), other.height());
+                run.report_field("difference", Difference::Dimensions.name());
+                differences.borrow_mut().push(Difference::Dimensions);
+                return Outcome::Inspected;
+            }
+
+            let (a, b) = (image.to_rgba8(), other.to_rgba8());
+            let (max, mean, psnr) = pixel_deltas(&a, &b);
+            let difference = if max == 0 { Difference::Identical } else { Difference::Different };
+            differences.borrow_mut().push(difference);
+            run.report_field("difference", difference.name());
+            if difference == Difference::Identical {
+                println!("{} identical", path.display());
+                return Outcome::Inspected;
+            }
+            let ssim = quality::ssim(&image, &other);
+            println!("{} max {} mean {:.2} psnr {:.1} dB ssim {:.4}", path.display(), max, mean, psnr, ssim);
+            run.report_field("max_delta", max);
+            run.report_field("mean_delta", mean);
+            run.report_field("psnr", psnr);
+            run.report_field("ssim", ssim);
+            if !heatmaps {
+                return Outcome::Inspected;
+            }
+            let bytes = run.timed_encode(|| encode_png(&heatmap(&a, &b), image::codecs::png::CompressionType::Fast, image::codecs::png::FilterType::Adaptive));
+            write_output(&path, &target_path.with_extension("png"), bytes, run);
+            Outcome::Processed
+        });
+
+        let differences = differences.into_inner();
+        let count = |difference| differences.iter().filter(|d| **d == difference).count();
+        println!(
+            "Identical: {}, different: {}, dimensions differ: {}, missing: {}.",
+            count(Difference::Identical),
+            count(Difference::Different),
+            count(Difference::Dimensions),
+            count(Difference::Missing)
+        );
+        summary
+    }
+}
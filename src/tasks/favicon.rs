@@ -0,0 +1,83 @@
+// The favicon task.
+
+use super::letterbox::{Frame, letterbox};
+use super::{OptionSpec, Task};
+use crate::{Outcome, Resample, RunArgs, Summary, open_image, parse_resample, process_directory, write_output};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "sizes", values: "\"16,32,48,64\", square sizes of 1-256 pixels", default: "16,32,48" },
+    OptionSpec { key: "filter", values: "nearest (point, box) | triangle (linear) | catmull-rom (cubic) | gaussian | lanczos3, or {horizontal}:{vertical}", default: "catmull-rom" },
+    OptionSpec { key: "filter_down", values: "a filter, for sides that shrink", default: "filter" },
+    OptionSpec { key: "filter_up", values: "a filter, for sides that grow", default: "filter" },
+    OptionSpec { key: "downscale", values: "direct | staged, halve big reductions first", default: "direct" },
+];
+
+/// Parse the sizes of the icons, separated by commas or whitespace, smallest first.
+fn parse_sizes(value: &str) -> Vec<u32> {
+    let mut sizes: Vec<u32> = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.parse::<u32>() {
+            Ok(size) if (1..=256).contains(&size) => size,
+            _ => panic!("Invalid size (expected 1-256): {}", part),
+        })
+        .collect();
+    sizes.sort_unstable();
+    sizes.dedup();
+    if sizes.is_empty() {
+        panic!("Invalid sizes: {}", value);
+    }
+    sizes
+}
+
+/// Encode an ICO with one PNG icon per size, each fitted into its square with transparent
+/// margins.
+fn encode_favicon(image: &image::DynamicImage, sizes: &[u32], resample: Resample) -> Vec<u8> {
+    let icons: Vec<image::RgbaImage> = sizes
+        .iter()
+        .map(|&size| letterbox(image, Frame::Size(size, size), image::Rgba([0, 0, 0, 0]), (0.5, 0.5), resample).to_rgba8())
+        .collect();
+    let frames: Vec<image::codecs::ico::IcoFrame> =
+        icons.iter().map(|icon| image::codecs::ico::IcoFrame::as_png(icon.as_raw(), icon.width(), icon.height(), image::ColorType::Rgba8).unwrap()).collect();
+    let mut bytes = Vec::new();
+    image::codecs::ico::IcoEncoder::new(&mut bytes).encode_images(&frames).unwrap();
+    bytes
+}
+
+/// Pack every image in a directory into a multi-resolution `.ico` next to its output path, for
+/// favicons and application icons. Images whose `.ico` would collide, such as `a.png` and
+/// `a.bmp`, get an index appended (`a.ico`, `a_2.ico`).
+///
+///  # Supported Options
+///
+///  ## sizes
+///  - "16,32,48" (default), the icon sizes in pixels, each 1-256
+///
+///  Quote the sizes since options are separated by commas, or separate them by spaces. Images
+///  that aren't square keep their aspect ratio and are centered with transparent margins.
+///
+///  Resampling options (filter, filter_down, filter_up, downscale) are accepted as well, see `parse_resample`.
+pub struct Favicon;
+
+impl Task for Favicon {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        OPTIONS.iter().collect()
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let sizes = parse_sizes(options.get("sizes").copied().unwrap_or("16,32,48"));
+        let resample = parse_resample(&options);
+        // targets are made unique before any is written.
+        run.output_extension.set(Some("ico"));
+
+        process_directory(source_path, run, |path, target_path| {
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            let bytes = run.timed_encode(|| encode_favicon(&image, &sizes, resample));
+            write_output(&path, &target_path, bytes, run);
+            Outcome::Processed
+        })
+    }
+}
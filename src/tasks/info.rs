@@ -0,0 +1,103 @@
+// The info task.
+
+use super::{OptionSpec, Task};
+#[cfg(feature = "pdf")]
+use crate::{is_pdf, open_image};
+use crate::{Outcome, RunArgs, Summary, image_dimensions, process_directory};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "group", values: "none | dimensions, count per size and aspect ratio", default: "none" },
+];
+
+/// Name of the common aspect ratio closest to width:height, within 2%.
+fn aspect_ratio_bucket(width: u32, height: u32) -> String {
+    const RATIOS: [(u32, u32); 9] = [(1, 1), (5, 4), (4, 3), (3, 2), (16, 10), (16, 9), (2, 1), (21, 9), (3, 1)];
+    let ratio = width as f64 / height as f64;
+    for (long, short) in RATIOS {
+        for (w, h) in [(long, short), (short, long)] {
+            if (ratio / (w as f64 / h as f64)).ln().abs() < 0.02 {
+                return format!("{}:{}", w, h);
+            }
+        }
+    }
+    "other".to_string()
+}
+
+/// Print counts per key, most common first.
+fn print_groups(title: &str, keys: impl Iterator<Item = String>) {
+    let mut counts = std::collections::HashMap::new();
+    let mut total = 0;
+    for key in keys {
+        *counts.entry(key).or_insert(0) += 1;
+        total += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let width = counts.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    println!("{}:", title);
+    for (key, count) in counts {
+        println!("  {:width$}  {} ({:.1}%)", key, count, count as f64 / total as f64 * 100.0, width = width);
+    }
+}
+
+/// The dimensions of an image from its header, reporting it when they can't be read.
+fn header_dimensions(path: &std::path::Path, run: &RunArgs) -> Result<(u32, u32), Outcome> {
+    image_dimensions(path, run).map_err(|error| {
+        log!("Error: can't read {}: {}", path.display(), error);
+        Outcome::Unreadable
+    })
+}
+
+/// Report the dimensions of the images in a directory without changing them. Only the image
+/// headers are read, except for PDFs whose first page is rendered.
+///
+///  # Supported Options
+///
+///  ## group
+///  - none (default), list every image with its dimensions
+///  - dimensions, count images per exact size and per aspect ratio
+pub struct Info;
+
+impl Task for Info {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        OPTIONS.iter().collect()
+    }
+
+    fn splits_pages(&self) -> bool {
+        false
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let group = match options.get("group").copied() {
+            None | Some("none") => false,
+            Some("dimensions") => true,
+            Some(group) => panic!("Invalid group: {}", group),
+        };
+        let dimensions = std::cell::RefCell::new(Vec::new());
+
+        let summary = process_directory(source_path, run, |path, _| {
+            // PDFs have no header to read, their first page is rendered.
+            #[cfg(feature = "pdf")]
+            let size = if is_pdf(&path) { open_image(&path, run).map(|page| (page.width(), page.height())) } else { header_dimensions(&path, run) };
+            #[cfg(not(feature = "pdf"))]
+            let size = header_dimensions(&path, run);
+            match size {
+                Ok((width, height)) => {
+                    if !group {
+                        println!("{} {}x{}", path.display(), width, height);
+                    }
+                    dimensions.borrow_mut().push((width, height));
+                    Outcome::Inspected
+                }
+                Err(outcome) => outcome,
+            }
+        });
+
+        if group {
+            let dimensions = dimensions.into_inner();
+            print_groups("Dimensions", dimensions.iter().map(|(width, height)| format!("{}x{}", width, height)));
+            print_groups("Aspect ratios", dimensions.iter().map(|&(width, height)| aspect_ratio_bucket(width, height)));
+        }
+        summary
+    }
+}
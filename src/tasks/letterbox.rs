@@ -0,0 +1,118 @@
+// The letterbox task.
+
+use super::canvas::resize_canvas;
+use super::rotate::parse_color;
+use super::smart_thumb::parse_anchor;
+use super::{OptionSpec, Task};
+use crate::{Resample, RunArgs, Summary, parse_dimensions, parse_resample, parse_save_args, process_directory, resize_by_size, transform_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "size", values: "{width}x{height}, frame to fit the image into", default: "required without aspect" },
+    OptionSpec { key: "aspect", values: "{width}:{height}, pad to this aspect ratio without scaling", default: "required without size" },
+    OptionSpec { key: "bg", values: "{rrggbb} | {rrggbbaa} | transparent", default: "000000" },
+    OptionSpec { key: "anchor", values: "center | top | bottom | left | right | top-left | top-right | bottom-left | bottom-right", default: "center" },
+    OptionSpec { key: "filter", values: "nearest (point, box) | triangle (linear) | catmull-rom (cubic) | gaussian | lanczos3, or {horizontal}:{vertical}", default: "catmull-rom" },
+    OptionSpec { key: "filter_down", values: "a filter, for sides that shrink", default: "filter" },
+    OptionSpec { key: "filter_up", values: "a filter, for sides that grow", default: "filter" },
+    OptionSpec { key: "downscale", values: "direct | staged, halve big reductions first", default: "direct" },
+];
+
+/// The frame an image is letterboxed into.
+#[derive(Copy, Clone)]
+pub enum Frame {
+    /// Scale the image to fit inside this size.
+    Size(u32, u32),
+    /// Keep the image at 1:1 and pad it to this aspect ratio.
+    Aspect(u32, u32),
+}
+
+/// Parse an aspect ratio like `16:9`.
+fn parse_aspect(value: &str) -> (u32, u32) {
+    match value.split_once(':').map(|(width, height)| (width.trim().parse::<u32>(), height.trim().parse::<u32>())) {
+        Some((Ok(width), Ok(height))) if width > 0 && height > 0 => (width, height),
+        _ => panic!("Invalid aspect (expected {{width}}:{{height}}, e.g. 16:9): {}", value),
+    }
+}
+
+/// Fit an image inside the frame and fill the rest with bars of `background`: above and below
+/// for images wider than the frame, on the sides for taller ones.
+pub fn letterbox(image: &image::DynamicImage, frame: Frame, background: image::Rgba<u8>, anchor: (f64, f64), resample: Resample) -> image::DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    match frame {
+        Frame::Size(frame_width, frame_height) => {
+            let scale = (frame_width as f64 / width as f64).min(frame_height as f64 / height as f64);
+            let fitted = (
+                ((width as f64 * scale).round() as u32).clamp(1, frame_width),
+                ((height as f64 * scale).round() as u32).clamp(1, frame_height),
+            );
+            let resized = match fitted == (width, height) {
+                true => image.clone(),
+                false => resize_by_size(image, fitted, resample),
+            };
+            resize_canvas(&resized, (frame_width, frame_height), anchor, background)
+        }
+        Frame::Aspect(aspect_width, aspect_height) => {
+            // grow the short side only, so the whole image stays at 1:1.
+            let wide_height = (width as f64 * aspect_height as f64 / aspect_width as f64).round() as u32;
+            let size = match wide_height >= height {
+                true => (width, wide_height),
+                false => (((height as f64 * aspect_width as f64 / aspect_height as f64).round() as u32).max(width), height),
+            };
+            resize_canvas(image, size, anchor, background)
+        }
+    }
+}
+
+/// Letterbox or pillarbox images in a directory into a fixed frame, keeping all of their content:
+/// unlike `smart-thumb`, nothing is cropped and the remaining space is filled with bars.
+///
+///  # Supported Options
+///
+///  ## size (Required unless `aspect` is given)
+///  - {width}x{height}, scale the image to fit inside and pad it to exactly this size
+///
+///  ## aspect (Required unless `size` is given)
+///  - {width}:{height}, e.g. 16:9, pad the image to this aspect ratio without scaling it
+///
+///  ## bg
+///  - {rrggbb} or {rrggbbaa}, color of the bars (default 000000)
+///  - transparent
+///
+///  ## anchor
+///  - center (default)
+///  - top, bottom, left, right
+///  - top-left, top-right, bottom-left, bottom-right
+///
+///  Resampling options (filter, filter_down, filter_up, downscale) are accepted as well, see `parse_resample`.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct Letterbox;
+
+impl Task for Letterbox {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let frame = match (options.get("size"), options.get("aspect")) {
+            (Some(_), Some(_)) => panic!("Give either size or aspect, not both"),
+            (Some(size_value), None) => match parse_dimensions(size_value) {
+                Some((width, height)) => Frame::Size(width, height),
+                None => panic!("Invalid size (expected {{width}}x{{height}} with both sides at least 1): {}", size_value),
+            },
+            (None, Some(aspect_value)) => {
+                let (width, height) = parse_aspect(aspect_value);
+                Frame::Aspect(width, height)
+            }
+            (None, None) => panic!("Missing required option: size or aspect"),
+        };
+        let background = parse_color(options.get("bg").copied().unwrap_or("000000"));
+        let anchor = parse_anchor(options.get("anchor").copied().unwrap_or("center"));
+        let resample = parse_resample(&options);
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            transform_image(&path, &target_path, run, &save, |image| letterbox(image, frame, background, anchor, resample))
+        })
+    }
+}
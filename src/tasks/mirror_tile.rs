@@ -0,0 +1,64 @@
+// The mirror-tile task.
+
+use super::{OptionSpec, Task};
+use crate::{RunArgs, Summary, is_high_depth, parse_dimensions, parse_save_args, process_directory, transform_image};
+
+const OPTIONS: &[OptionSpec] = &[OptionSpec { key: "tiles", values: "{columns}x{rows}", default: "2x2" }];
+
+/// Repeat an image on a grid of `columns` by `rows`, flipping every other column horizontally and
+/// every other row vertically so that neighbouring tiles meet at mirrored edges.
+fn mirror_tile(image: &image::DynamicImage, columns: u32, rows: u32) -> image::DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    let canvas_width = width.checked_mul(columns).unwrap_or_else(|| panic!("Tiled image is too wide: {} x {}", width, columns));
+    let canvas_height = height.checked_mul(rows).unwrap_or_else(|| panic!("Tiled image is too tall: {} x {}", height, rows));
+    // 16-bit images are tiled at 16 bits so they keep their precision.
+    match is_high_depth(image) {
+        true => image::DynamicImage::ImageRgba16(tile(&image.to_rgba16(), (canvas_width, canvas_height), columns, rows)),
+        false => image::DynamicImage::ImageRgba8(tile(&image.to_rgba8(), (canvas_width, canvas_height), columns, rows)),
+    }
+}
+
+/// Lay out the tiles of `mirror_tile` on a canvas of `size`, in the pixel type of the image.
+fn tile<P: image::Pixel + 'static>(image: &image::ImageBuffer<P, Vec<P::Subpixel>>, size: (u32, u32), columns: u32, rows: u32) -> image::ImageBuffer<P, Vec<P::Subpixel>> {
+    let (width, height) = image.dimensions();
+    let mirrored = image::imageops::flip_horizontal(image);
+    let flipped = [[image.clone(), mirrored.clone()], [image::imageops::flip_vertical(image), image::imageops::flip_vertical(&mirrored)]];
+    let mut canvas = image::ImageBuffer::new(size.0, size.1);
+    for row in 0..rows {
+        for column in 0..columns {
+            let tile = &flipped[(row % 2) as usize][(column % 2) as usize];
+            image::imageops::replace(&mut canvas, tile, (column * width) as i64, (row * height) as i64);
+        }
+    }
+    canvas
+}
+
+/// Turn images in a directory into seamless textures by mirroring them across their edges.
+///
+///  # Supported Options
+///
+///  ## tiles
+///  - {columns}x{rows} (default 2x2), 2x2 and other even counts tile seamlessly
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct MirrorTile;
+
+impl Task for MirrorTile {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn reorients(&self) -> bool {
+        true
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let tiles_value = options.get("tiles").copied().unwrap_or("2x2");
+        let (columns, rows) = parse_dimensions(tiles_value).unwrap_or_else(|| panic!("Invalid tiles: {}", tiles_value));
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            transform_image(&path, &target_path, run, &save, |image| mirror_tile(image, columns, rows))
+        })
+    }
+}
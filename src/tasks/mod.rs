@@ -0,0 +1,111 @@
+// Tasks that can be run on a directory of images.
+//
+// Each task lives in its own module and is registered in `TASKS` under the name given on the
+// command line, so adding a task doesn't require touching `main`.
+
+mod atlas;
+mod blurhash;
+mod canvas;
+mod color;
+mod color_matrix;
+mod convert;
+mod deskew;
+mod diff;
+mod favicon;
+mod info;
+mod letterbox;
+mod mirror_tile;
+mod optimize;
+mod patches;
+mod remap;
+mod replace_color;
+mod resize;
+mod rotate;
+mod smart_thumb;
+mod trim_alpha;
+
+use crate::{Cli, RunArgs, Summary};
+
+/// An option accepted by a task, as listed by `--explain`.
+pub struct OptionSpec {
+    pub key: &'static str,
+    pub values: &'static str,
+    pub default: &'static str,
+}
+
+/// Encoder options accepted by every task that writes images, see `parse_save_args`.
+const SAVE_OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "quality", values: "1-100, JPEG quality", default: "encoder default" },
+    OptionSpec { key: "colors", values: "2-256, GIF palette size", default: "256" },
+    OptionSpec { key: "dither", values: "on | off, GIF dithering", default: "on" },
+    OptionSpec { key: "premultiply", values: "true | false, premultiplied or straight alpha", default: "unchanged" },
+    OptionSpec { key: "png_compression", values: "fast | default | best", default: "fast" },
+    OptionSpec { key: "png_filter", values: "adaptive | none | sub | up | avg | paeth", default: "adaptive" },
+];
+
+/// A task that processes the images in a directory.
+pub trait Task {
+    /// The options the task reads.
+    fn options(&self) -> Vec<&'static OptionSpec>;
+
+    /// Whether the task rotates or flips the pixels, which makes the source's EXIF orientation
+    /// wrong for the output.
+    fn reorients(&self) -> bool {
+        false
+    }
+
+    /// Whether the task is run on every page of a PDF, writing a `_p{n}` output for each. Tasks
+    /// that don't write one output per image take the first page.
+    fn splits_pages(&self) -> bool {
+        true
+    }
+
+    /// Process the images in a directory.
+    ///
+    ///  @param source_path Path to source directory.
+    ///  @param run Settings shared by every task.
+    ///  @param options Options for the task.
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary;
+}
+
+/// Sets a task up from the command line.
+type Constructor = fn(&Cli) -> Box<dyn Task>;
+
+/// Every task by name, with the constructor that takes its settings from the command line.
+const TASKS: &[(&str, Constructor)] = &[
+    ("resize", |cli| Box::new(resize::Resize { max_dimension: cli.max_dimension, force: cli.force })),
+    ("optimize", |cli| Box::new(optimize::Optimize { min_savings: optimize::parse_savings(&cli.min_savings) })),
+    ("rotate", |_| Box::new(rotate::Rotate)),
+    ("deskew", |_| Box::new(deskew::Deskew)),
+    ("convert", |_| Box::new(convert::Convert)),
+    ("patches", |_| Box::new(patches::Patches)),
+    ("color", |_| Box::new(color::Color)),
+    ("blurhash", |_| Box::new(blurhash::Blurhash)),
+    ("info", |_| Box::new(info::Info)),
+    ("smart-thumb", |_| Box::new(smart_thumb::SmartThumb)),
+    ("mirror-tile", |_| Box::new(mirror_tile::MirrorTile)),
+    ("canvas", |_| Box::new(canvas::Canvas)),
+    ("diff", |_| Box::new(diff::Diff)),
+    ("color-matrix", |_| Box::new(color_matrix::ColorMatrix)),
+    ("letterbox", |_| Box::new(letterbox::Letterbox)),
+    ("remap", |_| Box::new(remap::Remap)),
+    ("favicon", |_| Box::new(favicon::Favicon)),
+    ("replace-color", |_| Box::new(replace_color::ReplaceColor)),
+    ("trim-alpha", |_| Box::new(trim_alpha::TrimAlpha)),
+    ("atlas", |_| Box::new(atlas::Atlas)),
+];
+
+/// Names of every task, in registration order.
+pub fn names() -> Vec<&'static str> {
+    TASKS.iter().map(|(name, _)| *name).collect()
+}
+
+/// The task with the given name, set up from the command line.
+pub fn find(name: &str, cli: &Cli) -> Option<Box<dyn Task>> {
+    TASKS.iter().find(|(task, _)| *task == name).map(|(_, new)| new(cli))
+}
+
+/// A task's own options followed by `SAVE_OPTIONS`, for tasks that write through `save_image`.
+fn with_save_options(options: &'static [OptionSpec]) -> Vec<&'static OptionSpec> {
+    options.iter().chain(SAVE_OPTIONS.iter()).collect()
+}
@@ -0,0 +1,102 @@
+// The optimize task.
+
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, decode_animated_webp, decode_apng, encode_animated_webp, encode_apng, encode_auto_quality, encode_image, encode_png, encode_tiff_pages, is_lossy, keep_original, open_image, parse_png_args, parse_quality, process_directory, tiff_pages, write_output};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "quality", values: "1-100, JPEG quality", default: "85" },
+    OptionSpec { key: "png_compression", values: "fast | default | best", default: "best" },
+    OptionSpec { key: "png_filter", values: "adaptive | none | sub | up | avg | paeth", default: "adaptive" },
+];
+
+/// Parse a savings threshold given either as a percentage (`5%`) or a fraction (`0.05`).
+pub fn parse_savings(value: &str) -> f64 {
+    let fraction = match value.strip_suffix('%') {
+        Some(percentage) => percentage.parse::<f64>().map(|p| p / 100.0),
+        None => value.parse::<f64>(),
+    };
+    match fraction {
+        Ok(fraction) if (0.0..1.0).contains(&fraction) => fraction,
+        _ => panic!("Invalid savings threshold: {}", value),
+    }
+}
+
+fn optimize_image(source_path: std::path::PathBuf, target_path: std::path::PathBuf, run: &RunArgs, quality: u8, png: (image::codecs::png::CompressionType, image::codecs::png::FilterType), min_savings: f64) -> Outcome {
+    // read the original bytes so they can be kept untouched.
+    let original = match run.read(&source_path) {
+        Ok(original) => original,
+        Err(outcome) => return outcome,
+    };
+    let format = image::ImageFormat::from_path(&target_path).unwrap();
+    // re-encode in memory and compare sizes. TIFFs keep all of their pages, animated PNGs and
+    // WebPs all of their frames.
+    let animation = match format {
+        _ if run.single_frame => None,
+        image::ImageFormat::Png => run.timed_decode(|| decode_apng(&original)),
+        image::ImageFormat::WebP => run.timed_decode(|| decode_animated_webp(&original)),
+        _ => None,
+    };
+    let pages = match format {
+        image::ImageFormat::Tiff => tiff_pages(&source_path, &original, run),
+        _ => None,
+    };
+    let encoded = if let Some(pages) = pages {
+        run.timed_encode(|| encode_tiff_pages(&pages))
+    } else if let Some((frames, plays)) = animation {
+        match format {
+            image::ImageFormat::WebP => run.timed_encode(|| encode_animated_webp(&frames, plays, None)),
+            _ => run.timed_encode(|| encode_apng(&frames, plays)),
+        }
+    } else {
+        let image = match open_image(&source_path, run) {
+            Ok(image) => image,
+            Err(outcome) => return outcome,
+        };
+        match format {
+            _ if run.quality_target.is_some() && is_lossy(format) => run.timed_encode(|| encode_auto_quality(&image, format, run)),
+            image::ImageFormat::Png => run.timed_encode(|| encode_png(&image, png.0, png.1)),
+            _ => run.timed_encode(|| encode_image(&image, format, quality)),
+        }
+    };
+    let savings = 1.0 - encoded.len() as f64 / original.len() as f64;
+    if savings < min_savings || encoded.len() >= original.len() {
+        keep_original(&source_path, &target_path, run);
+        return Outcome::NoImprovement;
+    }
+    write_output(&source_path, &target_path, encoded, run);
+    Outcome::Processed
+}
+
+/// Re-encode images in a directory to reduce their file size.
+///
+///  # Supported Options
+///
+///  ## quality
+///  - 1-100, JPEG quality (default 85)
+///
+///  ## png_compression, png_filter
+///  - see `parse_png_args`, best and adaptive by default
+///
+///  Images that do not shrink by at least `--min-savings` are left untouched. With
+///  `--output-quality-auto`, JPEG and WebP images get the lowest quality that reaches the target
+///  instead.
+pub struct Optimize {
+    /// Minimum fraction of bytes that must be saved, from `--min-savings`.
+    pub min_savings: f64,
+}
+
+impl Task for Optimize {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        OPTIONS.iter().collect()
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let min_savings = self.min_savings;
+        let quality = parse_quality(&options).unwrap_or(85);
+        let png = parse_png_args(&options, image::codecs::png::CompressionType::Best);
+
+        process_directory(source_path, run, |path, target_path| {
+            optimize_image(path, target_path, run, quality, png, min_savings)
+        })
+    }
+}
@@ -0,0 +1,242 @@
+// The patches task.
+
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, is_high_depth, mapped_target_path, open_image, parse_dimensions, parse_save_args, process_directory, save_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "size", values: "{width}x{height}", default: "256x256" },
+    OptionSpec { key: "stride", values: "{step} | {x}x{y}", default: "the patch size" },
+    OptionSpec { key: "pad", values: "reflect | edge | wrap | zero | drop, for patches past the edges", default: "reflect" },
+];
+
+/// How pixels past the edge of an image are filled, for patches and canvases that reach past it.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Pad {
+    /// Mirror the image at its edges.
+    Reflect,
+    /// Repeat the pixels on the edges.
+    Edge,
+    /// Continue with the opposite side of the image, as when it is tiled.
+    Wrap,
+    /// Fill with transparent black (or a solid color where the task has one).
+    Zero,
+    /// Skip patches that don't fit.
+    Drop,
+}
+
+/// Parse a `pad` option, with `zero` as `constant` where the task has a fill color.
+pub fn parse_pad(value: &str) -> Pad {
+    match value {
+        "reflect" => Pad::Reflect,
+        "edge" => Pad::Edge,
+        "wrap" => Pad::Wrap,
+        "zero" | "constant" => Pad::Zero,
+        "drop" => Pad::Drop,
+        _ => panic!("Invalid pad: {}", value),
+    }
+}
+
+/// Top-left positions of patches along one side: every `stride` pixels, with one more patch that
+/// reaches past the edge unless those are dropped.
+fn patch_offsets(length: u32, patch: u32, stride: u32, pad: Pad) -> Vec<u32> {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while offset < length {
+        if offset + patch > length && pad == Pad::Drop {
+            break;
+        }
+        offsets.push(offset);
+        // the last patch ends at the edge or reaches past it.
+        if offset + patch >= length {
+            break;
+        }
+        offset += stride;
+    }
+    offsets
+}
+
+/// Index into a side of `length` pixels for a position that may be past its edges, or `None`
+/// where the pixel is filled instead.
+pub fn padded_index(index: i64, length: u32, pad: Pad) -> Option<u32> {
+    match pad {
+        Pad::Reflect => Some(reflect_index(index, length)),
+        Pad::Edge => Some(index.clamp(0, length as i64 - 1) as u32),
+        Pad::Wrap => Some(index.rem_euclid(length as i64) as u32),
+        Pad::Zero | Pad::Drop => (0..length as i64).contains(&index).then_some(index as u32),
+    }
+}
+
+/// Index into a side of `length` pixels, mirrored at the edges.
+fn reflect_index(index: i64, length: u32) -> u32 {
+    let period = 2 * length as i64;
+    let index = index.rem_euclid(period);
+    if index < length as i64 {
+        index as u32
+    } else {
+        (period - 1 - index) as u32
+    }
+}
+
+/// The patch of `size` at a position, padded where it reaches past the edge of the image.
+fn patch(image: &image::DynamicImage, (x, y): (u32, u32), size: (u32, u32), pad: Pad) -> image::DynamicImage {
+    if x + size.0 <= image.width() && y + size.1 <= image.height() {
+        return image.crop_imm(x, y, size.0, size.1);
+    }
+    // keep the color type of the image for the encoder, and 16 bits for images with more than 8.
+    if is_high_depth(image) {
+        let padded = image::DynamicImage::ImageRgba16(padded_patch(&image.to_rgba16(), (x, y), size, pad, image::Rgba([0, 0, 0, 0])));
+        return match image.color() {
+            image::ColorType::L16 => image::DynamicImage::ImageLuma16(padded.to_luma16()),
+            image::ColorType::La16 => image::DynamicImage::ImageLumaA16(padded.to_luma_alpha16()),
+            image::ColorType::Rgb16 | image::ColorType::Rgb32F => image::DynamicImage::ImageRgb16(padded.to_rgb16()),
+            _ => padded,
+        };
+    }
+    let padded = image::DynamicImage::ImageRgba8(padded_patch(&image.to_rgba8(), (x, y), size, pad, image::Rgba([0, 0, 0, 0])));
+    match image.color() {
+        image::ColorType::L8 => image::DynamicImage::ImageLuma8(padded.to_luma8()),
+        image::ColorType::La8 => image::DynamicImage::ImageLumaA8(padded.to_luma_alpha8()),
+        image::ColorType::Rgb8 => image::DynamicImage::ImageRgb8(padded.to_rgb8()),
+        _ => padded,
+    }
+}
+
+/// The pixels of a patch that reaches past the edge of the image, padded or `empty` there.
+fn padded_patch<P: image::Pixel>(image: &image::ImageBuffer<P, Vec<P::Subpixel>>, (x, y): (u32, u32), size: (u32, u32), pad: Pad, empty: P) -> image::ImageBuffer<P, Vec<P::Subpixel>> {
+    image::ImageBuffer::from_fn(size.0, size.1, |px, py| {
+        let sx = padded_index((x + px) as i64, image.width(), pad);
+        let sy = padded_index((y + py) as i64, image.height(), pad);
+        match (sx, sy) {
+            (Some(sx), Some(sy)) => *image.get_pixel(sx, sy),
+            _ => empty,
+        }
+    })
+}
+
+/// Path of a patch cut out of an image: `name_x{x}_y{y}.ext`, by its top-left corner.
+fn patch_path(target_path: &std::path::Path, (x, y): (u32, u32)) -> std::path::PathBuf {
+    let stem = target_path.file_stem().unwrap().to_string_lossy();
+    let extension = target_path.extension().unwrap().to_string_lossy();
+    target_path.with_file_name(format!("{}_x{}_y{}.{}", stem, x, y, extension))
+}
+
+/// Cut images in a directory into patches that may overlap, e.g. for training data. The images
+/// themselves are left as they are.
+///
+///  # Supported Options
+///
+///  ## size
+///  - {width}x{height} (default 256x256)
+///
+///  ## stride
+///  - {step}, or {x}x{y} for different steps per side (default the patch size, no overlap)
+///
+///  ## pad
+///  - reflect (default), mirror the image into patches that reach past its edges
+///  - edge, repeat the edge pixels
+///  - wrap, continue with the opposite side of the image
+///  - zero, fill them with transparent black
+///  - drop, only write patches that fit in the image
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct Patches;
+
+impl Task for Patches {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let size_value = options.get("size").copied().unwrap_or("256x256");
+        let size = parse_dimensions(size_value).unwrap_or_else(|| panic!("Invalid size: {}", size_value));
+        let stride = match options.get("stride") {
+            None => size,
+            Some(value) => match value.parse::<u32>() {
+                Ok(step) if step > 0 => (step, step),
+                _ => parse_dimensions(value).unwrap_or_else(|| panic!("Invalid stride: {}", value)),
+            },
+        };
+        let pad = parse_pad(options.get("pad").copied().unwrap_or("reflect"));
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            let target_path = mapped_target_path(&path, &target_path, run);
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            for y in patch_offsets(image.height(), size.1, stride.1, pad) {
+                for x in patch_offsets(image.width(), size.0, stride.0, pad) {
+                    save_image(&patch(&image, (x, y), size, pad), &path, &patch_path(&target_path, (x, y)), run, &save);
+                }
+            }
+            Outcome::Processed
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_offsets_cover_the_side() {
+        assert_eq!(patch_offsets(10, 4, 4, Pad::Reflect), vec![0, 4, 8]);
+        // overlapping patches, the last one ends at the edge.
+        assert_eq!(patch_offsets(10, 4, 2, Pad::Reflect), vec![0, 2, 4, 6]);
+        assert_eq!(patch_offsets(8, 4, 4, Pad::Zero), vec![0, 4]);
+        // a patch larger than the side.
+        assert_eq!(patch_offsets(3, 4, 4, Pad::Edge), vec![0]);
+    }
+
+    #[test]
+    fn patch_offsets_drop_patches_past_the_edge() {
+        assert_eq!(patch_offsets(10, 4, 4, Pad::Drop), vec![0, 4]);
+        assert_eq!(patch_offsets(8, 4, 4, Pad::Drop), vec![0, 4]);
+        assert_eq!(patch_offsets(3, 4, 4, Pad::Drop), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn padded_index_inside_is_unchanged() {
+        for pad in [Pad::Reflect, Pad::Edge, Pad::Wrap, Pad::Zero, Pad::Drop] {
+            assert_eq!((0..4).map(|index| padded_index(index, 4, pad)).collect::<Vec<_>>(), vec![Some(0), Some(1), Some(2), Some(3)]);
+        }
+    }
+
+    #[test]
+    fn padded_index_reflects_at_edges() {
+        let indices: Vec<Option<u32>> = [-2, -1, 4, 5, 8, 9].into_iter().map(|index| padded_index(index, 4, Pad::Reflect)).collect();
+        assert_eq!(indices, vec![Some(1), Some(0), Some(3), Some(2), Some(0), Some(1)]);
+        assert_eq!(padded_index(-3, 1, Pad::Reflect), Some(0));
+    }
+
+    #[test]
+    fn padded_index_repeats_edges() {
+        let indices: Vec<Option<u32>> = [-5, -1, 4, 100].into_iter().map(|index| padded_index(index, 4, Pad::Edge)).collect();
+        assert_eq!(indices, vec![Some(0), Some(0), Some(3), Some(3)]);
+    }
+
+    #[test]
+    fn padded_index_wraps_around() {
+        let indices: Vec<Option<u32>> = [-5, -1, 4, 9].into_iter().map(|index| padded_index(index, 4, Pad::Wrap)).collect();
+        assert_eq!(indices, vec![Some(3), Some(3), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn padded_index_fills_zero_and_drop() {
+        for pad in [Pad::Zero, Pad::Drop] {
+            assert_eq!(padded_index(-1, 4, pad), None);
+            assert_eq!(padded_index(4, 4, pad), None);
+        }
+    }
+
+    #[test]
+    fn padded_patch_fills_past_the_edge() {
+        let image = image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(3, 1, |x, _| image::Luma([x as u8 * 10 + 10])));
+        let row = |pad| patch(&image, (1, 0), (4, 1), pad).to_luma8().into_raw();
+        assert_eq!(row(Pad::Reflect), vec![20, 30, 30, 20]);
+        assert_eq!(row(Pad::Edge), vec![20, 30, 30, 30]);
+        assert_eq!(row(Pad::Wrap), vec![20, 30, 10, 20]);
+        assert_eq!(row(Pad::Zero), vec![20, 30, 0, 0]);
+    }
+}
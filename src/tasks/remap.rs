@@ -0,0 +1,115 @@
+// The remap task.
+
+use super::{OptionSpec, SAVE_OPTIONS, Task};
+use crate::{Palette, RunArgs, Summary, parse_save_args, process_directory, transform_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "palette", values: "{path}.hex | {path}.gpl | swatch image", default: "required" },
+    OptionSpec { key: "dither", values: "on | off, Floyd-Steinberg dithering", default: "off" },
+];
+
+/// Colors of a palette file: a `.hex` file with one `rrggbb` color per line, a GIMP `.gpl`
+/// palette, or any image whose distinct opaque colors make up the palette, in order.
+fn read_palette(path: &std::path::Path) -> Vec<[u8; 3]> {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+    let mut colors: Vec<[u8; 3]> = match extension.as_deref() {
+        Some("hex") | Some("gpl") => {
+            let text = std::fs::read_to_string(path).unwrap_or_else(|error| panic!("Can't read palette {}: {}", path.display(), error));
+            let is_gpl = extension.as_deref() == Some("gpl");
+            text.lines().map(str::trim).filter(|line| !line.is_empty()).filter_map(|line| if is_gpl { gpl_color(line) } else { Some(hex_color(line)) }).collect()
+        }
+        _ => {
+            let swatch = image::open(path).unwrap_or_else(|error| panic!("Can't read palette {}: {}", path.display(), error));
+            swatch.to_rgba8().pixels().filter(|p| p[3] >= 128).map(|p| [p[0], p[1], p[2]]).collect()
+        }
+    };
+    // swatches repeat their colors over many pixels, keep the first of each.
+    let mut seen = std::collections::HashSet::new();
+    colors.retain(|color| seen.insert(*color));
+    if colors.is_empty() {
+        panic!("Invalid palette (no colors): {}", path.display());
+    }
+    colors
+}
+
+fn hex_color(line: &str) -> [u8; 3] {
+    let hex = line.trim_start_matches('#');
+    let channel = |index: usize| hex.get(index * 2..index * 2 + 2).and_then(|channel| u8::from_str_radix(channel, 16).ok());
+    match (hex.len(), channel(0), channel(1), channel(2)) {
+        (6, Some(r), Some(g), Some(b)) => [r, g, b],
+        _ => panic!("Invalid palette color: {}", line),
+    }
+}
+
+/// The color of a line of a GIMP palette, `None` for its header, comments and settings.
+fn gpl_color(line: &str) -> Option<[u8; 3]> {
+    if line.starts_with('#') || line.starts_with("GIMP Palette") || line.starts_with("Name:") || line.starts_with("Columns:") {
+        return None;
+    }
+    let channels: Vec<u8> = line.split_whitespace().take(3).map(|channel| channel.parse().unwrap_or_else(|_| panic!("Invalid palette color: {}", line))).collect();
+    match channels[..] {
+        [r, g, b] => Some([r, g, b]),
+        _ => panic!("Invalid palette color: {}", line),
+    }
+}
+
+/// Map every pixel to the nearest palette color, keeping its alpha.
+fn remap(image: &image::DynamicImage, palette: &Palette, dither: bool) -> image::DynamicImage {
+    let original = image.to_rgba8();
+    let mut rgba = original.clone();
+    if dither && rgba.width() > 1 && rgba.height() > 1 {
+        image::imageops::dither(&mut rgba, palette);
+    } else {
+        for pixel in rgba.pixels_mut() {
+            image::imageops::ColorMap::map_color(palette, pixel);
+        }
+    }
+    for (pixel, original) in rgba.pixels_mut().zip(original.pixels()) {
+        pixel[3] = original[3];
+    }
+    let remapped = image::DynamicImage::ImageRgba8(rgba);
+    if image.color().has_alpha() { remapped } else { image::DynamicImage::ImageRgb8(remapped.to_rgb8()) }
+}
+
+/// Remap images in a directory to a fixed palette, so a whole asset set shares one color theme.
+/// Every pixel becomes the nearest palette color by weighted RGB distance, the same one GIF
+/// quantization uses.
+///
+///  # Supported Options
+///
+///  ## palette (Required)
+///  - {path}.hex, one rrggbb color per line
+///  - {path}.gpl, a GIMP palette
+///  - any other image, a swatch whose distinct opaque colors make up the palette
+///
+///  ## dither
+///  - off (default), plain nearest colors, best for pixel art
+///  - on, spread the error with Floyd-Steinberg dithering, for smooth gradients
+///
+///  Alpha is kept as it is. The encoder options of `SAVE_OPTIONS` other than dither, which is
+///  its own, are accepted as well.
+pub struct Remap;
+
+impl Task for Remap {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        // dither is the task's own option here, off by default.
+        OPTIONS.iter().chain(SAVE_OPTIONS.iter().filter(|spec| spec.key != "dither")).collect()
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let palette = match options.get("palette") {
+            Some(palette) => Palette::new(read_palette(std::path::Path::new(palette)), None),
+            None => panic!("Missing required option: palette"),
+        };
+        let dither = match options.get("dither").copied() {
+            None | Some("off") => false,
+            Some("on") => true,
+            Some(dither) => panic!("Invalid dither: {}", dither),
+        };
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            transform_image(&path, &target_path, run, &save, |image| remap(image, &palette, dither))
+        })
+    }
+}
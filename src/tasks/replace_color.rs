@@ -0,0 +1,88 @@
+// The replace-color task.
+
+use super::rotate::parse_color;
+use super::{OptionSpec, Task};
+use crate::{RunArgs, Summary, is_high_depth, parse_save_args, process_directory, transform_image, widen_color, without_alpha};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "from", values: "{rrggbb}, color to replace", default: "required" },
+    OptionSpec { key: "to", values: "{rrggbb} | {rrggbbaa} | transparent", default: "transparent" },
+    OptionSpec { key: "tolerance", values: "0-255, largest channel difference that still matches", default: "0" },
+];
+
+/// Replace every pixel of an RGBA buffer whose red, green and blue are each within `tolerance` of
+/// `from` with `to`. Channels go up to `max`, which the tolerance (given at 8 bits) is scaled to.
+fn replace<S: Copy + Into<u32>>(buffer: &mut image::ImageBuffer<image::Rgba<S>, Vec<S>>, from: image::Rgba<S>, to: image::Rgba<S>, tolerance: u8, max: u32) -> usize
+where
+    image::Rgba<S>: image::Pixel<Subpixel = S>,
+{
+    let tolerance = tolerance as u32 * max / 255;
+    let mut replaced = 0;
+    for pixel in buffer.pixels_mut() {
+        if (0..3).all(|channel| pixel.0[channel].into().abs_diff(from.0[channel].into()) <= tolerance) {
+            *pixel = to;
+            replaced += 1;
+        }
+    }
+    replaced
+}
+
+fn replace_color(image: &image::DynamicImage, from: image::Rgba<u8>, to: image::Rgba<u8>, tolerance: u8, run: &RunArgs) -> image::DynamicImage {
+    // images without alpha only get one when the replacement isn't opaque.
+    let has_alpha = image.color().has_alpha() || to[3] < 255;
+    let (replaced, image) = if is_high_depth(image) {
+        let mut buffer = image.to_rgba16();
+        let replaced = replace(&mut buffer, widen_color(from), widen_color(to), tolerance, 65535);
+        (replaced, image::DynamicImage::ImageRgba16(buffer))
+    } else {
+        let mut buffer = image.to_rgba8();
+        let replaced = replace(&mut buffer, from, to, tolerance, 255);
+        (replaced, image::DynamicImage::ImageRgba8(buffer))
+    };
+    run.report_field("replaced_pixels", replaced);
+    if has_alpha { image } else { without_alpha(image) }
+}
+
+/// Replace one color of images in a directory with another or with transparency, like the color
+/// key of old sprite sheets (e.g. magenta backgrounds).
+///
+///  # Supported Options
+///
+///  ## from (Required)
+///  - {rrggbb}, the color to replace, its alpha is ignored
+///
+///  ## to
+///  - transparent (default)
+///  - {rrggbb} or {rrggbbaa}, the replacement
+///
+///  ## tolerance
+///  - 0-255, how far each of red, green and blue may be from `from` to still match (default 0,
+///    exact matches only). At 16 bits per channel the tolerance is scaled accordingly.
+///
+///  Images without alpha get one when the replacement is transparent.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct ReplaceColor;
+
+impl Task for ReplaceColor {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let from = match options.get("from") {
+            Some(from) => parse_color(from),
+            None => panic!("Missing required option: from"),
+        };
+        let to = parse_color(options.get("to").copied().unwrap_or("transparent"));
+        let tolerance = match options.get("tolerance") {
+            None => 0,
+            Some(tolerance_value) => tolerance_value.parse::<u8>().unwrap_or_else(|_| panic!("Invalid tolerance (expected 0-255): {}", tolerance_value)),
+        };
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            transform_image(&path, &target_path, run, &save, |image| replace_color(image, from, to, tolerance, run))
+        })
+    }
+}
@@ -0,0 +1,507 @@
+// The resize task.
+
+use image::GenericImageView;
+
+use super::rotate::parse_color;
+use super::trim_alpha::content_bounds;
+use super::{OptionSpec, Task};
+use crate::{Outcome, Resample, RunArgs, Summary, image_dimensions, keep_original, parse_dimensions, parse_resample, parse_save_args, process_directory, resize_by_size, transform_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "size", values: "{width}x{height} | {percentage}% | {side}", default: "128x128" },
+    OptionSpec { key: "mode", values: "exact | shortest | longest", default: "exact" },
+    OptionSpec { key: "megapixels", values: "{megapixels}, e.g. 2.0, instead of size", default: "none" },
+    OptionSpec { key: "upscale", values: "true | false, also enlarge images below megapixels or widths", default: "false" },
+    OptionSpec { key: "widths", values: "{width};{width};..., write every image at each width, instead of size", default: "none" },
+    OptionSpec { key: "size_subdirs", values: "true | false, write each width into a folder named by it", default: "false" },
+    OptionSpec { key: "min_box", values: "{width}x{height}, enlarge images that fit inside it, instead of size", default: "none" },
+    OptionSpec { key: "max_box", values: "{width}x{height}, shrink images that don't fit inside it, instead of size", default: "none" },
+    OptionSpec { key: "trim", values: "{rrggbb} | {rrggbbaa} | transparent | auto, background to crop before resizing", default: "none" },
+    OptionSpec { key: "trim_tolerance", values: "0-255, largest channel difference that still counts as background", default: "0" },
+    OptionSpec { key: "filter", values: "nearest (point, box) | triangle (linear) | catmull-rom (cubic) | gaussian | lanczos3, or {horizontal}:{vertical}", default: "catmull-rom" },
+    OptionSpec { key: "filter_down", values: "a filter, for sides that shrink", default: "filter" },
+    OptionSpec { key: "filter_up", values: "a filter, for sides that grow", default: "filter" },
+    OptionSpec { key: "downscale", values: "direct | staged, halve big reductions first", default: "direct" },
+];
+
+#[derive(Copy, Clone)]
+struct SizeArgs {
+    width: u32,
+    height: u32,
+    scale: f32,
+}
+
+/// The background cropped off images before resizing, `None` for the color of their top left
+/// pixel.
+#[derive(Copy, Clone)]
+struct Trim {
+    background: Option<image::Rgba<u8>>,
+    tolerance: u8,
+}
+
+/// Crop the margins of the trim background off an image, so it is resized without them. Images
+/// that are all background are kept whole.
+fn trim_image(image: &image::DynamicImage, trim: Option<Trim>) -> std::borrow::Cow<'_, image::DynamicImage> {
+    let Some(trim) = trim else {
+        return std::borrow::Cow::Borrowed(image);
+    };
+    let background = trim.background.unwrap_or_else(|| image.get_pixel(0, 0));
+    let is_content = |pixel: &image::Rgba<u8>| (0..4).any(|channel| pixel[channel].abs_diff(background[channel]) > trim.tolerance);
+    match content_bounds(image, is_content) {
+        Some((left, top, right, bottom)) => std::borrow::Cow::Owned(image.crop_imm(left, top, right - left, bottom - top)),
+        None => std::borrow::Cow::Borrowed(image),
+    }
+}
+
+/// A side scaled by `scale`, rounded half up and never below one pixel.
+fn scale_side(side: u32, scale: f32) -> u32 {
+    ((side as f32 * scale).round() as u32).max(1)
+}
+
+fn resize_by_scale(image: &image::DynamicImage, scale: f32, resample: Resample) -> image::DynamicImage {
+    // get image dimensions.
+    let (width, height) = image.dimensions();
+    // calculate new dimensions.
+    let new_width = scale_side(width, scale);
+    let new_height = scale_side(height, scale);
+    // resize image.
+    resize_by_size(image, (new_width, new_height), resample)
+}
+
+/// Downscale an image so that its longest side is at most `max_dimension`.
+fn resize_to_max_dimension(image: &image::DynamicImage, max_dimension: u32, resample: Resample) -> image::DynamicImage {
+    let longest = image.width().max(image.height());
+    if longest <= max_dimension {
+        return image.clone();
+    }
+    resize_by_scale(image, max_dimension as f32 / longest as f32, resample)
+}
+
+/// Dimensions that make the shortest (or longest) side of an image exactly `side` pixels while
+/// preserving the aspect ratio. The other side is rounded and never drops below one pixel.
+fn scale_to_side(width: u32, height: u32, side: u32, shortest: bool) -> (u32, u32) {
+    let width_is_reference = (width <= height) == shortest;
+    let (reference, other) = if width_is_reference { (width, height) } else { (height, width) };
+    let scaled = ((other as f64 * side as f64 / reference as f64).round() as u32).max(1);
+    if width_is_reference {
+        (side, scaled)
+    } else {
+        (scaled, side)
+    }
+}
+
+/// Dimensions that give an image an area of `megapixels` million pixels with one uniform scale,
+/// whatever its aspect ratio. Images that are already smaller keep their size unless `upscale`
+/// is set.
+fn scale_to_megapixels(width: u32, height: u32, megapixels: f64, upscale: bool) -> (u32, u32) {
+    let scale = (megapixels * 1_000_000.0 / (width as f64 * height as f64)).sqrt();
+    if scale >= 1.0 && !upscale {
+        return (width, height);
+    }
+    (scale_side(width, scale as f32), scale_side(height, scale as f32))
+}
+
+/// Dimensions that bring an image between two boxes with one uniform scale: images that don't
+/// fit into `max_box` are shrunk to fit it, those that fit into `min_box` are enlarged to fit it
+/// exactly, and all others keep their size.
+fn scale_to_boxes(width: u32, height: u32, min_box: Option<(u32, u32)>, max_box: Option<(u32, u32)>) -> (u32, u32) {
+    let fit_scale = |(box_width, box_height): (u32, u32)| (box_width as f32 / width as f32).min(box_height as f32 / height as f32);
+    let scale = match (min_box, max_box) {
+        (_, Some(max_box)) if width > max_box.0 || height > max_box.1 => fit_scale(max_box),
+        (Some(min_box), _) if width <= min_box.0 && height <= min_box.1 => fit_scale(min_box),
+        _ => return (width, height),
+    };
+    (scale_side(width, scale), scale_side(height, scale))
+}
+
+/// Where an image is written at one of several widths: `name_{width}.ext` next to where it would
+/// go, or with `size_subdirs` at the same path in a `{width}` folder of the dest (or the source
+/// when writing in place), e.g. `dest/320/photos/name.ext`.
+fn width_path(target_path: &std::path::Path, root: &std::path::Path, width: u32, size_subdirs: bool) -> std::path::PathBuf {
+    if !size_subdirs {
+        let stem = target_path.file_stem().unwrap().to_string_lossy();
+        return match target_path.extension() {
+            Some(extension) => target_path.with_file_name(format!("{}_{}.{}", stem, width, extension.to_string_lossy())),
+            None => target_path.with_file_name(format!("{}_{}", stem, width)),
+        };
+    }
+    match target_path.strip_prefix(root) {
+        Ok(relative_path) => root.join(width.to_string()).join(relative_path),
+        Err(_) => target_path.parent().unwrap().join(width.to_string()).join(target_path.file_name().unwrap()),
+    }
+}
+
+/// Keep an image that already has the size a resize would give it, unless `force` is set.
+///
+/// Only the header is read to find out. Returns `None` when the image has to be resized, or when
+/// its header can't be read so that the transform reports it.
+fn skip_sized(path: &std::path::Path, target_path: &std::path::Path, run: &RunArgs, force: bool, is_sized: impl Fn(u32, u32) -> bool) -> Option<Outcome> {
+    if force {
+        return None;
+    }
+    let (width, height) = image_dimensions(path, run).ok()?;
+    if !is_sized(width, height) {
+        return None;
+    }
+    if run.verbose {
+        println!("Skipping {} ({}x{} is already sized).", path.display(), width, height);
+    }
+    keep_original(path, target_path, run);
+    Some(Outcome::AlreadySized)
+}
+
+/// Resize images in a directory.
+///
+///  # Supported Options
+///
+///  ## size (Required unless `megapixels`, `min_box` or `max_box` is given)
+///  - {width}x{height}
+///  - {percentage}%
+///  - {side} (with mode=shortest or mode=longest)
+///
+///  ## mode
+///  - exact (default), resize to the given size
+///  - shortest, make the shortest side exactly {side} pixels, preserving aspect ratio
+///  - longest, make the longest side exactly {side} pixels, preserving aspect ratio
+///
+///  ## megapixels
+///  - {megapixels}, e.g. 2.0, scale images uniformly to this area instead of a size
+///
+///  ## upscale
+///  - false (default), only shrink images larger than `megapixels` or a width of `widths`
+///  - true, enlarge smaller ones as well
+///
+///  ## widths
+///  - {width};{width};..., e.g. 320;640;1280, write every image at each of the widths instead
+///    of at one size, keeping its aspect ratio, e.g. for a srcset. Images at most as wide as a
+///    width are written at their own size for it unless `upscale` is set.
+///
+///  ## size_subdirs
+///  - false (default), write the widths as `name_{width}.ext`
+///  - true, write them into folders named by the width, `{dest}/{width}/...`, mirroring the
+///    source layout below them unless `--flatten` is given
+///
+///  ## min_box, max_box
+///  - {width}x{height}, bring images between the boxes instead of to a size, keeping their
+///    aspect ratio: images that don't fit into `max_box` are shrunk to fit it, images that fit
+///    into `min_box` are enlarged until they touch its sides, and all others are left as they
+///    are. Either box may be given alone, `min_box` may not be larger than `max_box`.
+///
+///  ## trim
+///  - {rrggbb}, {rrggbbaa} or transparent, crop the margins of this color off images before
+///    resizing them, in the same pass, e.g. ffffff for the paper around scanned documents
+///  - auto, crop the margins of the color of the top left pixel
+///
+///  ## trim_tolerance
+///  - 0-255, how far each channel may be from the trim background to still count as background
+///    (default 0, exact matches only)
+///
+///  Resampling options (filter, filter_down, filter_up, downscale) are accepted as well, see `parse_resample`.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+///
+///  When `max_dimension` is given the size option is ignored and images are only shrunk so that
+///  their longest side fits.
+///
+///  Images that already have the resulting size are left untouched unless `force` or `trim` is
+///  set.
+pub struct Resize {
+    /// Longest side allowed, from `--max-dimension`.
+    pub max_dimension: Option<u32>,
+    /// Re-encode images that already have the resulting size, from `--force`.
+    pub force: bool,
+}
+
+impl Task for Resize {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let (max_dimension, mut force) = (self.max_dimension, self.force);
+        let trim = options.get("trim").map(|trim_value| Trim {
+            background: match *trim_value {
+                "auto" => None,
+                color => Some(parse_color(color)),
+            },
+            tolerance: match options.get("trim_tolerance") {
+                None => 0,
+                Some(tolerance_value) => tolerance_value.parse().unwrap_or_else(|_| panic!("Invalid trim_tolerance (expected 0-255): {}", tolerance_value)),
+            },
+        });
+        // the size after trimming isn't known from the header.
+        force |= trim.is_some();
+        let resample = parse_resample(&options);
+        let save = parse_save_args(&options);
+
+        // max dimension takes precedence over the size option.
+        if let Some(max_dimension) = max_dimension {
+            if max_dimension == 0 {
+                panic!("Invalid max dimension: {}", max_dimension);
+            }
+            return process_directory(source_path, run, |path, target_path| {
+                if let Some(outcome) = skip_sized(&path, &target_path, run, force, |width, height| width.max(height) <= max_dimension) {
+                    return outcome;
+                }
+                transform_image(&path, &target_path, run, &save, |image| {
+                    let image = &trim_image(image, trim);
+                    resize_to_max_dimension(image, max_dimension, resample)
+                })
+            });
+        }
+
+        // so do boxes to bring images between.
+        if options.contains_key("min_box") || options.contains_key("max_box") {
+            if options.contains_key("size") || options.contains_key("megapixels") {
+                panic!("Give either size, megapixels or min_box and max_box, not several");
+            }
+            let parse_box = |key: &str| options.get(key).map(|value| parse_dimensions(value).unwrap_or_else(|| panic!("Invalid {}: {}", key, value)));
+            let (min_box, max_box) = (parse_box("min_box"), parse_box("max_box"));
+            if let (Some(min_box), Some(max_box)) = (min_box, max_box) {
+                if min_box.0 > max_box.0 || min_box.1 > max_box.1 {
+                    panic!("Invalid boxes (min_box {}x{} is larger than max_box {}x{})", min_box.0, min_box.1, max_box.0, max_box.1);
+                }
+            }
+            return process_directory(source_path, run, |path, target_path| {
+                let is_sized = |width, height| scale_to_boxes(width, height, min_box, max_box) == (width, height);
+                if let Some(outcome) = skip_sized(&path, &target_path, run, force, is_sized) {
+                    return outcome;
+                }
+                transform_image(&path, &target_path, run, &save, |image| {
+                    let image = &trim_image(image, trim);
+                    resize_by_size(image, scale_to_boxes(image.width(), image.height(), min_box, max_box), resample)
+                })
+            });
+        }
+
+        // and so does a list of widths, every image is written once for each.
+        if let Some(widths_value) = options.get("widths") {
+            if options.contains_key("size") || options.contains_key("megapixels") {
+                panic!("Give either size, megapixels or widths, not several");
+            }
+            let widths: Vec<u32> = widths_value
+                .split(';')
+                .map(|width| match width.trim().parse() {
+                    Ok(width) if width > 0 => width,
+                    _ => panic!("Invalid widths: {}", widths_value),
+                })
+                .collect();
+            let upscale = match options.get("upscale").copied() {
+                None | Some("false") => false,
+                Some("true") => true,
+                Some(upscale) => panic!("Invalid upscale: {}", upscale),
+            };
+            let size_subdirs = match options.get("size_subdirs").copied() {
+                None | Some("false") => false,
+                Some("true") => true,
+                Some(size_subdirs) => panic!("Invalid size_subdirs: {}", size_subdirs),
+            };
+            let root = run.dest_path.clone().unwrap_or_else(|| source_path.clone());
+            return process_directory(source_path, run, |path, target_path| {
+                let mut outcome = Outcome::AlreadySized;
+                for &width in &widths {
+                    let scaled = |image_width: u32, image_height: u32| match image_width <= width && !upscale {
+                        true => (image_width, image_height),
+                        false => scale_to_side(image_width, image_height, width, image_width <= image_height),
+                    };
+                    let width_target_path = width_path(&target_path, &root, width, size_subdirs);
+                    if size_subdirs {
+                        std::fs::create_dir_all(width_target_path.parent().unwrap()).unwrap();
+                    }
+                    let width_outcome = match skip_sized(&path, &width_target_path, run, force, |image_width, image_height| scaled(image_width, image_height) == (image_width, image_height)) {
+                        Some(width_outcome) => width_outcome,
+                        None => transform_image(&path, &width_target_path, run, &save, |image| {
+                            let image = &trim_image(image, trim);
+                            resize_by_size(image, scaled(image.width(), image.height()), resample)
+                        }),
+                    };
+                    // an image that can't be read fails at every width, and is reported once.
+                    match width_outcome {
+                        Outcome::Unreadable | Outcome::Truncated => return width_outcome,
+                        Outcome::Processed => outcome = Outcome::Processed,
+                        width_outcome if outcome != Outcome::Processed => outcome = width_outcome,
+                        _ => {}
+                    }
+                }
+                // nothing goes where the image itself would have, drop the folder made for it if it stayed empty.
+                if size_subdirs {
+                    let _ = std::fs::remove_dir(target_path.parent().unwrap());
+                }
+                outcome
+            });
+        }
+
+        // a pixel area takes the place of the size option.
+        if let Some(megapixels_value) = options.get("megapixels") {
+            if options.contains_key("size") {
+                panic!("Give either size or megapixels, not both");
+            }
+            let megapixels: f64 = match megapixels_value.parse() {
+                Ok(megapixels) if f64::is_finite(megapixels) && megapixels > 0.0 => megapixels,
+                _ => panic!("Invalid megapixels: {}", megapixels_value),
+            };
+            let upscale = match options.get("upscale").copied() {
+                None | Some("false") => false,
+                Some("true") => true,
+                Some(upscale) => panic!("Invalid upscale: {}", upscale),
+            };
+            return process_directory(source_path, run, |path, target_path| {
+                let is_sized = |width, height| scale_to_megapixels(width, height, megapixels, upscale) == (width, height);
+                if let Some(outcome) = skip_sized(&path, &target_path, run, force, is_sized) {
+                    return outcome;
+                }
+                transform_image(&path, &target_path, run, &save, |image| {
+                    let image = &trim_image(image, trim);
+                    resize_by_size(image, scale_to_megapixels(image.width(), image.height(), megapixels, upscale), resample)
+                })
+            });
+        }
+
+        // check size option.
+        if !options.contains_key("size") {
+            panic!("Missing required option: size");
+        }
+
+        // get size value.
+        let size_value = options.get("size").unwrap();
+
+        // check if a side is specified in shortest/longest mode.
+        let mode = options.get("mode").copied().unwrap_or("exact");
+        if mode == "shortest" || mode == "longest" {
+            let side: u32 = match size_value.parse() {
+                Ok(side) if side > 0 => side,
+                _ => panic!("Invalid size for mode={}: {}", mode, size_value),
+            };
+            let shortest = mode == "shortest";
+            return process_directory(source_path, run, |path, target_path| {
+                if let Some(outcome) = skip_sized(&path, &target_path, run, force, |width, height| scale_to_side(width, height, side, shortest) == (width, height)) {
+                    return outcome;
+                }
+                transform_image(&path, &target_path, run, &save, |image| {
+                    let image = &trim_image(image, trim);
+                    resize_by_size(image, scale_to_side(image.width(), image.height(), side, shortest), resample)
+                })
+            });
+        } else if mode != "exact" {
+            panic!("Invalid mode: {}", mode);
+        }
+
+        // split size value by x.
+        let size: Vec<&str> = size_value.split("x").collect();
+        // check if size is valid.
+        if size.len() != 2 {
+            // check if size is specified in percentage.
+            if size_value.ends_with("%")
+            {
+                // strip percentage sign.
+                let size_value = size_value.strip_suffix("%").unwrap();
+                // parse size value to float.
+                let percentage: f32 = match size_value.parse() {
+                    Ok(percentage) if percentage > 0.0 => percentage,
+                    _ => panic!("Invalid size percentage: {}%", size_value),
+                };
+                // turn percentage into scale (0.0 - 1.0)
+                let scale = percentage / 100.0;
+                // Create new SizeArgs struct
+                let size = SizeArgs {
+                    width: 0,
+                    height: 0,
+                    scale,
+                };
+
+                return process_directory(source_path, run, |path, target_path| {
+                    let is_sized = |width: u32, height: u32| scale_side(width, size.scale) == width && scale_side(height, size.scale) == height;
+                    if let Some(outcome) = skip_sized(&path, &target_path, run, force, is_sized) {
+                        return outcome;
+                    }
+                    transform_image(&path, &target_path, run, &save, |image| {
+                        let image = &trim_image(image, trim);
+                        resize_by_scale(image, size.scale, resample)
+                    })
+                });
+            }
+        }
+
+        // parse size value to u32, both sides need at least one pixel.
+        let (width, height) = match parse_dimensions(size_value) {
+            Some(dimensions) => dimensions,
+            None => panic!("Invalid size (expected {{width}}x{{height}} with both sides at least 1): {}", size_value),
+        };
+        // Create new SizeArgs struct
+        let size = SizeArgs {
+            width,
+            height,
+            scale: 0.0,
+        };
+
+        process_directory(source_path.clone(), run, |path, target_path| {
+            if let Some(outcome) = skip_sized(&path, &target_path, run, force, |width, height| (width, height) == (size.width, size.height)) {
+                return outcome;
+            }
+            transform_image(&path, &target_path, run, &save, |image| {
+                let image = &trim_image(image, trim);
+                resize_by_size(image, (size.width, size.height), resample)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_to_side_sets_shortest_side() {
+        // landscape
+        assert_eq!(scale_to_side(400, 300, 150, true), (200, 150));
+        // portrait
+        assert_eq!(scale_to_side(300, 400, 150, true), (150, 200));
+        // square
+        assert_eq!(scale_to_side(300, 300, 150, true), (150, 150));
+    }
+
+    #[test]
+    fn scale_to_side_sets_longest_side() {
+        assert_eq!(scale_to_side(400, 300, 200, false), (200, 150));
+        assert_eq!(scale_to_side(300, 400, 200, false), (150, 200));
+        // enlarges as well.
+        assert_eq!(scale_to_side(40, 30, 200, false), (200, 150));
+    }
+
+    #[test]
+    fn scale_to_side_rounds_other_side() {
+        // 100 * 64 / 3 = 2133.33
+        assert_eq!(scale_to_side(3, 100, 64, true), (64, 2133));
+        // 200 * 100 / 3 = 6666.67
+        assert_eq!(scale_to_side(200, 3, 100, true), (6667, 100));
+        // 999 * 10 / 1000 = 9.99
+        assert_eq!(scale_to_side(1000, 999, 10, false), (10, 10));
+    }
+
+    #[test]
+    fn scale_to_side_keeps_one_pixel() {
+        assert_eq!(scale_to_side(10000, 10, 100, false), (100, 1));
+        assert_eq!(scale_to_side(1, 5000, 10, false), (1, 10));
+    }
+
+    #[test]
+    fn scale_to_megapixels_keeps_smaller_images() {
+        assert_eq!(scale_to_megapixels(1000, 1000, 4.0, false), (1000, 1000));
+        assert_eq!(scale_to_megapixels(1000, 1000, 4.0, true), (2000, 2000));
+    }
+
+    #[test]
+    fn scale_to_megapixels_reaches_exact_targets() {
+        assert_eq!(scale_to_megapixels(4000, 3000, 3.0, false), (2000, 1500));
+        assert_eq!(scale_to_megapixels(6000, 4000, 24.0, false), (6000, 4000));
+        assert_eq!(scale_to_megapixels(6000, 4000, 24.0, true), (6000, 4000));
+    }
+
+    #[test]
+    fn scale_to_megapixels_handles_extreme_aspect_ratios() {
+        assert_eq!(scale_to_megapixels(100_000, 10, 0.25, false), (50_000, 5));
+        assert_eq!(scale_to_megapixels(10, 100_000, 0.25, false), (5, 50_000));
+        // the short side would be a fraction of a pixel.
+        assert_eq!(scale_to_megapixels(1_000_000, 2, 0.0002, false), (10_000, 1));
+    }
+}
@@ -0,0 +1,267 @@
+// The rotate task.
+
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, is_high_depth, mapped_target_path, metadata, parse_save_args, process_directory, transform_image, widen_color, without_alpha, write_output};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "degrees", values: "clockwise angle, e.g. 90 or 2.3", default: "required" },
+    OptionSpec { key: "bg", values: "{rrggbb} | {rrggbbaa} | transparent", default: "transparent with alpha, white otherwise" },
+    OptionSpec { key: "crop", values: "true | false, keep the canvas size", default: "false" },
+    OptionSpec { key: "lossless", values: "true | false, turn JPEGs by their EXIF orientation instead of re-encoding", default: "false" },
+];
+
+/// The EXIF orientation that shows an image turned clockwise by `quarter_turns` from how the
+/// `orientation` shows it.
+///
+/// Every orientation is taken as a horizontal flip or not, followed by a clockwise rotation, and
+/// the turn adds to that rotation.
+fn rotated_orientation(orientation: u16, quarter_turns: u32) -> u16 {
+    // (quarter turns, flipped) of the orientations 1 to 8.
+    const TRANSFORMS: [(u32, bool); 8] = [(0, false), (0, true), (2, false), (2, true), (3, true), (1, false), (1, true), (3, false)];
+    let (turns, flipped) = TRANSFORMS[orientation as usize - 1];
+    let transform = ((turns + quarter_turns) % 4, flipped);
+    TRANSFORMS.iter().position(|candidate| *candidate == transform).unwrap() as u16 + 1
+}
+
+/// A JPEG turned clockwise by `quarter_turns` without touching its image data: only its EXIF
+/// orientation changes, relative to how viewers showed it before.
+fn rotate_losslessly(jpeg: &[u8], quarter_turns: u32) -> Vec<u8> {
+    let exif = metadata::jpeg_exif(jpeg);
+    let orientation = exif.as_deref().and_then(metadata::orientation).unwrap_or(1);
+    let exif = metadata::with_orientation(exif, rotated_orientation(orientation, quarter_turns));
+    metadata::with_exif(jpeg, exif.as_deref())
+}
+
+/// Parse a hex color (`rrggbb` or `rrggbbaa`, with or without `#`) or `transparent`.
+pub fn parse_color(value: &str) -> image::Rgba<u8> {
+    if value == "transparent" {
+        return image::Rgba([0, 0, 0, 0]);
+    }
+    let hex = value.trim_start_matches('#');
+    let channel = |index: usize| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16);
+    let color = match hex.len() {
+        6 => (channel(0), channel(1), channel(2), Ok(255)),
+        8 => (channel(0), channel(1), channel(2), channel(3)),
+        _ => panic!("Invalid color: {}", value),
+    };
+    match color {
+        (Ok(r), Ok(g), Ok(b), Ok(a)) if hex.is_ascii() => image::Rgba([r, g, b, a]),
+        _ => panic!("Invalid color: {}", value),
+    }
+}
+
+/// Rotate an image clockwise by an arbitrary angle.
+///
+/// Pixels are sampled bilinearly and the corners exposed by the rotation are filled with
+/// `background`. With `crop` the original canvas size is kept, otherwise the canvas grows to fit
+/// the whole rotated image. Right angles are rotated losslessly.
+pub fn rotate_image(image: &image::DynamicImage, degrees: f64, background: image::Rgba<u8>, crop: bool) -> image::DynamicImage {
+    let degrees = degrees.rem_euclid(360.0);
+    if degrees == 0.0 {
+        return image.clone();
+    } else if degrees == 180.0 {
+        return image.rotate180();
+    } else if !crop && degrees == 90.0 {
+        return image.rotate90();
+    } else if !crop && degrees == 270.0 {
+        return image.rotate270();
+    }
+
+    // 16-bit images are sampled at 16 bits so they keep their precision.
+    let rotated = if is_high_depth(image) {
+        image::DynamicImage::ImageRgba16(rotate_buffer(&image.to_rgba16(), degrees, widen_color(background), crop, 65535.0, |v| v as u16))
+    } else {
+        image::DynamicImage::ImageRgba8(rotate_buffer(&image.to_rgba8(), degrees, background, crop, 255.0, |v| v as u8))
+    };
+
+    // keep images without alpha opaque when the background is.
+    if !image.color().has_alpha() && background[3] == 255 {
+        without_alpha(rotated)
+    } else {
+        rotated
+    }
+}
+
+/// Rotate an RGBA buffer whose channels go up to `max` by an arbitrary angle, see `rotate_image`.
+fn rotate_buffer<S: Copy + Into<f64>>(
+    source: &image::ImageBuffer<image::Rgba<S>, Vec<S>>,
+    degrees: f64,
+    background: image::Rgba<S>,
+    crop: bool,
+    max: f64,
+    from_f64: impl Fn(f64) -> S,
+) -> image::ImageBuffer<image::Rgba<S>, Vec<S>>
+where
+    image::Rgba<S>: image::Pixel<Subpixel = S>,
+{
+    let (width, height) = (source.width() as f64, source.height() as f64);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (new_width, new_height) = if crop {
+        (source.width(), source.height())
+    } else {
+        (
+            ((width * cos.abs() + height * sin.abs()) - 1e-6).ceil().max(1.0) as u32,
+            ((width * sin.abs() + height * cos.abs()) - 1e-6).ceil().max(1.0) as u32,
+        )
+    };
+
+    // sample one source pixel, using the background outside of the image.
+    let pixel = |x: i64, y: i64| -> [f64; 4] {
+        let p = if x < 0 || y < 0 || x >= source.width() as i64 || y >= source.height() as i64 {
+            background
+        } else {
+            *source.get_pixel(x as u32, y as u32)
+        };
+        let [r, g, b, a] = p.0.map(Into::into);
+        // premultiply so transparent neighbors don't darken edges.
+        let alpha = a / max;
+        [r * alpha, g * alpha, b * alpha, a]
+    };
+
+    image::ImageBuffer::from_fn(new_width, new_height, |x, y| {
+        // map the output pixel center back into the source image.
+        let dx = x as f64 + 0.5 - new_width as f64 / 2.0;
+        let dy = y as f64 + 0.5 - new_height as f64 / 2.0;
+        let sx = dx * cos + dy * sin + width / 2.0 - 0.5;
+        let sy = -dx * sin + dy * cos + height / 2.0 - 0.5;
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let mut sum = [0.0; 4];
+        for (px, py, weight) in [
+            (x0, y0, (1.0 - fx) * (1.0 - fy)),
+            (x0 + 1, y0, fx * (1.0 - fy)),
+            (x0, y0 + 1, (1.0 - fx) * fy),
+            (x0 + 1, y0 + 1, fx * fy),
+        ] {
+            let p = pixel(px, py);
+            for channel in 0..4 {
+                sum[channel] += p[channel] * weight;
+            }
+        }
+        let alpha = sum[3] / max;
+        let unpremultiply = |c: f64| if alpha > 0.0 { from_f64((c / alpha).round().clamp(0.0, max)) } else { from_f64(0.0) };
+        image::Rgba([unpremultiply(sum[0]), unpremultiply(sum[1]), unpremultiply(sum[2]), from_f64(sum[3].round().clamp(0.0, max))])
+    })
+}
+
+/// Rotate images in a directory.
+///
+///  # Supported Options
+///
+///  ## degrees (Required)
+///  - clockwise angle, e.g. 90 or 2.3
+///
+///  ## bg
+///  - {rrggbb} or {rrggbbaa}, fill for exposed corners
+///  - transparent (default for images with alpha, white otherwise)
+///
+///  ## crop
+///  - false (default), grow the canvas to fit the rotated image
+///  - true, keep the original canvas size
+///
+///  ## lossless
+///  - false (default), decode, rotate and encode every image
+///  - true, JPEGs written as JPEGs are turned by right angles through their EXIF orientation,
+///    so they keep their exact image data and quality. The turn applies to how viewers show
+///    them. Other images and angles fall back to decoding and encoding.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct Rotate;
+
+impl Task for Rotate {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn reorients(&self) -> bool {
+        true
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let degrees_value = match options.get("degrees") {
+            Some(degrees_value) => degrees_value,
+            None => panic!("Missing required option: degrees"),
+        };
+        let degrees: f64 = match degrees_value.parse() {
+            Ok(degrees) if f64::is_finite(degrees) => degrees,
+            _ => panic!("Invalid degrees: {}", degrees_value),
+        };
+        let background = options.get("bg").map(|bg| parse_color(bg));
+        let crop = match options.get("crop").copied() {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(crop) => panic!("Invalid crop: {}", crop),
+        };
+        let lossless = match options.get("lossless").copied() {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(lossless) => panic!("Invalid lossless: {}", lossless),
+        };
+        let degrees_turned = degrees.rem_euclid(360.0);
+        let quarter_turns = (lossless && degrees_turned % 90.0 == 0.0).then_some(degrees_turned as u32 / 90);
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            let is_jpeg = |path: &std::path::Path| image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::Jpeg);
+            let mapped_path = mapped_target_path(&path, &target_path, run);
+            if let Some(quarter_turns) = quarter_turns.filter(|_| is_jpeg(&path) && is_jpeg(&mapped_path)) {
+                let jpeg = match run.read(&path) {
+                    Ok(jpeg) => jpeg,
+                    Err(outcome) => return outcome,
+                };
+                // a mislabeled image isn't a JPEG to turn.
+                if jpeg.starts_with(&[0xFF, 0xD8]) {
+                    write_output(&path, &mapped_path, rotate_losslessly(&jpeg, quarter_turns), run);
+                    return Outcome::Processed;
+                }
+            }
+            transform_image(&path, &target_path, run, &save, |image| {
+                let background = background.unwrap_or(if image.color().has_alpha() {
+                    image::Rgba([0, 0, 0, 0])
+                } else {
+                    image::Rgba([255, 255, 255, 255])
+                });
+                rotate_image(image, degrees, background, crop)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How a viewer shows `image` with an EXIF orientation, composed of clockwise quarter turns
+    /// and horizontal flips.
+    fn shown(image: &image::GrayImage, orientation: u16) -> image::GrayImage {
+        let turned = |turns: u32| (0..turns).fold(image.clone(), |image, _| image::imageops::rotate90(&image));
+        match orientation {
+            1 => image.clone(),
+            2 => image::imageops::flip_horizontal(image),
+            3 => turned(2),
+            4 => image::imageops::flip_horizontal(&turned(2)),
+            5 => image::imageops::flip_horizontal(&turned(1)),
+            6 => turned(1),
+            7 => image::imageops::flip_horizontal(&turned(3)),
+            8 => turned(3),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn rotated_orientation_adds_the_turns() {
+        // every pixel differs, so any flip or turn shows.
+        let image = image::GrayImage::from_fn(3, 2, |x, y| image::Luma([(y * 3 + x) as u8]));
+        // orientation 5 is the transpose and 7 the transverse, as EXIF defines them.
+        assert_eq!(shown(&image, 5).get_pixel(1, 2), image.get_pixel(2, 1));
+        assert_eq!(shown(&image, 7).get_pixel(1, 2), image.get_pixel(0, 0));
+        for orientation in 1..=8 {
+            for quarter_turns in 0..4 {
+                let expected = (0..quarter_turns).fold(shown(&image, orientation), |image, _| image::imageops::rotate90(&image));
+                assert_eq!(shown(&image, rotated_orientation(orientation, quarter_turns)), expected, "orientation {} turned {} times", orientation, quarter_turns);
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+// The smart-thumb task.
+
+use image::GenericImageView;
+
+use super::{OptionSpec, Task};
+use crate::{Resample, RunArgs, Summary, parse_dimensions, parse_resample, parse_save_args, process_directory, resize_by_size, transform_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "size", values: "{width}x{height}", default: "256x256" },
+    OptionSpec { key: "anchor", values: "center | top | bottom | left | right | top-left | top-right | bottom-left | bottom-right | skin | face | \"{x},{y}\" (0.0-1.0)", default: "center" },
+    OptionSpec { key: "filter", values: "nearest (point, box) | triangle (linear) | catmull-rom (cubic) | gaussian | lanczos3, or {horizontal}:{vertical}", default: "catmull-rom" },
+    OptionSpec { key: "filter_down", values: "a filter, for sides that shrink", default: "filter" },
+    OptionSpec { key: "filter_up", values: "a filter, for sides that grow", default: "filter" },
+    OptionSpec { key: "downscale", values: "direct | staged, halve big reductions first", default: "direct" },
+];
+
+/// Parse where a crop is taken from (or content is placed), as fractions of the cropped-away (or
+/// added) width and height.
+pub fn parse_anchor(value: &str) -> (f64, f64) {
+    match value {
+        "center" => (0.5, 0.5),
+        "top" => (0.5, 0.0),
+        "bottom" => (0.5, 1.0),
+        "left" => (0.0, 0.5),
+        "right" => (1.0, 0.5),
+        "top-left" => (0.0, 0.0),
+        "top-right" => (1.0, 0.0),
+        "bottom-left" => (0.0, 1.0),
+        "bottom-right" => (1.0, 1.0),
+        _ => panic!("Invalid anchor: {}", value),
+    }
+}
+
+/// Where a thumbnail is cropped from.
+#[derive(Copy, Clone)]
+enum Anchor {
+    /// Fractions of the cropped-away width and height, see `parse_anchor`.
+    Fixed(f64, f64),
+    /// Around a point given as fractions of the width and height, see `parse_point`.
+    Point(f64, f64),
+    /// Around the top of the largest region of skin tones, see `skin_center`, or the center
+    /// without one.
+    Skin,
+    /// Around the clearest face, see `face_center`, or the center without one.
+    #[cfg(feature = "face-detection")]
+    Face,
+}
+
+/// Parse a point of interest, `x,y` or `x y` as fractions of the width and height from the top
+/// left, `None` when the value doesn't look like one.
+fn parse_point(value: &str) -> Option<(f64, f64)> {
+    let fractions: Vec<&str> = value.split(|c: char| c == ',' || c.is_whitespace()).filter(|part| !part.is_empty()).collect();
+    let [x, y] = fractions[..] else {
+        return None;
+    };
+    let fraction = |part: &str| match part.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => fraction,
+        _ => panic!("Invalid anchor (expected fractions of 0.0-1.0): {}", value),
+    };
+    Some((fraction(x), fraction(y)))
+}
+
+/// Whether a color is within the skin tones of the Chai and Ngan YCbCr ranges, leaving out
+/// shadows that are too dark to tell.
+fn is_skin(pixel: &image::Rgb<u8>) -> bool {
+    let [r, g, b] = pixel.0.map(|channel| channel as f64);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    luma > 40.0 && (77.0..=127.0).contains(&cb) && (133.0..=173.0).contains(&cr)
+}
+
+/// The area of a region of an image and its bounding box (left, top, right, bottom) in pixels.
+type Region = (usize, usize, usize, usize, usize);
+
+/// Which pixels of an image have skin tones, row by row, and the regions they form, the regions
+/// covering at least 1% of the image only.
+fn skin_regions(image: &image::RgbImage) -> (Vec<bool>, Vec<Region>) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let skin: Vec<bool> = image.pixels().map(is_skin).collect();
+    let mut visited = vec![false; skin.len()];
+    let mut regions = Vec::new();
+    for start in 0..skin.len() {
+        if !skin[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut region = (0, width, height, 0, 0);
+        let mut stack = vec![start];
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            region = (region.0 + 1, region.1.min(x), region.2.min(y), region.3.max(x), region.4.max(y));
+            let neighbors = [(x > 0).then(|| index - 1), (x + 1 < width).then(|| index + 1), (y > 0).then(|| index - width), (y + 1 < height).then(|| index + width)];
+            for neighbor in neighbors.into_iter().flatten() {
+                if skin[neighbor] && !visited[neighbor] {
+                    visited[neighbor] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+        if region.0 * 100 >= skin.len() {
+            regions.push(region);
+        }
+    }
+    (skin, regions)
+}
+
+/// Center of the top of the largest region of skin tones in an image, as fractions of its width
+/// and height, if it has one covering at least 1% of the image.
+///
+/// This looks at colors only and detects no faces: in a portrait the region is usually a head,
+/// possibly with neck and shoulders below it, so the square at its top is taken, but any
+/// skin-colored surface, such as wood or sand, counts the same. See `face_center` for faces.
+fn skin_center(image: &image::DynamicImage) -> Option<(f64, f64)> {
+    let small = image.thumbnail(128, 128).to_rgb8();
+    let (width, height) = (small.width() as f64, small.height() as f64);
+    let (_, regions) = skin_regions(&small);
+    let &(_, left, top, right, bottom) = regions.iter().max_by_key(|region| region.0)?;
+    let top_size = (right - left + 1).min(bottom - top + 1) as f64;
+    Some(((left + right + 1) as f64 / 2.0 / width, (top as f64 + top_size / 2.0) / height))
+}
+
+/// Center of the clearest face in an image, as fractions of its width and height, if one is found
+/// among its regions of skin tones, see `crate::face`.
+#[cfg(feature = "face-detection")]
+fn face_center(image: &image::DynamicImage) -> Option<(f64, f64)> {
+    let small = image.thumbnail(256, 256).to_rgb8();
+    let (skin, regions) = skin_regions(&small);
+    let bounds: Vec<(usize, usize, usize, usize)> = regions.iter().map(|&(_, left, top, right, bottom)| (left, top, right, bottom)).collect();
+    crate::face::face_center(&small, &skin, &bounds)
+}
+
+/// The anchor that centers a crop of `crop` pixels out of `length` on `center` (a fraction of
+/// `length`), as far as the image allows.
+fn centered_anchor(center: f64, crop: u32, length: u32) -> f64 {
+    if crop >= length {
+        return 0.5;
+    }
+    ((center * length as f64 - crop as f64 / 2.0) / (length - crop) as f64).clamp(0.0, 1.0)
+}
+
+/// Crop an image to the aspect ratio of `size` around the anchor, then resize it to exactly `size`.
+fn cover_crop(image: &image::DynamicImage, size: (u32, u32), anchor: Anchor, resample: Resample, run: &RunArgs) -> image::DynamicImage {
+    let (width, height) = image.dimensions();
+    let aspect = size.0 as f64 / size.1 as f64;
+    // the largest region with the target aspect ratio that fits the image.
+    let crop_width = ((height as f64 * aspect).round() as u32).clamp(1, width);
+    let crop_height = ((width as f64 / aspect).round() as u32).clamp(1, height);
+    let centered = |(x, y): (f64, f64)| (centered_anchor(x, crop_width, width), centered_anchor(y, crop_height, height));
+    let anchor = match anchor {
+        Anchor::Fixed(x, y) => (x, y),
+        Anchor::Point(x, y) => centered((x, y)),
+        Anchor::Skin => {
+            let skin = skin_center(image);
+            run.report_field("skin", skin.is_some());
+            skin.map_or((0.5, 0.5), centered)
+        }
+        #[cfg(feature = "face-detection")]
+        Anchor::Face => {
+            let face = face_center(image);
+            run.report_field("face", face.is_some());
+            face.map_or((0.5, 0.5), centered)
+        }
+    };
+    let x = ((width - crop_width) as f64 * anchor.0).round() as u32;
+    let y = ((height - crop_height) as f64 * anchor.1).round() as u32;
+    resize_by_size(&image.crop_imm(x, y, crop_width, crop_height), size, resample)
+}
+
+/// Crop images in a directory to the aspect ratio of a thumbnail size and resize them to it, in
+/// one pass.
+///
+///  # Supported Options
+///
+///  ## size
+///  - {width}x{height} (default 256x256)
+///
+///  ## anchor
+///  - center (default)
+///  - top, bottom, left, right (top keeps faces in portraits)
+///  - top-left, top-right, bottom-left, bottom-right
+///  - skin, around the top of the largest region of skin tones, or the center when there is
+///    none. It often keeps the face of portraits, but only looks at colors: skin-colored
+///    backgrounds mislead it.
+///  - face, around the clearest face, or the center when none is found (needs the
+///    face-detection feature). Faces are regions of skin tones with two eyes and a mouth where a
+///    face has them, frontal faces covering at least 1% of the image are found.
+///  - "{x},{y}", around a point as fractions of the width and height from the top left, e.g.
+///    "0.5,0.33" for the upper third of portraits. The crop stays within the image, so points
+///    near an edge crop from that edge.
+///
+///  Resampling options (filter, filter_down, filter_up, downscale) are accepted as well, see `parse_resample`.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct SmartThumb;
+
+impl Task for SmartThumb {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let size_value = options.get("size").copied().unwrap_or("256x256");
+        let size = parse_dimensions(size_value).unwrap_or_else(|| panic!("Invalid size: {}", size_value));
+        let anchor = match options.get("anchor").copied().unwrap_or("center") {
+            "skin" => Anchor::Skin,
+            #[cfg(feature = "face-detection")]
+            "face" => Anchor::Face,
+            #[cfg(not(feature = "face-detection"))]
+            "face" => panic!("anchor=face needs rsimg built with the face-detection feature, anchor=skin works without it"),
+            anchor => match parse_point(anchor) {
+                Some((x, y)) => Anchor::Point(x, y),
+                None => {
+                    let (x, y) = parse_anchor(anchor);
+                    Anchor::Fixed(x, y)
+                }
+            },
+        };
+        let resample = parse_resample(&options);
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            transform_image(&path, &target_path, run, &save, |image| cover_crop(image, size, anchor, resample, run))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A portrait on a blue background: a skin-colored oval with dark eyes and a red mouth, its
+    /// center at (70, 97), next to a larger skin-colored board.
+    fn portrait() -> image::DynamicImage {
+        let inside = |x: u32, y: u32, center: (f64, f64), radii: (f64, f64)| ((x as f64 - center.0) / radii.0).powi(2) + ((y as f64 - center.1) / radii.1).powi(2) <= 1.0;
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 256, |x, y| {
+            if inside(x, y, (56.0, 78.0), (5.0, 4.0)) || inside(x, y, (84.0, 78.0), (5.0, 4.0)) {
+                image::Rgb([50, 45, 70])
+            } else if inside(x, y, (70.0, 115.0), (12.0, 4.0)) {
+                image::Rgb([190, 50, 60])
+            } else if inside(x, y, (70.0, 90.0), (35.0, 45.0)) || (150..240).contains(&x) && (140..250).contains(&y) {
+                image::Rgb([220, 170, 140])
+            } else {
+                image::Rgb([60, 90, 170])
+            }
+        }))
+    }
+
+    #[test]
+    fn skin_center_takes_the_largest_region() {
+        let (x, y) = skin_center(&portrait()).unwrap();
+        assert!((x - 195.0 / 256.0).abs() < 0.02 && (y - 185.0 / 256.0).abs() < 0.02, "{x} {y}");
+        assert_eq!(skin_center(&image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 64, image::Rgb([60, 90, 170])))), None);
+    }
+
+    #[cfg(feature = "face-detection")]
+    #[test]
+    fn face_center_finds_the_face() {
+        let (x, y) = face_center(&portrait()).unwrap();
+        assert!((x - 70.0 / 256.0).abs() < 0.03 && (y - 97.0 / 256.0).abs() < 0.03, "{x} {y}");
+        // skin without eyes and mouth.
+        let board = image::RgbImage::from_fn(256, 256, |x, y| if (60..200).contains(&x) && (40..220).contains(&y) { image::Rgb([220, 170, 140]) } else { image::Rgb([60, 90, 170]) });
+        assert_eq!(face_center(&image::DynamicImage::ImageRgb8(board)), None);
+    }
+}
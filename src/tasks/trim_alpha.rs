@@ -0,0 +1,98 @@
+// The trim-alpha task.
+
+use super::canvas::resize_canvas;
+use super::{OptionSpec, Task};
+use crate::{Outcome, RunArgs, Summary, mapped_target_path, open_image, parse_save_args, process_directory, save_image};
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { key: "threshold", values: "0-254, largest alpha that still counts as transparent", default: "0" },
+    OptionSpec { key: "margin", values: "{pixels}, transparent border kept around the content", default: "0" },
+];
+
+/// Bounding box (left, top, right, bottom, exclusive) of the pixels that are content, e.g. those
+/// whose alpha is above a threshold, `None` when there are none.
+pub fn content_bounds(image: &image::DynamicImage, is_content: impl Fn(&image::Rgba<u8>) -> bool) -> Option<(u32, u32, u32, u32)> {
+    let pixels = image.to_rgba8();
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in pixels.enumerate_pixels() {
+        if !is_content(pixel) {
+            continue;
+        }
+        bounds = Some(match bounds {
+            None => (x, y, x + 1, y + 1),
+            Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1)),
+        });
+    }
+    bounds
+}
+
+/// Crop an image to its content and add a transparent `margin` on every side.
+fn trim_alpha(image: &image::DynamicImage, (left, top, right, bottom): (u32, u32, u32, u32), margin: u32) -> image::DynamicImage {
+    let content = image.crop_imm(left, top, right - left, bottom - top);
+    if margin == 0 {
+        return content;
+    }
+    let size = (content.width() + margin * 2, content.height() + margin * 2);
+    resize_canvas(&content, size, (0.5, 0.5), image::Rgba([0, 0, 0, 0]))
+}
+
+/// Crop the fully transparent margins of images in a directory, tightening sprite bounds so game
+/// atlases waste less texture space.
+///
+///  # Supported Options
+///
+///  ## threshold
+///  - 0 (default), only fully transparent pixels are trimmed
+///  - 1-254, pixels with this alpha or less are trimmed as well, for faint antialiasing halos
+///
+///  ## margin
+///  - 0 (default)
+///  - {pixels}, a uniform transparent border added back around the content
+///
+///  Images without alpha and fully transparent images are written unchanged. Animations only
+///  keep their first frame. Every image prints and reports its size before and after trimming.
+///
+///  The encoder options of `SAVE_OPTIONS` are accepted as well, `--explain` lists them.
+pub struct TrimAlpha;
+
+impl Task for TrimAlpha {
+    fn options(&self) -> Vec<&'static OptionSpec> {
+        super::with_save_options(OPTIONS)
+    }
+
+    fn run(&self, source_path: std::path::PathBuf, run: &RunArgs, options: std::collections::HashMap<&str, &str>) -> Summary {
+        let threshold = match options.get("threshold") {
+            None => 0,
+            Some(threshold_value) => match threshold_value.parse::<u8>() {
+                Ok(threshold) if threshold < 255 => threshold,
+                _ => panic!("Invalid threshold (expected 0-254): {}", threshold_value),
+            },
+        };
+        let margin = match options.get("margin") {
+            None => 0,
+            Some(margin_value) => margin_value.parse::<u32>().unwrap_or_else(|_| panic!("Invalid margin: {}", margin_value)),
+        };
+        let save = parse_save_args(&options);
+
+        process_directory(source_path, run, |path, target_path| {
+            let target_path = mapped_target_path(&path, &target_path, run);
+            let image = match open_image(&path, run) {
+                Ok(image) => image,
+                Err(outcome) => return outcome,
+            };
+            let bounds = if image.color().has_alpha() { content_bounds(&image, |pixel| pixel[3] > threshold) } else { None };
+            let trimmed = match bounds {
+                Some(bounds) => trim_alpha(&image, bounds, margin),
+                None => image.clone(),
+            };
+            let (before, after) = (image.width() as u64 * image.height() as u64, trimmed.width() as u64 * trimmed.height() as u64);
+            let reduction = if before == 0 { 0.0 } else { 100.0 * (before as f64 - after as f64) / before as f64 };
+            println!("{} {}x{} → {}x{} ({:.0}% fewer pixels)", path.display(), image.width(), image.height(), trimmed.width(), trimmed.height(), reduction);
+            run.report_field("original_size", format!("{}x{}", image.width(), image.height()));
+            run.report_field("trimmed_size", format!("{}x{}", trimmed.width(), trimmed.height()));
+            run.report_field("pixel_reduction", reduction);
+            save_image(&trimmed, &path, &target_path, run, &save);
+            Outcome::Processed
+        })
+    }
+}
@@ -0,0 +1,59 @@
+// Runs of the rsimg binary on images in a scratch directory.
+
+/// An empty scratch directory for one test.
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("rsimg-test-{}-{}", std::process::id(), name));
+    if path.exists() {
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Run rsimg on a source directory, panicking when it fails.
+fn rsimg(source_path: &std::path::Path, args: &[&str]) {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rsimg")).arg("--source").arg(source_path).args(args).output().unwrap();
+    assert!(output.status.success(), "rsimg failed: {}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn name_template_in_place_keeps_source() {
+    let dir = scratch_dir("name-template");
+    image::RgbImage::from_pixel(64, 32, image::Rgb([200, 100, 50])).save(dir.join("a.png")).unwrap();
+
+    rsimg(&dir, &["--task", "resize", "--options", "size=16x8", "--name-template", "{stem}_small.png"]);
+
+    assert_eq!(image::image_dimensions(dir.join("a.png")).unwrap(), (64, 32));
+    assert_eq!(image::image_dimensions(dir.join("a_small.png")).unwrap(), (16, 8));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn favicon_targets_dont_collide() {
+    let dir = scratch_dir("favicon-collision");
+    image::RgbImage::from_pixel(64, 64, image::Rgb([200, 100, 50])).save(dir.join("a.png")).unwrap();
+    image::RgbImage::from_pixel(32, 32, image::Rgb([50, 100, 200])).save(dir.join("a.bmp")).unwrap();
+
+    rsimg(&dir, &["--task", "favicon"]);
+
+    assert!(dir.join("a.ico").exists());
+    assert!(dir.join("a_2.ico").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn preflight_plans_the_manifest_out() {
+    let dir = scratch_dir("preflight-manifest-out");
+    let source = dir.join("source");
+    std::fs::create_dir_all(source.join("sub")).unwrap();
+    image::RgbImage::from_pixel(64, 32, image::Rgb([200, 100, 50])).save(source.join("sub").join("a.png")).unwrap();
+    let (dest, map) = (dir.join("dest"), dir.join("map.json"));
+
+    rsimg(&source, &["--task", "resize", "--options", "size=16x8", "--dest", dest.to_str().unwrap(), "--preflight", "--manifest-out", map.to_str().unwrap()]);
+
+    let map: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&map).unwrap()).unwrap();
+    assert_eq!(map["planned"], true);
+    assert_eq!(map["files"]["sub/a.png"][0]["path"], "sub/a.png");
+    assert!(!dest.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}